@@ -0,0 +1,278 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use crate::config::Config;
+use crate::output::outln;
+use crate::status::same_file;
+use crate::sync::copy_recursively;
+
+static CURRENT: OnceLock<Backup> = OnceLock::new();
+
+/// Held while reading and rewriting `index.json`, which repositories syncing side by
+/// side update at the same time.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+/// One run's worth of backups under `~/.gitbox/backups/<timestamp>/`, where each
+/// saved item keeps its absolute path, e.g. `.../20250101T120000.000/home/me/.bashrc`.
+/// Every saved item is also recorded in `~/.gitbox/backups/index.json`.
+pub struct Backup {
+    /// `~/.gitbox/backups/`, holding every run and the index
+    root: PathBuf,
+    dir: PathBuf,
+}
+
+/// A saved item as recorded in the index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupEntry {
+    pub id: u64,
+    pub created_at: DateTime<Utc>,
+    /// Where the item was
+    pub original: PathBuf,
+    /// The saved copy
+    pub copy: PathBuf,
+    /// What was about to overwrite or delete it
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BackupIndex {
+    entries: Vec<BackupEntry>,
+}
+
+impl Backup {
+    /// Nothing is written until the first item is saved.
+    pub fn new() -> Self {
+        Self::in_root(backups_dir())
+    }
+
+    fn in_root(root: PathBuf) -> Self {
+        let stamp = Utc::now().format("%Y%m%dT%H%M%S%.3f").to_string();
+        Self { dir: root.join(stamp), root }
+    }
+
+    /// The run shared by everything this process overwrites or deletes.
+    pub fn current() -> &'static Backup {
+        CURRENT.get_or_init(Backup::new)
+    }
+
+    /// Where `path` is (or would be) saved.
+    pub fn destination(&self, path: &Path) -> PathBuf {
+        let relative: PathBuf = path.components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        self.dir.join(relative)
+    }
+
+    /// Copies the file or directory at `path` into the backup, records it in the
+    /// index and returns the copy's location.
+    pub fn save(&self, path: &Path, reason: &str) -> Result<PathBuf> {
+        let _lock = lock_index();
+        let mut index = BackupIndex::load(&self.root)?;
+        let id = index.entries.iter().map(|entry| entry.id).max().unwrap_or(0) + 1;
+
+        // An item saved twice in one run keeps both copies
+        let mut destination = self.destination(path);
+        if fs::symlink_metadata(&destination).is_ok() {
+            let mut name = destination.file_name().unwrap_or_default().to_os_string();
+            name.push(format!(".{}", id));
+            destination.set_file_name(name);
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create backup directory: {:?}", parent))?;
+        }
+        copy_recursively(path, &destination)
+            .with_context(|| format!("Failed to back up {:?}", path))?;
+
+        index.entries.push(BackupEntry {
+            id,
+            created_at: Utc::now(),
+            original: path.to_path_buf(),
+            copy: destination.clone(),
+            reason: reason.to_string(),
+        });
+        index.save(&self.root)?;
+        Ok(destination)
+    }
+}
+
+/// Saves whatever is at `path` before `replacement` takes its place. Nothing is saved
+/// if `path` does not exist, is the replacement itself, or is a file with the same content.
+pub fn save_before_replacing(path: &Path, replacement: &Path, reason: &str) -> Result<()> {
+    // A dangling symlink has nothing worth keeping either
+    if !path.exists() || same_file(path, replacement) {
+        return Ok(());
+    }
+    let identical = path.is_file()
+        && matches!((fs::read(path), fs::read(replacement)), (Ok(a), Ok(b)) if a == b);
+    if identical {
+        return Ok(());
+    }
+    let saved = Backup::current().save(path, reason)?;
+    outln!("Backed up {:?} to {:?}", path, saved);
+    Ok(())
+}
+
+/// Every recorded backup, oldest first.
+pub fn list() -> Result<Vec<BackupEntry>> {
+    Ok(BackupIndex::load(&backups_dir())?.entries)
+}
+
+/// Copies backup `id` back to where it came from. Whatever is there now is backed
+/// up first; a file is rewritten in place so hard links to it stay intact.
+pub fn restore(id: u64, dry_run: bool) -> Result<()> {
+    let entry = BackupIndex::load(&backups_dir())?.entries.into_iter()
+        .find(|entry| entry.id == id)
+        .with_context(|| format!("No backup with id {}. Run 'gitbox backups list' to see them.", id))?;
+    if !entry.copy.exists() {
+        return Err(anyhow::anyhow!("The copy of backup {} is missing: {:?}", id, entry.copy));
+    }
+    if dry_run {
+        outln!("Would restore {:?} from {:?}", entry.original, entry.copy);
+        return Ok(());
+    }
+
+    save_before_replacing(&entry.original, &entry.copy, &format!("restoring backup {}", id))?;
+    if entry.copy.is_dir() != entry.original.is_dir() {
+        match fs::symlink_metadata(&entry.original) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&entry.original)
+                .with_context(|| format!("Failed to remove directory: {:?}", entry.original))?,
+            Ok(_) => fs::remove_file(&entry.original)
+                .with_context(|| format!("Failed to remove file: {:?}", entry.original))?,
+            Err(_) => {}
+        }
+    }
+    if let Some(parent) = entry.original.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    copy_recursively(&entry.copy, &entry.original)?;
+    outln!("Restored {:?} from backup {}", entry.original, id);
+    Ok(())
+}
+
+/// Deletes backups older than `older_than` and returns how many were removed.
+pub fn prune(older_than: Duration, dry_run: bool) -> Result<usize> {
+    let cutoff = Utc::now() - older_than;
+    let _lock = lock_index();
+    let mut index = BackupIndex::load(&backups_dir())?;
+    let (expired, kept): (Vec<_>, Vec<_>) = index.entries.into_iter()
+        .partition(|entry| entry.created_at < cutoff);
+
+    for entry in &expired {
+        if dry_run {
+            outln!("Would delete backup {} of {:?}", entry.id, entry.original);
+            continue;
+        }
+        match fs::symlink_metadata(&entry.copy) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&entry.copy)
+                .with_context(|| format!("Failed to remove backup: {:?}", entry.copy))?,
+            Ok(_) => fs::remove_file(&entry.copy)
+                .with_context(|| format!("Failed to remove backup: {:?}", entry.copy))?,
+            Err(_) => {}
+        }
+        remove_empty_parents(&entry.copy);
+    }
+
+    if !dry_run {
+        index.entries = kept;
+        index.save(&backups_dir())?;
+    }
+    Ok(expired.len())
+}
+
+fn backups_dir() -> PathBuf {
+    Config::gitbox_dir().join("backups")
+}
+
+fn lock_index() -> std::sync::MutexGuard<'static, ()> {
+    // The lock guards no data, so a panic while holding it leaves nothing inconsistent
+    INDEX_LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Removes directories left empty below `~/.gitbox/backups/`.
+fn remove_empty_parents(path: &Path) {
+    let root = backups_dir();
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if dir == root || !dir.starts_with(&root) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
+
+impl BackupIndex {
+    fn path(root: &Path) -> PathBuf {
+        root.join("index.json")
+    }
+
+    fn load(root: &Path) -> Result<Self> {
+        let path = Self::path(root);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read backup index: {:?}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse backup index: {:?}", path))
+    }
+
+    fn save(&self, root: &Path) -> Result<()> {
+        let path = Self::path(root);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create backup directory: {:?}", parent))?;
+        }
+        let content = serde_json::to_string_pretty(self)
+            .context("Failed to serialize backup index")?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write backup index: {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::thread;
+
+    #[test]
+    fn backups_from_two_repositories_at_once_all_reach_the_index() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("backups");
+        let backup = Backup::in_root(root.clone());
+        let home = temp.path().join("home");
+        for repo in ["notes", "dots"] {
+            fs::create_dir_all(home.join(repo)).unwrap();
+            for n in 0..10 {
+                fs::write(home.join(repo).join(format!("file{}", n)), format!("{} {}", repo, n)).unwrap();
+            }
+        }
+
+        thread::scope(|scope| {
+            for repo in ["notes", "dots"] {
+                let (backup, home) = (&backup, &home);
+                scope.spawn(move || {
+                    // Each file twice, so the second copy needs a name of its own
+                    for n in 0..20 {
+                        backup.save(&home.join(repo).join(format!("file{}", n % 10)), repo).unwrap();
+                    }
+                });
+            }
+        });
+
+        let entries = BackupIndex::load(&root).unwrap().entries;
+        let ids: BTreeSet<u64> = entries.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, (1..=40).collect());
+        let copies: BTreeSet<&PathBuf> = entries.iter().map(|entry| &entry.copy).collect();
+        assert_eq!(copies.len(), 40);
+        for entry in &entries {
+            assert_eq!(fs::read(&entry.copy).unwrap(), fs::read(&entry.original).unwrap());
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use git2::{Commit, Delta, Oid, Repository, Signature, Tree};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::config::{CommitConfig, SignFormat};
+use crate::template;
+
+static SETTINGS: OnceLock<CommitConfig> = OnceLock::new();
+
+const METADATA_FILE: &str = ".gitbox";
+
+/// Most file names listed in a generated commit subject; the body lists them all.
+const MAX_NAMES_IN_SUBJECT: usize = 3;
+
+/// Makes the `[commit]` settings available to every commit gitbox makes.
+pub fn install(settings: &CommitConfig) {
+    let _ = SETTINGS.set(settings.clone());
+}
+
+fn settings() -> &'static CommitConfig {
+    SETTINGS.get_or_init(CommitConfig::default)
+}
+
+/// Who gitbox commits as: `[commit]` in config.toml, then `user.name` and `user.email`
+/// from git config, then `gitbox <gitbox@local>`.
+pub fn signature(git_repo: &Repository) -> Result<Signature<'static>> {
+    let git_config = git_repo.config().ok();
+    let from_git = |key: &str| git_config.as_ref().and_then(|config| config.get_string(key).ok());
+    let settings = settings();
+    let name = settings.name.clone().or_else(|| from_git("user.name")).unwrap_or_else(|| "gitbox".to_string());
+    let email = settings.email.clone().or_else(|| from_git("user.email")).unwrap_or_else(|| "gitbox@local".to_string());
+    Signature::now(&name, &email).context("Failed to create git signature")
+}
+
+/// Commits `tree` on top of `parents` and moves HEAD (or the branch it points to) to
+/// it, signing the commit if `[commit] sign` is set.
+pub fn create(git_repo: &Repository, message: &str, tree: &Tree, parents: &[&Commit]) -> Result<Oid> {
+    let signature = signature(git_repo)?;
+    let Some(format) = settings().sign else {
+        return git_repo.commit(Some("HEAD"), &signature, &signature, message, tree, parents)
+            .context("Failed to create commit");
+    };
+
+    let buffer = git_repo.commit_create_buffer(&signature, &signature, message, tree, parents)
+        .context("Failed to create commit")?;
+    let content = buffer.as_str().context("Commit is not valid UTF-8")?;
+    let signed = sign(git_repo, format, content)?;
+    let id = git_repo.commit_signed(content, &signed, None)
+        .context("Failed to create signed commit")?;
+
+    let reflog = format!("commit: {}", message.lines().next().unwrap_or_default());
+    let head = git_repo.find_reference("HEAD").context("Failed to read HEAD")?;
+    match head.symbolic_target() {
+        Some(branch) => {
+            git_repo.reference(branch, id, true, &reflog)
+                .with_context(|| format!("Failed to update {}", branch))?;
+        }
+        None => git_repo.set_head_detached(id).context("Failed to update HEAD")?,
+    }
+    Ok(id)
+}
+
+/// Signs a commit's content the way git does for `gpg.format` gpg or ssh.
+fn sign(git_repo: &Repository, format: SignFormat, content: &str) -> Result<String> {
+    let key = settings().signing_key.clone()
+        .or_else(|| git_repo.config().ok().and_then(|config| config.get_string("user.signingkey").ok()));
+    let mut command = match format {
+        SignFormat::Gpg => {
+            let mut command = Command::new("gpg");
+            command.args(["--detach-sign", "--armor"]);
+            if let Some(key) = &key {
+                command.args(["--local-user", key]);
+            }
+            command
+        }
+        SignFormat::Ssh => {
+            let key = key.context("Signing with ssh needs a key file: set signing_key under [commit] in config.toml")?;
+            let key = match (key.strip_prefix("~/"), dirs::home_dir()) {
+                (Some(rest), Some(home)) => home.join(rest),
+                _ => Path::new(&key).to_path_buf(),
+            };
+            let mut command = Command::new("ssh-keygen");
+            command.args(["-Y", "sign", "-n", "git", "-f"]).arg(key);
+            command
+        }
+    };
+
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {} to sign the commit", program))?;
+    child.stdin.take().context("Failed to open signer input")?
+        .write_all(content.as_bytes())
+        .with_context(|| format!("Failed to pass the commit to {}", program))?;
+    let output = child.wait_with_output()
+        .with_context(|| format!("Failed to run {} to sign the commit", program))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "{} could not sign the commit: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).context("Commit signature is not valid UTF-8")
+}
+
+/// Builds the message for a commit from `old` to `new`: `subject` (or, without one, a
+/// summary naming the changed files), then every added, modified and removed file and
+/// this machine's hostname.
+pub fn message(git_repo: &Repository, old: Option<&Tree>, new: &Tree, subject: Option<&str>) -> Result<String> {
+    let diff = git_repo.diff_tree_to_tree(old, Some(new), None)
+        .context("Failed to compare staged changes")?;
+    let (mut added, mut modified, mut removed) = (Vec::new(), Vec::new(), Vec::new());
+    for delta in diff.deltas() {
+        let file = if delta.status() == Delta::Deleted { delta.old_file() } else { delta.new_file() };
+        let Some(path) = file.path() else {
+            continue;
+        };
+        // Synced items are named by their path below files/, like everywhere else
+        let path = path.strip_prefix("files").unwrap_or(path).to_path_buf();
+        match delta.status() {
+            Delta::Added => added.push(path),
+            Delta::Deleted => removed.push(path),
+            _ => modified.push(path),
+        }
+    }
+
+    let host = &template::machine().host;
+    let groups = [("Add", "Added", &added), ("Update", "Modified", &modified), ("Remove", "Removed", &removed)];
+    let subject = match subject {
+        Some(subject) => subject.to_string(),
+        None => {
+            // The metadata changes along with most synced files; it is only named alone
+            let metadata = Path::new(METADATA_FILE);
+            let only_metadata = groups.iter().all(|(_, _, paths)| paths.iter().all(|path| path == metadata));
+            let parts: Vec<String> = groups.iter()
+                .map(|(verb, _, paths)| {
+                    let named: Vec<&PathBuf> = paths.iter().filter(|path| only_metadata || *path != metadata).collect();
+                    (verb, named)
+                })
+                .filter(|(_, named)| !named.is_empty())
+                .enumerate()
+                .map(|(index, (verb, named))| {
+                    let verb = if index == 0 { verb.to_string() } else { verb.to_lowercase() };
+                    format!("{} {}", verb, file_names(&named))
+                })
+                .collect();
+            format!("{} on {}", parts.join("; "), host)
+        }
+    };
+
+    let mut message = format!("{}\n\n", subject);
+    for (_, label, paths) in groups {
+        for path in paths {
+            message.push_str(&format!("{} {}\n", label, path.display()));
+        }
+    }
+    message.push_str(&format!("\nHost: {}\n", host));
+    Ok(message)
+}
+
+fn file_names(paths: &[&PathBuf]) -> String {
+    let names: Vec<String> = paths.iter()
+        .take(MAX_NAMES_IN_SUBJECT)
+        .map(|path| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned())
+        .collect();
+    let mut text = names.join(", ");
+    if paths.len() > MAX_NAMES_IN_SUBJECT {
+        text.push_str(&format!(" and {} more", paths.len() - MAX_NAMES_IN_SUBJECT));
+    }
+    text
+}
@@ -1,16 +1,154 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use clap::ValueEnum;
 use dirs::home_dir;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::PathBuf;
 
+use crate::git::{self, REMOTE};
+use crate::sync::list_synced_entries;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub github_token: Option<String>,
+    /// GitHub REST API base URL, for GitHub Enterprise (`https://<host>/api/v3`)
+    #[serde(default)]
+    pub github_api_url: Option<String>,
     pub default_branch: String,
     pub repos_dir: PathBuf,
+    /// Custom named roots used to store portable paths in `.gitbox` metadata,
+    /// e.g. `WORK = "/mnt/work"` stores `/mnt/work/notes.md` as `$WORK/notes.md`.
+    #[serde(default)]
+    pub roots: HashMap<String, PathBuf>,
+    /// What to do with uncommitted edits to tracked files before pulling
+    #[serde(default)]
+    pub on_dirty_pull: DirtyPullStrategy,
+    /// How many repositories `sync-all-repos` syncs at once (4 if unset)
+    #[serde(default)]
+    pub sync_jobs: Option<usize>,
+    /// Command used by `gitbox resolve --use tool`. It runs through `sh -c` with
+    /// `$BASE`, `$LOCAL`, `$REMOTE` and `$MERGED` set to file paths, e.g.
+    /// `meld "$LOCAL" "$BASE" "$REMOTE" --output "$MERGED"`.
+    #[serde(default)]
+    pub merge_tool: Option<String>,
+    /// Directory of bare repositories (a path or `file://` URL) used as remotes
+    /// instead of GitHub, e.g. a mounted share or a scratch directory in tests.
+    #[serde(default)]
+    pub local_remote: Option<PathBuf>,
+    /// Named remote providers, e.g. `[remotes.nas]` with `kind = "git"` and
+    /// `url = "ssh://nas/srv/git"`. `github` is always available.
+    #[serde(default)]
+    pub remotes: HashMap<String, RemoteConfig>,
+    /// Provider for repositories without an entry in `repo_remotes`
+    #[serde(default)]
+    pub default_remote: Option<String>,
+    /// Provider chosen for each repository, by name from `remotes`
+    #[serde(default)]
+    pub repo_remotes: HashMap<String, String>,
+    /// Keys and patterns for files stored encrypted in repositories
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    /// This machine's profile, used to render templates and pick repository profiles
+    #[serde(default)]
+    pub machine: MachineConfig,
+    /// Author and signing of the commits gitbox makes
+    #[serde(default)]
+    pub commit: CommitConfig,
+}
+
+/// The `[encryption]` table. Encrypted files are stored with age, either to the keys
+/// in `identity` or, without one, to a passphrase (from `GITBOX_PASSPHRASE` or a prompt).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// age identity file, as written by `age-keygen`
+    #[serde(default)]
+    pub identity: Option<PathBuf>,
+    /// Other machines' age public keys (`age1...`) that can decrypt as well
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Files to always encrypt, as globs relative to the sync root, e.g. `.ssh/*`
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+/// The `[machine]` table. It describes this machine to templates and is never committed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MachineConfig {
+    /// Overrides the system hostname
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Free-form labels such as `work` or `personal`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Extra template variables, e.g. `email = "me@work.example"`
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+}
+
+/// The `[commit]` table. Without a name or email, gitbox uses `user.name` and
+/// `user.email` from your git config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommitConfig {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Sign commits with gpg or ssh-keygen; unsigned when omitted
+    #[serde(default)]
+    pub sign: Option<SignFormat>,
+    /// gpg key id or ssh key file (defaults to `user.signingkey` from your git config)
+    #[serde(default)]
+    pub signing_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignFormat {
+    Gpg,
+    Ssh,
+}
+
+/// A place remote repositories are created, from the `[remotes]` table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteConfig {
+    pub kind: RemoteKind,
+    /// Server URL for GitLab and Gitea (e.g. `https://git.example.com`), REST API URL
+    /// for GitHub (defaults to `github_api_url`), or the URL repositories live under
+    /// for plain git (e.g. `ssh://nas/srv/git`)
+    #[serde(default)]
+    pub url: Option<String>,
+    /// API token; GitHub falls back to `github_token`, GitLab to `GITLAB_TOKEN`
+    /// and Gitea to `GITEA_TOKEN`
+    #[serde(default)]
+    pub token: Option<String>,
+    /// User, group or organization owning the repositories (defaults to the token's user)
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteKind {
+    Github,
+    Gitlab,
+    #[serde(alias = "forgejo")]
+    Gitea,
+    /// Bare repositories under a plain git URL or local directory
+    Git,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DirtyPullStrategy {
+    /// Commit the edits, then merge the remote changes
+    #[default]
+    Commit,
+    /// Stash the edits and re-apply them after pulling
+    Stash,
+    /// Refuse to pull and list the edited files
+    Abort,
 }
 
 impl Default for Config {
@@ -18,8 +156,20 @@ impl Default for Config {
         let gitbox_dir = Self::gitbox_dir();
         Self {
             github_token: None,
+            github_api_url: None,
             default_branch: "main".to_string(),
             repos_dir: gitbox_dir.join("repos"),
+            roots: HashMap::new(),
+            on_dirty_pull: DirtyPullStrategy::default(),
+            sync_jobs: None,
+            merge_tool: None,
+            local_remote: None,
+            remotes: HashMap::new(),
+            default_remote: None,
+            repo_remotes: HashMap::new(),
+            encryption: EncryptionConfig::default(),
+            machine: MachineConfig::default(),
+            commit: CommitConfig::default(),
         }
     }
 }
@@ -80,9 +230,12 @@ impl Config {
         Ok(())
     }
 
-    pub fn set_github_token(&mut self, token: String) -> Result<()> {
-        self.github_token = Some(token);
-        self.save()
+    /// The configured GitHub token, else `GH_TOKEN` or `GITHUB_TOKEN` from the environment.
+    pub fn github_token(&self) -> Option<String> {
+        self.github_token.clone()
+            .or_else(|| std::env::var("GH_TOKEN").ok())
+            .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+            .filter(|token| !token.is_empty())
     }
 
     pub fn get_repo_path(&self, repo_name: &str) -> PathBuf {
@@ -210,22 +363,13 @@ impl AppInfo {
                 if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
                     // Count files in this repo
                     let files_dir = path.join("files");
-                    let file_count = if files_dir.exists() {
-                        fs::read_dir(&files_dir)
-                            .map(|entries| entries.count())
-                            .unwrap_or(0)
-                    } else {
-                        0
-                    };
+                    let file_count = list_synced_entries(&files_dir)
+                        .map(|entries| entries.len())
+                        .unwrap_or(0);
 
                     // Get remote URL if available
-                    let remote_url = std::process::Command::new("git")
-                        .args(&["remote", "get-url", "origin"])
-                        .current_dir(&path)
-                        .output()
-                        .ok()
-                        .filter(|output| output.status.success())
-                        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+                    let remote_url = git2::Repository::open(&path).ok()
+                        .and_then(|repo| git::remote_url(&repo, REMOTE));
 
                     // Use existing repo info or create new one
                     let repo_info = if let Some(existing) = self.repositories.get(name) {
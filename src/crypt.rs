@@ -0,0 +1,162 @@
+use age::secrecy::SecretString;
+use age::{scrypt, x25519};
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{IsTerminal, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::config::EncryptionConfig;
+use crate::output;
+use crate::sync::glob_set;
+
+static SETTINGS: OnceLock<EncryptionConfig> = OnceLock::new();
+static KEYS: OnceLock<Keys> = OnceLock::new();
+
+/// Environment variable holding the passphrase when no identity file is configured.
+pub const PASSPHRASE_VAR: &str = "GITBOX_PASSPHRASE";
+
+/// What encrypted repository copies are encrypted to and decrypted with.
+enum Keys {
+    /// Identities from an age key file, plus extra recipients that can also decrypt
+    Age {
+        identities: Vec<x25519::Identity>,
+        recipients: Vec<x25519::Recipient>,
+    },
+    Passphrase(SecretString),
+}
+
+/// Makes the `[encryption]` settings available; keys are only loaded once needed.
+pub fn install(settings: &EncryptionConfig) {
+    let _ = SETTINGS.set(settings.clone());
+}
+
+fn settings() -> &'static EncryptionConfig {
+    SETTINGS.get_or_init(EncryptionConfig::default)
+}
+
+/// Whether `relative` (a path below a repository's `files/`) matches one of the
+/// configured `patterns`.
+pub fn matches_pattern(relative: &Path) -> Result<bool> {
+    let patterns = &settings().patterns;
+    if patterns.is_empty() {
+        return Ok(false);
+    }
+    Ok(glob_set(patterns)?.is_match(relative))
+}
+
+pub fn encrypt(plaintext: &[u8]) -> Result<Vec<u8>> {
+    let encryptor = match keys()? {
+        Keys::Age { recipients, .. } => {
+            age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+                .context("Failed to set up encryption")?
+        }
+        Keys::Passphrase(passphrase) => age::Encryptor::with_user_passphrase(passphrase.clone()),
+    };
+    let mut ciphertext = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut ciphertext).context("Failed to encrypt")?;
+    writer.write_all(plaintext).context("Failed to encrypt")?;
+    writer.finish().context("Failed to encrypt")?;
+    Ok(ciphertext)
+}
+
+pub fn decrypt(ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let decryptor = age::Decryptor::new_buffered(ciphertext).context("Not an age-encrypted file")?;
+    let reader = match keys()? {
+        Keys::Age { identities, .. } => {
+            decryptor.decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))
+        }
+        Keys::Passphrase(passphrase) => {
+            let identity = scrypt::Identity::new(passphrase.clone());
+            decryptor.decrypt(std::iter::once(&identity as &dyn age::Identity))
+        }
+    };
+    let mut plaintext = Vec::new();
+    reader.context("Failed to decrypt: wrong key or passphrase")?
+        .read_to_end(&mut plaintext)
+        .context("Failed to decrypt")?;
+    Ok(plaintext)
+}
+
+/// Reads and decrypts an encrypted repository copy.
+pub fn read_decrypted(path: &Path) -> Result<Vec<u8>> {
+    let ciphertext = fs::read(path).with_context(|| format!("Failed to read file: {:?}", path))?;
+    decrypt(&ciphertext).with_context(|| format!("Failed to decrypt {:?}", path))
+}
+
+/// Writes the encrypted content of `plain` to `encrypted`.
+pub fn encrypt_file(plain: &Path, encrypted: &Path) -> Result<()> {
+    let plaintext = fs::read(plain).with_context(|| format!("Failed to read file: {:?}", plain))?;
+    if let Some(parent) = encrypted.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::write(encrypted, encrypt(&plaintext)?)
+        .with_context(|| format!("Failed to write file: {:?}", encrypted))
+}
+
+/// Writes the decrypted content of `encrypted` to `plain`, in place if it exists.
+pub fn decrypt_file(encrypted: &Path, plain: &Path) -> Result<()> {
+    let plaintext = read_decrypted(encrypted)?;
+    if let Some(parent) = plain.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::write(plain, plaintext).with_context(|| format!("Failed to write file: {:?}", plain))
+}
+
+fn keys() -> Result<&'static Keys> {
+    if let Some(keys) = KEYS.get() {
+        return Ok(keys);
+    }
+    // Repositories syncing side by side wait for one passphrase prompt and share its answer
+    let _prompt = output::prompt_lock();
+    if let Some(keys) = KEYS.get() {
+        return Ok(keys);
+    }
+    let keys = load_keys(settings())?;
+    Ok(KEYS.get_or_init(|| keys))
+}
+
+fn load_keys(settings: &EncryptionConfig) -> Result<Keys> {
+    let Some(identity_path) = &settings.identity else {
+        return Ok(Keys::Passphrase(read_passphrase()?));
+    };
+
+    let content = fs::read_to_string(identity_path)
+        .with_context(|| format!("Failed to read age identity file: {:?}", identity_path))?;
+    let identities = content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse::<x25519::Identity>()
+            .map_err(|e| anyhow::anyhow!("Invalid age identity in {:?}: {}", identity_path, e)))
+        .collect::<Result<Vec<_>>>()?;
+    if identities.is_empty() {
+        return Err(anyhow::anyhow!("No age identities found in {:?}", identity_path));
+    }
+
+    let mut recipients: Vec<x25519::Recipient> = identities.iter().map(|identity| identity.to_public()).collect();
+    for recipient in &settings.recipients {
+        recipients.push(recipient.parse()
+            .map_err(|e| anyhow::anyhow!("Invalid age recipient '{}': {}", recipient, e))?);
+    }
+    Ok(Keys::Age { identities, recipients })
+}
+
+fn read_passphrase() -> Result<SecretString> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(SecretString::from(passphrase));
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "Encrypted files need a key: set `identity` under [encryption] in config.toml, or the passphrase in {}",
+            PASSPHRASE_VAR
+        ));
+    }
+    let passphrase = rpassword::prompt_password("Passphrase for encrypted files: ")
+        .context("Failed to read passphrase")?;
+    if passphrase.is_empty() {
+        return Err(anyhow::anyhow!("The passphrase cannot be empty"));
+    }
+    Ok(SecretString::from(passphrase))
+}
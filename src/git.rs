@@ -0,0 +1,310 @@
+use git2::build::RepoBuilder;
+use git2::{
+    BranchType, Cred, CredentialType, Direction, ErrorCode, FetchOptions, FetchPrune, Oid, Progress, PushOptions, Remote,
+    RemoteCallbacks, Repository,
+};
+use std::cell::RefCell;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+/// Name of the remote every gitbox repository syncs with.
+pub const REMOTE: &str = "origin";
+
+/// Progress is redrawn on one terminal line, so only one transfer at a time can show it.
+static SHOW_PROGRESS: AtomicBool = AtomicBool::new(true);
+
+/// Stops showing transfer progress, for when several repositories sync at once.
+pub fn hide_progress() {
+    SHOW_PROGRESS.store(false, Ordering::Relaxed);
+}
+
+fn shows_progress() -> bool {
+    SHOW_PROGRESS.load(Ordering::Relaxed) && std::io::stderr().is_terminal()
+}
+
+#[derive(Debug, Error)]
+pub enum GitError {
+    #[error("repository has no remote '{0}' configured")]
+    NoRemote(String),
+    #[error("authentication with {url} failed (tried ssh-agent, SSH keys in ~/.ssh, a token and git credential helpers): {message}")]
+    Auth { url: String, message: String },
+    #[error("push of {reference} was rejected: {reason}")]
+    Rejected { reference: String, reason: String },
+    #[error("{0}")]
+    Git(#[from] git2::Error),
+}
+
+impl GitError {
+    /// Whether a push failed because the remote has commits we do not have yet.
+    pub fn is_rejected(&self) -> bool {
+        matches!(self, GitError::Rejected { .. })
+    }
+}
+
+/// Credentials offered to remotes. ssh-agent, key files in `~/.ssh` and git credential
+/// helpers are always tried; a token is only sent to the GitHub host it belongs to.
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    /// GitHub tokens, each with the URL prefix of the host it is for
+    /// (e.g. `https://github.com/`)
+    pub github_tokens: Vec<(String, String)>,
+}
+
+/// The URL of a remote, if it is configured.
+pub fn remote_url(repo: &Repository, remote_name: &str) -> Option<String> {
+    repo.find_remote(remote_name).ok()
+        .and_then(|remote| remote.url().map(|url| url.to_string()))
+}
+
+/// Whether a git repository answers at `url`. Authentication failures are errors;
+/// anything else (unknown path, missing repository) counts as absent.
+pub fn repository_exists(url: &str, auth: &Auth) -> Result<bool, GitError> {
+    let mut remote = Remote::create_detached(url)?;
+    let credentials = RefCell::new(CredentialState::new(auth));
+    let result = remote.connect_auth(Direction::Fetch, Some(callbacks(&credentials, None)), None)
+        .map(|_| ());
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) => match credentials.borrow().explain(e, url) {
+            auth_error @ GitError::Auth { .. } => Err(auth_error),
+            _ => Ok(false),
+        },
+    }
+}
+
+/// Clones `url` into `path` with `branch` checked out and tracking the remote.
+pub fn clone(url: &str, path: &Path, branch: &str, auth: &Auth) -> Result<Repository, GitError> {
+    let credentials = RefCell::new(CredentialState::new(auth));
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks(&credentials, None));
+
+    let result = RepoBuilder::new()
+        .branch(branch)
+        .fetch_options(options)
+        .clone(url, path);
+    finish_progress();
+    result.map_err(|e| credentials.borrow().explain(e, url))
+}
+
+/// Fetches `branch` from the remote into `refs/remotes/<remote>/<branch>`.
+/// Returns the fetched commit, or `None` if the remote does not have the branch.
+pub fn fetch(repo: &Repository, remote_name: &str, branch: &str, auth: &Auth) -> Result<Option<Oid>, GitError> {
+    let mut remote = repo.find_remote(remote_name)
+        .map_err(|_| GitError::NoRemote(remote_name.to_string()))?;
+    let url = remote.url().unwrap_or_default().to_string();
+    let tracking_ref = format!("refs/remotes/{}/{}", remote_name, branch);
+
+    let credentials = RefCell::new(CredentialState::new(auth));
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks(&credentials, None))
+        .prune(FetchPrune::On);
+
+    let refspec = format!("+refs/heads/{}:{}", branch, tracking_ref);
+    remote.fetch(&[&refspec], Some(&mut options), None)
+        .map_err(|e| credentials.borrow().explain(e, &url))?;
+    finish_progress();
+
+    match repo.refname_to_id(&tracking_ref) {
+        Ok(id) => Ok(Some(id)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Pushes `branch` to the same branch on the remote and makes it the upstream.
+pub fn push(repo: &Repository, remote_name: &str, branch: &str, auth: &Auth) -> Result<(), GitError> {
+    let mut remote = repo.find_remote(remote_name)
+        .map_err(|_| GitError::NoRemote(remote_name.to_string()))?;
+    let url = remote.pushurl().or(remote.url()).unwrap_or_default().to_string();
+    let reference = format!("refs/heads/{}", branch);
+
+    let credentials = RefCell::new(CredentialState::new(auth));
+    let rejection = RefCell::new(None);
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks(&credentials, Some(&rejection)));
+
+    let refspec = format!("{}:{}", reference, reference);
+    let result = remote.push(&[&refspec], Some(&mut options));
+    finish_progress();
+    match result {
+        Err(e) if e.code() == ErrorCode::NotFastForward => {
+            return Err(GitError::Rejected { reference, reason: e.message().to_string() });
+        }
+        Err(e) => return Err(credentials.borrow().explain(e, &url)),
+        Ok(()) => {}
+    }
+    drop(options);
+    if let Some(reason) = rejection.into_inner() {
+        return Err(GitError::Rejected { reference, reason });
+    }
+
+    let mut local = repo.find_branch(branch, BranchType::Local)?;
+    local.set_upstream(Some(&format!("{}/{}", remote_name, branch)))?;
+    Ok(())
+}
+
+/// Makes sure HEAD is on `branch`, creating it at the current commit if needed.
+/// Returns true if the branch was created.
+pub fn ensure_branch(repo: &Repository, branch: &str) -> Result<bool, GitError> {
+    let reference = format!("refs/heads/{}", branch);
+    if repo.find_reference(&reference).is_ok() {
+        return Ok(false);
+    }
+
+    // Same commit, so the working tree does not change
+    if let Some(head) = repo.head().ok().and_then(|head| head.peel_to_commit().ok()) {
+        repo.branch(branch, &head, false)?;
+    }
+    repo.set_head(&reference)?;
+    Ok(true)
+}
+
+/// Which credentials have been offered so far; libgit2 keeps asking until one works.
+struct CredentialState {
+    github_tokens: Vec<(String, String)>,
+    tried_agent: bool,
+    key_files: Vec<PathBuf>,
+    tried_token: bool,
+    tried_helper: bool,
+    tried_default: bool,
+    exhausted: bool,
+}
+
+impl CredentialState {
+    fn new(auth: &Auth) -> Self {
+        let key_files = dirs::home_dir()
+            .map(|home| {
+                ["id_rsa", "id_ecdsa", "id_ed25519"].iter()
+                    .map(|name| home.join(".ssh").join(name))
+                    .filter(|path| path.exists())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            github_tokens: auth.github_tokens.clone(),
+            tried_agent: false,
+            key_files,
+            tried_token: false,
+            tried_helper: false,
+            tried_default: false,
+            exhausted: false,
+        }
+    }
+
+    fn next(&mut self, url: &str, username_from_url: Option<&str>, allowed: CredentialType) -> Result<Cred, git2::Error> {
+        let username = username_from_url.unwrap_or("git");
+
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if !self.tried_agent {
+                self.tried_agent = true;
+                if std::env::var_os("SSH_AUTH_SOCK").is_some() {
+                    return Cred::ssh_key_from_agent(username);
+                }
+            }
+            // Pops from the end, so id_ed25519 is tried first
+            if let Some(key) = self.key_files.pop() {
+                let public_key = key.with_extension("pub");
+                return Cred::ssh_key(username, public_key.exists().then_some(public_key.as_path()), &key, None);
+            }
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if !self.tried_token {
+                self.tried_token = true;
+                let token = self.github_tokens.iter()
+                    .find(|(host, _)| url.starts_with(host.as_str()))
+                    .map(|(_, token)| token.as_str());
+                if let Some(token) = token {
+                    return Cred::userpass_plaintext(username_from_url.unwrap_or("x-access-token"), token);
+                }
+            }
+            if !self.tried_helper {
+                self.tried_helper = true;
+                if let Ok(config) = git2::Config::open_default() {
+                    if let Ok(cred) = Cred::credential_helper(&config, url, username_from_url) {
+                        return Ok(cred);
+                    }
+                }
+            }
+        }
+        if allowed.contains(CredentialType::DEFAULT) && !self.tried_default {
+            self.tried_default = true;
+            return Cred::default();
+        }
+        self.exhausted = true;
+        Err(git2::Error::from_str("no more credentials to try"))
+    }
+
+    /// Reports failures caused by rejected or missing credentials as authentication errors.
+    fn explain(&self, error: git2::Error, url: &str) -> GitError {
+        if error.code() == ErrorCode::Auth || self.exhausted {
+            GitError::Auth { url: url.to_string(), message: error.message().to_string() }
+        } else {
+            GitError::Git(error)
+        }
+    }
+}
+
+fn callbacks<'a>(credentials: &'a RefCell<CredentialState>, rejection: Option<&'a RefCell<Option<String>>>) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| credentials.borrow_mut().next(url, username, allowed));
+
+    if let Some(rejection) = rejection {
+        callbacks.push_update_reference(move |_, status| {
+            if let Some(status) = status {
+                *rejection.borrow_mut() = Some(status.to_string());
+            }
+            Ok(())
+        });
+    }
+
+    if shows_progress() {
+        callbacks.transfer_progress(|progress| {
+            report_transfer(&progress);
+            true
+        });
+        callbacks.push_transfer_progress(|current, total, _| {
+            if let Some(percent) = (current * 100).checked_div(total) {
+                eprint!("\rWriting objects: {:>3}% ({}/{})", percent, current, total);
+            }
+        });
+        callbacks.sideband_progress(|data| {
+            eprint!("remote: {}", String::from_utf8_lossy(data));
+            true
+        });
+    }
+    callbacks
+}
+
+fn report_transfer(progress: &Progress) {
+    let total = progress.total_objects();
+    if total == 0 {
+        return;
+    }
+    let received = progress.received_objects();
+    if received < total {
+        eprint!("\rReceiving objects: {:>3}% ({}/{})", received * 100 / total, received, total);
+    } else {
+        let indexed = progress.indexed_deltas();
+        let deltas = progress.total_deltas();
+        if let Some(percent) = (indexed * 100).checked_div(deltas) {
+            eprint!("\rResolving deltas: {:>3}% ({}/{})    ", percent, indexed, deltas);
+        } else {
+            eprint!("\rReceiving objects: 100% ({}/{})", received, total);
+        }
+    }
+}
+
+/// Ends a progress line so following output starts on a new line.
+fn finish_progress() {
+    let mut stderr = std::io::stderr();
+    if shows_progress() {
+        let _ = write!(stderr, "\r\x1b[K");
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+
+use crate::config::RemoteConfig;
+use crate::remote::{PAGE_SIZE, RemoteProvider, api_call};
+
+/// A Gitea or Forgejo server, through its REST API.
+pub struct GiteaClient {
+    api: String,
+    token: String,
+    owner: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Repo {
+    ssh_url: String,
+}
+
+#[derive(Deserialize)]
+struct RepoName {
+    name: String,
+    owner: User,
+}
+
+impl GiteaClient {
+    pub fn new(remote: &RemoteConfig) -> Result<Self> {
+        let url = remote.url.as_deref()
+            .context("Gitea remotes need the server's `url`, e.g. https://git.example.com")?
+            .trim_end_matches('/');
+        let token = remote.token.clone()
+            .or_else(|| std::env::var("GITEA_TOKEN").ok())
+            .context("Gitea needs an API token: set `token` for the remote in config.toml or GITEA_TOKEN")?;
+
+        Ok(Self {
+            api: format!("{}/api/v1", url),
+            token,
+            owner: remote.owner.clone(),
+        })
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        ureq::request(method, &format!("{}{}", self.api, path))
+            .set("Authorization", &format!("token {}", self.token))
+    }
+
+    fn owner(&self) -> Result<String> {
+        match &self.owner {
+            Some(owner) => Ok(owner.clone()),
+            None => self.whoami(),
+        }
+    }
+
+    fn repo(&self, name: &str) -> Result<Option<Repo>> {
+        api_call(self.request("GET", &format!("/repos/{}/{}", self.owner()?, name)), None)
+    }
+}
+
+impl RemoteProvider for GiteaClient {
+    fn create_repo(&self, name: &str) -> Result<()> {
+        let body = serde_json::json!({ "name": name, "private": true });
+
+        // Repositories owned by someone other than the token's user belong to an organization
+        let user = self.whoami()?;
+        let path = match self.owner.as_deref().filter(|owner| *owner != user) {
+            Some(org) => format!("/orgs/{}/repos", org),
+            None => "/user/repos".to_string(),
+        };
+        api_call::<IgnoredAny>(self.request("POST", &path), Some(body))
+            .context("Failed to create Gitea repository")?;
+        Ok(())
+    }
+
+    fn repo_exists(&self, name: &str) -> Result<bool> {
+        Ok(self.repo(name)?.is_some())
+    }
+
+    fn clone_url(&self, name: &str) -> Result<String> {
+        let repo = self.repo(name)?
+            .with_context(|| format!("Gitea repository '{}' not found", name))?;
+        Ok(repo.ssh_url)
+    }
+
+    fn whoami(&self) -> Result<String> {
+        let user: User = api_call(self.request("GET", "/user"), None)?
+            .context("Gitea did not return the current user")?;
+        Ok(user.login)
+    }
+
+    fn list_repos(&self) -> Result<Vec<String>> {
+        let owner = self.owner()?;
+        let listing = if owner == self.whoami()? {
+            "/user/repos".to_string()
+        } else {
+            format!("/orgs/{}/repos", owner)
+        };
+        let mut names = Vec::new();
+        for page in 1.. {
+            let path = format!("{}?limit={}&page={}", listing, PAGE_SIZE, page);
+            let repos: Vec<RepoName> = api_call(self.request("GET", &path), None)?.unwrap_or_default();
+            let last_page = repos.len() < PAGE_SIZE;
+            for repo in repos {
+                // `/user/repos` also lists repositories the user only collaborates on
+                if !repo.owner.login.eq_ignore_ascii_case(&owner) {
+                    continue;
+                }
+                let contents = format!("/repos/{}/{}/contents/.gitbox", repo.owner.login, repo.name);
+                if api_call::<IgnoredAny>(self.request("GET", &contents), None)?.is_some() {
+                    names.push(repo.name);
+                }
+            }
+            if last_page {
+                break;
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
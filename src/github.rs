@@ -1,82 +1,251 @@
 use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde::de::IgnoredAny;
 use std::process::Command;
 
-pub struct GitHubClient;
+use crate::remote::{PAGE_SIZE, RemoteProvider, api_call};
+
+/// API base URL of github.com; GitHub Enterprise uses `https://<host>/api/v3`.
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Where git reaches the repositories of the REST API at `api_url`, as a URL prefix:
+/// `https://github.com/` for github.com, `https://<host>/` for GitHub Enterprise at
+/// `https://<host>/api/v3`.
+pub fn git_host(api_url: &str) -> String {
+    let api_url = api_url.trim_end_matches('/');
+    if api_url == DEFAULT_API_URL {
+        return "https://github.com/".to_string();
+    }
+    let (scheme, rest) = api_url.split_once("://").unwrap_or(("https", api_url));
+    let host = rest.split('/').next().unwrap_or(rest);
+    format!("{}://{}/", scheme, host)
+}
+
+/// GitHub through its REST API when a token is available, or the `gh` CLI otherwise.
+pub struct GitHubClient {
+    backend: Backend,
+    owner: Option<String>,
+}
+
+enum Backend {
+    Api { url: String, token: String },
+    Cli,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Repo {
+    ssh_url: String,
+}
+
+#[derive(Deserialize)]
+struct RepoName {
+    name: String,
+}
 
 impl GitHubClient {
-    pub fn new(_token: Option<&str>) -> Result<Self> {
+    /// Uses the REST API at `api_url` (github.com by default) if there is a `token`,
+    /// and an authenticated `gh` otherwise. `owner` is an organization to create
+    /// repositories in; the authenticated user otherwise.
+    pub fn new(token: Option<String>, api_url: Option<&str>, owner: Option<String>) -> Result<Self> {
+        if let Some(token) = token {
+            let url = api_url.unwrap_or(DEFAULT_API_URL).trim_end_matches('/').to_string();
+            return Ok(Self { backend: Backend::Api { url, token }, owner });
+        }
+
         // Check if gh CLI is available
         let output = Command::new("gh")
             .arg("auth")
             .arg("status")
             .output()
-            .context("Failed to run 'gh' command. Please install GitHub CLI (gh) and authenticate with 'gh auth login'")?;
+            .context("No GitHub token configured and failed to run 'gh'. Set github_token in config.toml or GH_TOKEN, or install GitHub CLI (gh) and run 'gh auth login'")?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("GitHub CLI authentication failed: {}", stderr));
+            return Err(anyhow::anyhow!("No GitHub token configured and GitHub CLI authentication failed: {}", stderr));
         }
 
-        Ok(Self)
+        Ok(Self { backend: Backend::Cli, owner })
     }
 
-    pub async fn create_private_repo(&self, repo_name: &str) -> Result<String> {
-        // Create repository using gh CLI
+    fn owner(&self) -> Result<String> {
+        match &self.owner {
+            Some(owner) => Ok(owner.clone()),
+            None => self.get_authenticated_user(),
+        }
+    }
+
+    fn full_name(&self, repo_name: &str) -> Result<String> {
+        Ok(format!("{}/{}", self.owner()?, repo_name))
+    }
+
+    pub fn get_authenticated_user(&self) -> Result<String> {
+        if let Backend::Api { url, token } = &self.backend {
+            let user: User = api_call(api_request(url, token, "GET", "/user"), None)?
+                .context("GitHub did not return the authenticated user")?;
+            return Ok(user.login);
+        }
+
         let output = Command::new("gh")
-            .args(&["repo", "create", repo_name, "--private", "--clone=false"])
+            .args(["api", "user", "--jq", ".login"])
             .output()
-            .context("Failed to create GitHub repository with gh CLI")?;
+            .context("Failed to get authenticated user with gh CLI")?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("Failed to create GitHub repository: {}", stderr));
+            return Err(anyhow::anyhow!("Failed to get authenticated user: {}", stderr));
         }
 
-        // Get the clone URL (use sshUrl for git operations)
+        let username = String::from_utf8(output.stdout)
+            .context("Invalid UTF-8 in username response")?
+            .trim()
+            .to_string();
+
+        Ok(username)
+    }
+}
+
+impl RemoteProvider for GitHubClient {
+    fn create_repo(&self, name: &str) -> Result<()> {
+        if let Backend::Api { url, token } = &self.backend {
+            // Repositories owned by someone other than the token's user belong to an organization
+            let user = self.get_authenticated_user()?;
+            let path = match self.owner.as_deref().filter(|owner| *owner != user) {
+                Some(org) => format!("/orgs/{}/repos", org),
+                None => "/user/repos".to_string(),
+            };
+            let body = serde_json::json!({ "name": name, "private": true });
+            api_call::<IgnoredAny>(api_request(url, token, "POST", &path), Some(body))
+                .context("Failed to create GitHub repository")?;
+            return Ok(());
+        }
+
+        // Create repository using gh CLI
         let output = Command::new("gh")
-            .args(&["repo", "view", repo_name, "--json", "sshUrl", "-q", ".sshUrl"])
+            .args(["repo", "create", &self.full_name(name)?, "--private", "--clone=false"])
             .output()
-            .context("Failed to get repository clone URL")?;
+            .context("Failed to create GitHub repository with gh CLI")?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("Failed to get repository clone URL: {}", stderr));
+            return Err(anyhow::anyhow!("Failed to create GitHub repository: {}", stderr));
         }
 
-        let clone_url = String::from_utf8(output.stdout)
-            .context("Invalid UTF-8 in clone URL response")?
-            .trim()
-            .to_string();
-
-        Ok(clone_url)
+        Ok(())
     }
 
-    pub async fn repo_exists(&self, owner: &str, repo_name: &str) -> Result<bool> {
-        let repo_full_name = format!("{}/{}", owner, repo_name);
+    fn repo_exists(&self, name: &str) -> Result<bool> {
+        let full_name = self.full_name(name)?;
+        if let Backend::Api { url, token } = &self.backend {
+            let request = api_request(url, token, "GET", &format!("/repos/{}", full_name));
+            return Ok(api_call::<IgnoredAny>(request, None)?.is_some());
+        }
+
         let output = Command::new("gh")
-            .args(&["repo", "view", &repo_full_name])
+            .args(["repo", "view", &full_name])
             .output()
             .context("Failed to check repository existence with gh CLI")?;
 
         Ok(output.status.success())
     }
 
-    pub async fn get_authenticated_user(&self) -> Result<String> {
+    fn clone_url(&self, name: &str) -> Result<String> {
+        let full_name = self.full_name(name)?;
+        if let Backend::Api { url, token } = &self.backend {
+            let repo: Repo = api_call(api_request(url, token, "GET", &format!("/repos/{}", full_name)), None)?
+                .with_context(|| format!("GitHub repository '{}' not found", full_name))?;
+            return Ok(repo.ssh_url);
+        }
+
+        // Use sshUrl for git operations
         let output = Command::new("gh")
-            .args(&["api", "user", "--jq", ".login"])
+            .args(["repo", "view", &full_name, "--json", "sshUrl", "-q", ".sshUrl"])
             .output()
-            .context("Failed to get authenticated user with gh CLI")?;
+            .context("Failed to get repository clone URL")?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("Failed to get authenticated user: {}", stderr));
+            return Err(anyhow::anyhow!("Failed to get repository clone URL: {}", stderr));
         }
 
-        let username = String::from_utf8(output.stdout)
-            .context("Invalid UTF-8 in username response")?
+        let clone_url = String::from_utf8(output.stdout)
+            .context("Invalid UTF-8 in clone URL response")?
             .trim()
             .to_string();
 
-        Ok(username)
+        Ok(clone_url)
+    }
+
+    fn whoami(&self) -> Result<String> {
+        self.get_authenticated_user()
+    }
+
+    fn delete_command(&self, name: &str) -> Option<String> {
+        match &self.owner {
+            Some(owner) => Some(format!("gh repo delete {}/{}", owner, name)),
+            None => Some(format!("gh repo delete {}", name)),
+        }
+    }
+
+    fn list_repos(&self) -> Result<Vec<String>> {
+        let owner = self.owner()?;
+        let mut names = Vec::new();
+        if let Backend::Api { url, token } = &self.backend {
+            let listing = if self.owner.is_some() && owner != self.get_authenticated_user()? {
+                format!("/orgs/{}/repos", owner)
+            } else {
+                "/user/repos?affiliation=owner".to_string()
+            };
+            let separator = if listing.contains('?') { '&' } else { '?' };
+            for page in 1.. {
+                let path = format!("{}{}per_page={}&page={}", listing, separator, PAGE_SIZE, page);
+                let repos: Vec<RepoName> = api_call(api_request(url, token, "GET", &path), None)?.unwrap_or_default();
+                let last_page = repos.len() < PAGE_SIZE;
+                for repo in repos {
+                    let contents = format!("/repos/{}/{}/contents/.gitbox", owner, repo.name);
+                    if api_call::<IgnoredAny>(api_request(url, token, "GET", &contents), None)?.is_some() {
+                        names.push(repo.name);
+                    }
+                }
+                if last_page {
+                    break;
+                }
+            }
+        } else {
+            let output = Command::new("gh")
+                .args(["repo", "list", &owner, "--limit", "1000", "--json", "name", "-q", ".[].name"])
+                .output()
+                .context("Failed to list repositories with gh CLI")?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(anyhow::anyhow!("Failed to list repositories: {}", stderr));
+            }
+
+            for name in String::from_utf8_lossy(&output.stdout).lines() {
+                let has_metadata = Command::new("gh")
+                    .args(["api", &format!("repos/{}/{}/contents/.gitbox", owner, name), "--silent"])
+                    .output()
+                    .context("Failed to check repository contents with gh CLI")?
+                    .status
+                    .success();
+                if has_metadata {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
     }
-}
\ No newline at end of file
+}
+
+fn api_request(url: &str, token: &str, method: &str, path: &str) -> ureq::Request {
+    ureq::request(method, &format!("{}{}", url, path))
+        .set("Authorization", &format!("Bearer {}", token))
+        .set("Accept", "application/vnd.github+json")
+        .set("User-Agent", concat!("gitbox/", env!("CARGO_PKG_VERSION")))
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+
+use crate::config::RemoteConfig;
+use crate::remote::{PAGE_SIZE, RemoteProvider, api_call};
+
+/// GitLab.com or a self-hosted GitLab, through its REST API.
+pub struct GitLabClient {
+    api: String,
+    token: String,
+    owner: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct Project {
+    ssh_url_to_repo: String,
+}
+
+#[derive(Deserialize)]
+struct ProjectSummary {
+    id: u64,
+    path: String,
+    default_branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Namespace {
+    id: u64,
+}
+
+impl GitLabClient {
+    pub fn new(remote: &RemoteConfig) -> Result<Self> {
+        let url = remote.url.as_deref().unwrap_or("https://gitlab.com").trim_end_matches('/');
+        let token = remote.token.clone()
+            .or_else(|| std::env::var("GITLAB_TOKEN").ok())
+            .context("GitLab needs an API token: set `token` for the remote in config.toml or GITLAB_TOKEN")?;
+
+        Ok(Self {
+            api: format!("{}/api/v4", url),
+            token,
+            owner: remote.owner.clone(),
+        })
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        ureq::request(method, &format!("{}{}", self.api, path))
+            .set("PRIVATE-TOKEN", &self.token)
+    }
+
+    fn owner(&self) -> Result<String> {
+        match &self.owner {
+            Some(owner) => Ok(owner.clone()),
+            None => self.whoami(),
+        }
+    }
+
+    fn project(&self, name: &str) -> Result<Option<Project>> {
+        // Full paths are passed URL-encoded, including subgroup separators
+        let full_path = format!("{}/{}", self.owner()?, name).replace('/', "%2F");
+        api_call(self.request("GET", &format!("/projects/{}", full_path)), None)
+    }
+}
+
+impl RemoteProvider for GitLabClient {
+    fn create_repo(&self, name: &str) -> Result<()> {
+        let mut body = serde_json::json!({ "name": name, "path": name, "visibility": "private" });
+
+        // Projects land in the user's namespace unless a group owns them
+        let user = self.whoami()?;
+        if let Some(owner) = self.owner.as_deref().filter(|owner| *owner != user) {
+            let namespace: Namespace = api_call(self.request("GET", &format!("/namespaces/{}", owner.replace('/', "%2F"))), None)?
+                .with_context(|| format!("GitLab namespace '{}' not found", owner))?;
+            body["namespace_id"] = namespace.id.into();
+        }
+
+        api_call::<IgnoredAny>(self.request("POST", "/projects"), Some(body))
+            .context("Failed to create GitLab project")?;
+        Ok(())
+    }
+
+    fn repo_exists(&self, name: &str) -> Result<bool> {
+        Ok(self.project(name)?.is_some())
+    }
+
+    fn clone_url(&self, name: &str) -> Result<String> {
+        let project = self.project(name)?
+            .with_context(|| format!("GitLab project '{}' not found", name))?;
+        Ok(project.ssh_url_to_repo)
+    }
+
+    fn whoami(&self) -> Result<String> {
+        let user: User = api_call(self.request("GET", "/user"), None)?
+            .context("GitLab did not return the current user")?;
+        Ok(user.username)
+    }
+
+    fn list_repos(&self) -> Result<Vec<String>> {
+        let user = self.whoami()?;
+        let listing = match self.owner.as_deref().filter(|owner| *owner != user) {
+            Some(group) => format!("/groups/{}/projects?", group.replace('/', "%2F")),
+            None => "/projects?owned=true&".to_string(),
+        };
+        let mut names = Vec::new();
+        for page in 1.. {
+            let path = format!("{}per_page={}&page={}", listing, PAGE_SIZE, page);
+            let projects: Vec<ProjectSummary> = api_call(self.request("GET", &path), None)?.unwrap_or_default();
+            let last_page = projects.len() < PAGE_SIZE;
+            for project in projects {
+                // Empty projects have no default branch and so no .gitbox
+                let Some(branch) = project.default_branch else {
+                    continue;
+                };
+                let file = format!("/projects/{}/repository/files/.gitbox?ref={}", project.id, branch);
+                if api_call::<IgnoredAny>(self.request("GET", &file), None)?.is_some() {
+                    names.push(project.path);
+                }
+            }
+            if last_page {
+                break;
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
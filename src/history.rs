@@ -0,0 +1,263 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use git2::{Commit, DiffOptions, ObjectType, Oid, Patch, Repository, Sort, Tree, TreeWalkMode, TreeWalkResult};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backup::Backup;
+use crate::crypt;
+use crate::sync::{FileInfo, SyncMode, list_files};
+use crate::template;
+
+/// A commit that changed a tracked item.
+#[derive(Debug)]
+pub struct Change {
+    pub id: Oid,
+    pub time: DateTime<Utc>,
+    pub author: String,
+    pub summary: String,
+}
+
+/// Finds the commit `rev` names: a commit id (or a unique prefix), `HEAD~2`, a branch, ...
+pub fn resolve_commit<'r>(git_repo: &'r Repository, rev: &str) -> Result<Commit<'r>> {
+    git_repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| format!("Unknown revision '{}'", rev))
+}
+
+/// The commits reachable from HEAD that changed `path` (relative to the repository
+/// root), newest first. A merge counts only if it differs from every parent.
+pub fn changes(git_repo: &Repository, path: &Path) -> Result<Vec<Change>> {
+    let mut walk = git_repo.revwalk().context("Failed to walk history")?;
+    if walk.push_head().is_err() {
+        return Ok(Vec::new());
+    }
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+
+    let mut changes = Vec::new();
+    for id in walk {
+        let commit = git_repo.find_commit(id?)?;
+        let current = entry_id(&commit.tree()?, path);
+        let changed = if commit.parent_count() == 0 {
+            current.is_some()
+        } else {
+            commit.parents().all(|parent| parent.tree().ok().and_then(|tree| entry_id(&tree, path)) != current)
+        };
+        if changed {
+            let time = DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default();
+            changes.push(Change {
+                id: commit.id(),
+                time,
+                author: commit.author().name().unwrap_or_default().to_string(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// How many commits reachable from `tip` are not reachable from `base` (all of them
+/// without a base).
+pub fn commits_since(git_repo: &Repository, tip: Oid, base: Option<Oid>) -> Result<usize> {
+    let mut walk = git_repo.revwalk().context("Failed to walk history")?;
+    walk.push(tip)?;
+    if let Some(base) = base {
+        walk.hide(base)?;
+    }
+    Ok(walk.count())
+}
+
+fn entry_id(tree: &Tree, path: &Path) -> Option<Oid> {
+    tree.get_path(path).ok().map(|entry| entry.id())
+}
+
+/// Every file at or below `path` in `tree`, keyed by its path relative to `path` (empty
+/// for a file). Nothing if `path` is not in the tree.
+fn files_at(git_repo: &Repository, tree: &Tree, path: &Path) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    let Ok(entry) = tree.get_path(path) else {
+        return Ok(files);
+    };
+    match entry.kind() {
+        Some(ObjectType::Blob) => {
+            files.insert(PathBuf::new(), git_repo.find_blob(entry.id())?.content().to_vec());
+        }
+        Some(ObjectType::Tree) => {
+            let subtree = git_repo.find_tree(entry.id())?;
+            let mut result = Ok(());
+            subtree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+                if entry.kind() != Some(ObjectType::Blob) {
+                    return TreeWalkResult::Ok;
+                }
+                let relative = Path::new(dir).join(entry.name().unwrap_or_default());
+                match git_repo.find_blob(entry.id()) {
+                    Ok(blob) => {
+                        files.insert(relative, blob.content().to_vec());
+                        TreeWalkResult::Ok
+                    }
+                    Err(e) => {
+                        result = Err(e);
+                        TreeWalkResult::Abort
+                    }
+                }
+            })?;
+            result.context("Failed to read file from history")?;
+        }
+        _ => {}
+    }
+    Ok(files)
+}
+
+/// A tracked item, or a file inside a tracked directory, as the history commands see it.
+#[derive(Debug)]
+pub struct Item {
+    pub info: FileInfo,
+    /// Path in the repository, relative to its root
+    pub repo_path: PathBuf,
+    /// Where it lives on this machine
+    pub local: PathBuf,
+    /// Its copy in the repository's working tree
+    pub synced: PathBuf,
+}
+
+impl Item {
+    /// The stored content of every file of the item in `commit`, as committed (so
+    /// still encrypted for an encrypted entry).
+    pub fn stored_at(&self, git_repo: &Repository, commit: &Commit) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+        files_at(git_repo, &commit.tree()?, &self.repo_path)
+    }
+
+    /// What the user edits: the decrypted original of an encrypted entry, the template
+    /// of a template entry, otherwise the original.
+    pub fn readable(&self, stored: BTreeMap<PathBuf, Vec<u8>>) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+        if !self.info.encrypted {
+            return Ok(stored);
+        }
+        stored.into_iter()
+            .map(|(path, content)| Ok((path, crypt::decrypt(&content)?)))
+            .collect()
+    }
+
+    /// The current content of every file of the item on this machine, in the form
+    /// `readable` gives. Ignored files in a directory are left out.
+    pub fn current(&self, git_repo: &Repository) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+        let source = if self.info.mode == SyncMode::Template { &self.synced } else { &self.local };
+        let mut files = BTreeMap::new();
+        if source.is_dir() {
+            for name in list_files(source)? {
+                if git_repo.is_path_ignored(self.repo_path.join(&name)).unwrap_or(false) {
+                    continue;
+                }
+                let path = source.join(&name);
+                files.insert(name, fs::read(&path).with_context(|| format!("Failed to read file: {:?}", path))?);
+            }
+        } else if source.is_file() {
+            files.insert(PathBuf::new(), fs::read(source).with_context(|| format!("Failed to read file: {:?}", source))?);
+        }
+        Ok(files)
+    }
+
+    /// Puts `stored` (from `stored_at`) back into the repository copy and the original,
+    /// writing through hard links, and removes files a directory gained since. Local
+    /// files with other content are backed up first. Returns the number of files that
+    /// changed.
+    pub fn write_back(&self, git_repo: &Repository, stored: &BTreeMap<PathBuf, Vec<u8>>) -> Result<usize> {
+        let mut changed = 0;
+        if self.info.is_directory {
+            let head = git_repo.head()
+                .and_then(|head| head.peel_to_commit())
+                .context("Failed to read the current commit")?;
+            for name in self.stored_at(git_repo, &head)?.keys().filter(|name| !stored.contains_key(*name)) {
+                let local = within(&self.local, name);
+                if local.exists() {
+                    Backup::current().save(&local, "removed by restore-version")?;
+                }
+                for path in [within(&self.synced, name), local] {
+                    if path.exists() {
+                        fs::remove_file(&path).with_context(|| format!("Failed to remove file: {:?}", path))?;
+                    }
+                }
+                changed += 1;
+            }
+        }
+        for (name, content) in stored {
+            let synced = within(&self.synced, name);
+            let local = within(&self.local, name);
+            let local_content = if self.info.encrypted {
+                crypt::decrypt(content)?
+            } else if self.info.mode == SyncMode::Template {
+                let rendered = template::render_with(&synced, |variant| {
+                    if variant == synced {
+                        return Ok(Some(content.clone()));
+                    }
+                    Ok(fs::read(variant).ok())
+                })?;
+                rendered.unwrap_or_default()
+            } else {
+                content.clone()
+            };
+
+            let local_differs = fs::read(&local).ok().as_ref() != Some(&local_content);
+            let synced_differs = fs::read(&synced).ok().as_ref() != Some(content);
+            if !local_differs && !synced_differs {
+                continue;
+            }
+            if local_differs && local.exists() {
+                Backup::current().save(&local, "replaced by restore-version")?;
+            }
+            for (path, content) in [(&synced, content), (&local, &local_content)] {
+                if fs::read(path).ok().as_ref() == Some(content) {
+                    continue;
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+                }
+                // Writing in place keeps a hard-linked original pointing at the new content
+                fs::write(path, content).with_context(|| format!("Failed to write file: {:?}", path))?;
+            }
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    /// A unified diff from `old` to `new`, both in the form `readable` gives.
+    pub fn diff(&self, old: &BTreeMap<PathBuf, Vec<u8>>, new: &BTreeMap<PathBuf, Vec<u8>>) -> Result<String> {
+        let names: BTreeSet<&PathBuf> = old.keys().chain(new.keys()).collect();
+        let mut text = String::new();
+        for name in names {
+            let label = within(&self.repo_path, name);
+            text.push_str(&unified_diff(old.get(name), new.get(name), &label)?);
+        }
+        Ok(text)
+    }
+}
+
+/// `base` itself for an empty `name`, otherwise `name` inside it.
+fn within(base: &Path, name: &Path) -> PathBuf {
+    if name.as_os_str().is_empty() { base.to_path_buf() } else { base.join(name) }
+}
+
+/// A unified diff from `old` to `new` (a missing side counts as empty) of the file at
+/// `path`. Empty if they are the same.
+fn unified_diff(old: Option<&Vec<u8>>, new: Option<&Vec<u8>>, path: &Path) -> Result<String> {
+    if old == new {
+        return Ok(String::new());
+    }
+    let mut options = DiffOptions::new();
+    options.context_lines(3);
+    let mut patch = Patch::from_buffers(
+        old.map_or(&[][..], Vec::as_slice),
+        Some(path),
+        new.map_or(&[][..], Vec::as_slice),
+        Some(path),
+        Some(&mut options),
+    )
+    .context("Failed to compare versions")?;
+    if patch.delta().flags().is_binary() {
+        return Ok(format!("Binary file {} differs\n", path.display()));
+    }
+    let text = patch.to_buf().context("Failed to format diff")?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
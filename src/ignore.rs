@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use git2::Repository;
+use std::fs;
+use std::path::Path;
+
+use crate::sync::GitboxMetadata;
+
+/// Name of the files holding ignore patterns: one at the repository root for all of
+/// `files/`, and optionally one inside each synced directory.
+pub const IGNORE_FILE: &str = ".gitboxignore";
+
+/// Lines around the rules gitbox writes to `.git/info/exclude`; the rest is left alone.
+const BEGIN: &str = "# BEGIN gitbox: generated from .gitboxignore files and `sync --exclude`; edits here are overwritten";
+const END: &str = "# END gitbox";
+
+/// Writes every gitbox ignore pattern of the repository at `repo_path` to a marked
+/// block of its `.git/info/exclude`, so staging, status and stashing all skip the
+/// ignored files. Rules outside the block are kept.
+pub fn refresh(repo_path: &Path) -> Result<()> {
+    let rules = rules(repo_path)?;
+    let exclude_file = repo_path.join(".git").join("info").join("exclude");
+    let current = fs::read_to_string(&exclude_file).unwrap_or_default();
+    let content = replace_block(&current, &rules);
+    if content == current {
+        return Ok(());
+    }
+    if let Some(parent) = exclude_file.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::write(&exclude_file, content)
+        .with_context(|| format!("Failed to write ignore rules: {:?}", exclude_file))
+}
+
+/// Makes `git_repo` skip the files the current gitbox ignore patterns cover without
+/// writing anything, for commands that only look at the repository. The rules last as
+/// long as this handle.
+pub fn apply(git_repo: &Repository) -> Result<()> {
+    let Some(workdir) = git_repo.workdir() else {
+        return Ok(());
+    };
+    git_repo.add_ignore_rule(&rules(workdir)?)
+        .context("Failed to apply ignore rules")
+}
+
+/// Every gitbox ignore pattern of the repository at `repo_path`, as gitignore rules
+/// relative to its root.
+fn rules(repo_path: &Path) -> Result<String> {
+    let metadata = GitboxMetadata::load_from_dir(repo_path)?;
+    let mut rules = String::new();
+
+    let files_dir = repo_path.join("files");
+    push_rules(&mut rules, "files", &read_patterns(&repo_path.join(IGNORE_FILE))?);
+    for info in metadata.files.values().filter(|info| info.is_directory) {
+        let Ok(relative) = info.synced_path.strip_prefix(repo_path) else {
+            continue;
+        };
+        if !info.synced_path.starts_with(&files_dir) {
+            continue;
+        }
+        let base = relative.to_string_lossy();
+        push_rules(&mut rules, &base, &info.exclude);
+        push_rules(&mut rules, &base, &read_patterns(&info.synced_path.join(IGNORE_FILE))?);
+    }
+    Ok(rules)
+}
+
+/// `current` with the gitbox block (if any) removed and a new one holding `rules`
+/// appended. A begin marker without an end marker is dropped on its own, keeping the
+/// lines after it.
+fn replace_block(current: &str, rules: &str) -> String {
+    let lines: Vec<&str> = current.lines().collect();
+    let mut content = String::new();
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        index += 1;
+        if line == BEGIN {
+            if let Some(end) = lines[index..].iter().position(|line| *line == END) {
+                index += end + 1;
+            }
+            continue;
+        }
+        content.push_str(line);
+        content.push('\n');
+    }
+    content.push_str(&format!("{}\n{}{}\n", BEGIN, rules, END));
+    content
+}
+
+/// Patterns in an ignore file, without comments and blank lines. A missing file has none.
+fn read_patterns(path: &Path) -> Result<Vec<String>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read ignore file: {:?}", path))?;
+    Ok(content.lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Adds `patterns`, written relative to the repository directory `base`, as
+/// gitignore rules relative to the repository root.
+fn push_rules(rules: &mut String, base: &str, patterns: &[String]) {
+    for pattern in patterns {
+        let (negate, pattern) = match pattern.strip_prefix('!') {
+            Some(rest) => ("!", rest),
+            None => ("", pattern.as_str()),
+        };
+        // As in .gitignore, a pattern with a slash (other than a trailing one) is
+        // relative to its base; one without matches at any depth below it
+        let anchored = pattern.trim_end_matches('/').contains('/');
+        let pattern = pattern.trim_start_matches('/');
+        if anchored {
+            rules.push_str(&format!("{}/{}/{}\n", negate, base, pattern));
+        } else {
+            rules.push_str(&format!("{}/{}/**/{}\n", negate, base, pattern));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_block_keeps_rules_around_the_block() {
+        let current = format!("*.swp\n{}\n/files/old\n{}\n.DS_Store\n", BEGIN, END);
+        assert_eq!(
+            replace_block(&current, "/files/new\n"),
+            format!("*.swp\n.DS_Store\n{}\n/files/new\n{}\n", BEGIN, END)
+        );
+    }
+
+    #[test]
+    fn replace_block_without_an_end_marker_keeps_the_lines_after_it() {
+        let current = format!("*.swp\n{}\n/files/old\n.DS_Store\n", BEGIN);
+        assert_eq!(
+            replace_block(&current, "/files/new\n"),
+            format!("*.swp\n/files/old\n.DS_Store\n{}\n/files/new\n{}\n", BEGIN, END)
+        );
+    }
+}
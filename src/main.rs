@@ -1,17 +1,46 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
+mod backup;
+mod commit;
 mod config;
+mod crypt;
 mod repo;
+mod git;
+mod gitea;
 mod github;
+mod gitlab;
+mod history;
+mod ignore;
+mod merge;
+mod output;
+mod paths;
+mod profile;
+mod remote;
+mod repair;
+mod restore;
+mod secrets;
+mod status;
 mod sync;
+mod sync_all;
+mod template;
+mod watch;
 
-use config::Config;
-use repo::RepoManager;
+use config::{Config, DirtyPullStrategy};
+use merge::ResolveChoice;
+use profile::Profile;
+use repo::{PullOptions, RepoManager, SyncOptions};
+use sync::SyncMode;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Print what would be linked, overwritten, committed, pushed and created without doing it
+    #[arg(long, global = true)]
+    dry_run: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -22,6 +51,21 @@ enum Commands {
     AddRepo {
         /// Repository name
         name: String,
+        /// Remote provider from `[remotes]` in config.toml to create it on (remembered for this repository)
+        #[arg(long)]
+        remote: Option<String>,
+    },
+    /// Set up this machine from the remote: clone repositories and link their files into place
+    Restore {
+        /// Repository to restore
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        repo: Option<String>,
+        /// Restore every gitbox repository found on the remote
+        #[arg(long)]
+        all: bool,
+        /// Remote provider from `[remotes]` in config.toml to restore from
+        #[arg(long)]
+        remote: Option<String>,
     },
     /// Delete a local repository
     DeleteLocalRepo {
@@ -49,20 +93,129 @@ enum Commands {
         /// Target repository (defaults to 'gitbox-default')
         #[arg(long)]
         repo: Option<String>,
+        /// Directory the repository layout is relative to (defaults to your home directory)
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Store the file encrypted (see [encryption] in config.toml)
+        #[arg(long)]
+        encrypt: bool,
+        /// Leave matching paths inside a synced directory out of the repository (.gitignore syntax, repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+        /// Link the repository copy to the original, keep an independent copy and copy changes both ways,
+        /// or store a template rendered per machine (defaults to link for files; directories are always copied)
+        #[arg(long, value_enum)]
+        mode: Option<SyncMode>,
+        #[command(flatten)]
+        commit: CommitArgs,
+    },
+    /// Stop syncing a file and leave an independent copy in its place
+    Unsync {
+        /// Synced file or directory
+        path: String,
+        /// Repository the file is synced to (searched in all repositories if omitted)
+        #[arg(long)]
+        repo: Option<String>,
+        /// Keep the file's content in the repository
+        #[arg(long)]
+        keep_in_repo: bool,
+        #[command(flatten)]
+        commit: CommitArgs,
     },
     /// Sync a file from remote repository to current directory
     #[command(name = "sync-from-remote")]
     SyncFromRemote {
-        /// File name to sync from remote
+        /// Path inside the repository (or a unique file name) to sync from remote
         filename: String,
         /// Source repository (defaults to 'gitbox-default')
         #[arg(long)]
         repo: Option<String>,
+        #[command(flatten)]
+        pull: PullArgs,
     },
     /// List all repositories
     ListRepos,
     /// List all synced files across repositories
     ListFiles,
+    /// Show the state of every synced file across repositories
+    Status {
+        /// Only show this repository
+        #[arg(long)]
+        repo: Option<String>,
+        /// Fetch from remotes before comparing
+        #[arg(long)]
+        fetch: bool,
+        /// Print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Re-link tracked files whose hard links were broken (e.g. by an editor's atomic save)
+    Repair {
+        /// Only repair this repository
+        #[arg(long)]
+        repo: Option<String>,
+    },
+    /// Watch synced files and automatically commit, push and pull changes
+    #[command(alias = "daemon")]
+    Watch {
+        /// Seconds to wait after the last change before committing
+        #[arg(long, default_value_t = 2)]
+        debounce: u64,
+        /// Seconds between pushes of committed changes
+        #[arg(long, default_value_t = 60)]
+        push_interval: u64,
+        /// Seconds between pulls of remote changes
+        #[arg(long, default_value_t = 300)]
+        pull_interval: u64,
+    },
+    /// Resolve a conflict left by a pull and finish the sync once none remain
+    Resolve {
+        /// Conflicted file: its original path, its path in the repository, or its name.
+        /// Lists pending conflicts when omitted.
+        file: Option<String>,
+        /// How to resolve it (asks when omitted)
+        #[arg(long = "use", value_enum)]
+        choice: Option<ResolveChoice>,
+        /// Repository with the conflict (searched in all repositories if omitted)
+        #[arg(long)]
+        repo: Option<String>,
+        /// Drop the pending merge and keep every local file as it is
+        #[arg(long, conflicts_with_all = ["file", "choice"])]
+        abort: bool,
+    },
+    /// Show the commits that changed a synced file
+    Log {
+        /// Synced file: its local path, a file inside a synced directory, or its path under files/
+        file: String,
+        /// Repository tracking it (searched in all repositories if omitted)
+        #[arg(long)]
+        repo: Option<String>,
+        /// Show at most this many commits
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+    },
+    /// Show how a synced file changed since an earlier version
+    Diff {
+        /// Synced file: its local path, a file inside a synced directory, or its path under files/
+        file: String,
+        /// Version to compare with: a commit id from `gitbox log`, `HEAD~2`, ...
+        #[arg(default_value = "HEAD")]
+        rev: String,
+        /// Repository tracking it (searched in all repositories if omitted)
+        #[arg(long)]
+        repo: Option<String>,
+    },
+    /// Put an earlier version of a synced file back in place, then commit and push it
+    #[command(name = "restore-version")]
+    RestoreVersion {
+        /// Synced file: its local path, a file inside a synced directory, or its path under files/
+        file: String,
+        /// Version to restore: a commit id from `gitbox log`, `HEAD~2`, ...
+        rev: String,
+        /// Repository tracking it (searched in all repositories if omitted)
+        #[arg(long)]
+        repo: Option<String>,
+    },
     /// List remote files in the default repository
     #[command(name = "list-remote-files")]
     ListRemoteFiles,
@@ -74,6 +227,8 @@ enum Commands {
         /// Target repository (defaults to 'gitbox-default')
         #[arg(long)]
         repo: Option<String>,
+        #[command(flatten)]
+        commit: CommitArgs,
     },
     /// Pull remote file from repository to current directory
     #[command(name = "sync-pull")]
@@ -83,9 +238,39 @@ enum Commands {
         /// Source repository (defaults to 'gitbox-default')
         #[arg(long)]
         repo: Option<String>,
+        #[command(flatten)]
+        pull: PullArgs,
     },
     /// Sync all repositories with remotes
-    SyncAllRepos,
+    SyncAllRepos {
+        /// How many repositories to sync at once (defaults to the `sync_jobs` config setting, or 4)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        #[command(flatten)]
+        commit: CommitArgs,
+    },
+    /// Rewrite legacy .gitbox metadata to machine-portable paths
+    #[command(name = "migrate-metadata")]
+    MigrateMetadata,
+    /// Allow a secret flagged by the pre-commit scan to be committed and pushed
+    #[command(name = "allow-secret")]
+    AllowSecret {
+        /// Fingerprint from the scan report (`secret:...`), or a pattern of repository paths (`files/.config/app/*`)
+        entry: String,
+        /// Repository to allow it in (defaults to 'gitbox-default')
+        #[arg(long)]
+        repo: Option<String>,
+    },
+    /// Choose which synced entries apply to which machines
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Copies gitbox saved before overwriting or deleting local files
+    Backups {
+        #[command(subcommand)]
+        action: BackupsAction,
+    },
     /// Repository operations
     Repo {
         /// Get repository by name
@@ -96,27 +281,145 @@ enum Commands {
     },
 }
 
+impl Commands {
+    /// Whether the command honours `--dry-run` (or changes nothing anyway).
+    fn supports_dry_run(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Unsync { .. }
+                | Commands::Repair { .. }
+                | Commands::Watch { .. }
+                | Commands::Resolve { .. }
+                | Commands::MigrateMetadata
+                | Commands::ListRemoteFiles
+        )
+    }
+
+    /// The `-m` given to a command that commits.
+    fn commit_message(&self) -> Option<String> {
+        match self {
+            Commands::Sync { commit, .. }
+            | Commands::Unsync { commit, .. }
+            | Commands::SyncPush { commit, .. }
+            | Commands::SyncAllRepos { commit, .. }
+            | Commands::Repo { action: RepoAction::Sync { commit }, .. } => commit.message.clone(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Args)]
+struct CommitArgs {
+    /// Subject of the commit (by default it names the changed files and this machine)
+    #[arg(short, long)]
+    message: Option<String>,
+}
+
+#[derive(Args)]
+struct PullArgs {
+    /// What to do with uncommitted edits to tracked files (defaults to the `on_dirty_pull` config setting)
+    #[arg(long, value_enum)]
+    on_dirty: Option<DirtyPullStrategy>,
+    /// Discard uncommitted edits to tracked files before pulling
+    #[arg(long)]
+    force: bool,
+}
+
+impl From<PullArgs> for PullOptions {
+    fn from(args: PullArgs) -> Self {
+        Self {
+            on_dirty: args.on_dirty,
+            force: args.force,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum BackupsAction {
+    /// List saved copies, oldest first
+    List,
+    /// Copy a saved item back to where it came from
+    Restore {
+        /// Backup id, as shown by `gitbox backups list`
+        id: u64,
+    },
+    /// Delete old backups
+    Prune {
+        /// Delete backups older than this many days
+        #[arg(long, default_value_t = 30)]
+        older_than: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileAction {
+    /// Show this machine, the profiles and the entries they keep from it
+    Show {
+        /// Only this repository (defaults to all)
+        #[arg(long)]
+        repo: Option<String>,
+    },
+    /// Create a profile or add machines and entries to it
+    Set {
+        /// Profile name
+        name: String,
+        /// Repository to store it in (defaults to 'gitbox-default')
+        #[arg(long)]
+        repo: Option<String>,
+        /// Machines with this hostname use the profile (repeatable)
+        #[arg(long = "host", value_name = "HOSTNAME")]
+        hosts: Vec<String>,
+        /// Machines with this tag in their [machine] config use the profile (repeatable)
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Machines running this OS (linux, macos, windows, ...) use the profile (repeatable)
+        #[arg(long, value_name = "OS")]
+        os: Vec<String>,
+        /// Entries selected by the profile, as patterns relative to the repository's files/ (repeatable)
+        #[arg(long = "files", value_name = "PATTERN")]
+        files: Vec<String>,
+    },
+}
+
 #[derive(Subcommand)]
 enum RepoAction {
     /// List files in the repository
     List,
     /// Show repository information
     Info,
-    /// Sync repository with GitHub (pull/push)
-    Sync,
+    /// Sync repository with its remote (pull/push)
+    Sync {
+        #[command(flatten)]
+        commit: CommitArgs,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     
+    if cli.dry_run && !cli.command.supports_dry_run() {
+        return Err(anyhow::anyhow!("--dry-run is not supported by this command"));
+    }
+
     let config = Config::load_or_create()?;
     let mut repo_manager = RepoManager::new(&config)?;
+    repo_manager.set_dry_run(cli.dry_run);
+    repo_manager.set_commit_message(cli.command.commit_message());
+    // Confirmations of completed work, which a dry run does not do
+    let done = |message: String| {
+        if !cli.dry_run {
+            println!("{}", message);
+        }
+    };
 
     match cli.command {
-        Commands::AddRepo { name } => {
-            repo_manager.add_repo(&name).await?;
-            println!("Repository '{}' created and pushed to GitHub", name);
+        Commands::AddRepo { name, remote } => {
+            repo_manager.add_repo(&name, remote.as_deref()).await?;
+            done(format!("Repository '{}' created and pushed to its remote", name));
+        }
+        Commands::Restore { repo, all: _, remote } => {
+            repo_manager.restore(repo.as_deref(), remote.as_deref())?;
         }
         Commands::DeleteLocalRepo { get, force } => {
             repo_manager.delete_repo(&get, force)?;
@@ -124,15 +427,19 @@ async fn main() -> Result<()> {
         Commands::RemoveLocalRepo { get, force } => {
             repo_manager.delete_repo(&get, force)?;
         }
-        Commands::Sync { path, repo } => {
+        Commands::Sync { path, repo, root, encrypt, exclude, mode, .. } => {
             let repo_name = repo.unwrap_or_else(|| "gitbox-default".to_string());
-            repo_manager.sync_file_with_default(&path, &repo_name).await?;
-            println!("File '{}' synced to repository '{}' and pushed to GitHub", path, repo_name);
+            let options = SyncOptions { root, encrypt, exclude, mode };
+            repo_manager.sync_file_with_default(&path, &repo_name, &options).await?;
+            done(format!("File '{}' synced to repository '{}' and pushed to its remote", path, repo_name));
         }
-        Commands::SyncFromRemote { filename, repo } => {
+        Commands::Unsync { path, repo, keep_in_repo, .. } => {
+            repo_manager.unsync(&path, repo.as_deref(), keep_in_repo)?;
+        }
+        Commands::SyncFromRemote { filename, repo, pull } => {
             let repo_name = repo.unwrap_or_else(|| "gitbox-default".to_string());
-            repo_manager.sync_from_remote(&filename, &repo_name).await?;
-            println!("File '{}' synced from repository '{}' to current directory", filename, repo_name);
+            repo_manager.sync_from_remote(&filename, &repo_name, pull.into()).await?;
+            done(format!("File '{}' synced from repository '{}' to current directory", filename, repo_name));
         }
         Commands::ListRepos => {
             let repos = repo_manager.list_repos()?;
@@ -152,7 +459,69 @@ async fn main() -> Result<()> {
             } else {
                 println!("Synced files ({} total):", files.len());
                 for file in files {
-                    println!("  {} -> {}", file.original_path, file.repository);
+                    let suffix = if file.is_directory { "/" } else { "" };
+                    println!("  {}{} -> {} ({})", file.original_path, suffix, file.repository, file.synced_path);
+                }
+            }
+        }
+        Commands::Status { repo, fetch, json } => {
+            let statuses = repo_manager.status(repo.as_deref(), fetch)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&statuses)?);
+            } else if statuses.is_empty() {
+                println!("No repositories found");
+            } else {
+                for status in statuses {
+                    let remote = match &status.upstream {
+                        Some(upstream) => format!("{}, ahead {}, behind {}", upstream, status.ahead, status.behind),
+                        None => "no remote-tracking branch".to_string(),
+                    };
+                    println!("{} ({}, {})", status.name, status.branch, remote);
+                    if status.files.is_empty() {
+                        println!("  No synced files");
+                    }
+                    for file in status.files {
+                        let suffix = if file.is_directory { "/" } else { "" };
+                        match file.detail {
+                            Some(detail) => println!("  {:<12} {}{} ({})", file.state, file.original_path.display(), suffix, detail),
+                            None => println!("  {:<12} {}{}", file.state, file.original_path.display(), suffix),
+                        }
+                    }
+                }
+            }
+        }
+        Commands::Repair { repo } => {
+            let repaired = repo_manager.repair(repo.as_deref())?;
+            if repaired == 0 {
+                println!("All links are intact");
+            } else {
+                println!("Repaired {} link(s)", repaired);
+            }
+        }
+        Commands::Watch { debounce, push_interval, pull_interval } => {
+            let options = watch::WatchOptions {
+                debounce: Duration::from_secs(debounce),
+                push_interval: Duration::from_secs(push_interval.max(1)),
+                pull_interval: Duration::from_secs(pull_interval.max(1)),
+            };
+            watch::run(&repo_manager, options).await?;
+        }
+        Commands::Resolve { file, choice, repo, abort } => {
+            if abort {
+                repo_manager.abort_merge(repo.as_deref())?;
+            } else if let Some(file) = file {
+                repo_manager.resolve(&file, repo.as_deref(), choice)?;
+            } else {
+                let merges = repo_manager.pending_merges()?;
+                if merges.is_empty() {
+                    println!("No conflicts to resolve");
+                }
+                for (name, pending) in merges {
+                    println!("{}:", name);
+                    for path in &pending.conflicts {
+                        let state = if pending.resolved.contains_key(path) { "resolved" } else { "conflicted" };
+                        println!("  {:<12} {}", state, path);
+                    }
                 }
             }
         }
@@ -167,35 +536,104 @@ async fn main() -> Result<()> {
                 }
             }
         }
-        Commands::SyncPush { file, repo } => {
+        Commands::SyncPush { file, repo, .. } => {
             let repo_name = repo.unwrap_or_else(|| "gitbox-default".to_string());
             repo_manager.sync_push(&repo_name, file.as_deref()).await?;
             if let Some(file_name) = file {
-                println!("Successfully pushed file '{}' to repository '{}'", file_name, repo_name);
+                done(format!("Successfully pushed file '{}' to repository '{}'", file_name, repo_name));
             } else {
-                println!("Successfully pushed local changes to repository '{}'", repo_name);
+                done(format!("Successfully pushed local changes to repository '{}'", repo_name));
             }
         }
-        Commands::SyncPull { file, repo } => {
+        Commands::SyncPull { file, repo, pull } => {
             let repo_name = repo.unwrap_or_else(|| "gitbox-default".to_string());
-            repo_manager.sync_pull(&repo_name, &file).await?;
-            println!("Successfully pulled file '{}' from repository '{}'", file, repo_name);
+            repo_manager.sync_pull(&repo_name, &file, pull.into()).await?;
+            done(format!("Successfully pulled file '{}' from repository '{}'", file, repo_name));
         }
-        Commands::SyncAllRepos => {
+        Commands::SyncAllRepos { jobs, .. } => {
             let repos = repo_manager.list_repos()?;
             if repos.is_empty() {
                 println!("No repositories found to sync");
-            } else {
-                println!("Syncing {} repositories with remotes...", repos.len());
+            } else if cli.dry_run {
                 for repo in repos {
-                    match repo_manager.sync_repo(&repo) {
-                        Ok(_) => println!("✓ Synced '{}'", repo),
-                        Err(e) => println!("✗ Failed to sync '{}': {}", repo, e),
+                    println!("{}:", repo);
+                    if let Err(e) = repo_manager.sync_repo(&repo) {
+                        println!("✗ Cannot sync '{}': {}", repo, e);
                     }
                 }
+            } else {
+                let jobs = jobs.or(config.sync_jobs).unwrap_or(sync_all::DEFAULT_JOBS).max(1);
+                println!("Syncing {} repositories with remotes, {} at a time...", repos.len(), jobs.min(repos.len()));
+                let syncs = sync_all::run(Arc::new(repo_manager), repos, jobs).await?;
+                println!("\n{}", sync_all::summary(&syncs));
+                let failed = syncs.iter().filter(|sync| sync.failed()).count();
+                if failed > 0 {
+                    return Err(anyhow::anyhow!("{} of {} repositories failed to sync", failed, syncs.len()));
+                }
                 println!("Sync completed");
             }
         }
+        Commands::MigrateMetadata => {
+            repo_manager.migrate_metadata()?;
+        }
+        Commands::AllowSecret { entry, repo } => {
+            let repo_name = repo.unwrap_or_else(|| "gitbox-default".to_string());
+            repo_manager.allow_secret(&entry, &repo_name)?;
+        }
+        Commands::Log { file, repo, max_count } => {
+            let changes = repo_manager.file_log(&file, repo.as_deref())?;
+            if changes.is_empty() {
+                println!("No commits changed '{}'", file);
+            }
+            for change in changes.iter().take(max_count.unwrap_or(usize::MAX)) {
+                let time = change.time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S");
+                let id = change.id.to_string();
+                println!("{}  {}  {}  ({})", &id[..7], time, change.summary, change.author);
+            }
+        }
+        Commands::Diff { file, rev, repo } => {
+            let diff = repo_manager.file_diff(&file, &rev, repo.as_deref())?;
+            if diff.is_empty() {
+                println!("'{}' is unchanged since {}", file, rev);
+            } else {
+                print!("{}", diff);
+            }
+        }
+        Commands::RestoreVersion { file, rev, repo } => {
+            repo_manager.restore_version(&file, &rev, repo.as_deref())?;
+        }
+        Commands::Profile { action } => {
+            match action {
+                ProfileAction::Show { repo } => {
+                    println!("{}", repo_manager.profile_report(repo.as_deref())?);
+                }
+                ProfileAction::Set { name, repo, hosts, tags, os, files } => {
+                    let repo_name = repo.unwrap_or_else(|| "gitbox-default".to_string());
+                    repo_manager.set_profile(&name, &repo_name, Profile { hosts, tags, os, files })?;
+                }
+            }
+        }
+        Commands::Backups { action } => {
+            match action {
+                BackupsAction::List => {
+                    let entries = backup::list()?;
+                    if entries.is_empty() {
+                        println!("No backups");
+                    }
+                    for entry in entries {
+                        let time = entry.created_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S");
+                        println!("{:>4}  {}  {}  ({})", entry.id, time, entry.original.display(), entry.reason);
+                    }
+                }
+                BackupsAction::Restore { id } => {
+                    backup::restore(id, cli.dry_run)?;
+                }
+                BackupsAction::Prune { older_than } => {
+                    let pruned = backup::prune(chrono::Duration::days(older_than), cli.dry_run)?;
+                    done(format!("Deleted {} backup(s) older than {} days", pruned, older_than));
+                }
+            }
+        }
         Commands::Repo { get, action } => {
             match action {
                 RepoAction::List => {
@@ -213,9 +651,9 @@ async fn main() -> Result<()> {
                     let info = repo_manager.get_repo_info(&get)?;
                     println!("{}", info);
                 }
-                RepoAction::Sync => {
+                RepoAction::Sync { .. } => {
                     repo_manager.sync_repo(&get)?;
-                    println!("Repository '{}' synced with GitHub", get);
+                    done(format!("Repository '{}' synced with its remote", get));
                 }
             }
         }
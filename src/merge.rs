@@ -0,0 +1,491 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use git2::build::CheckoutBuilder;
+use git2::{Commit, FileMode, Index, IndexEntry, IndexTime, Oid, Repository, Tree};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backup::Backup;
+use crate::commit;
+use crate::output::outln;
+use crate::sync::merge_metadata;
+
+/// File inside `.git/` recording a merge that is waiting for conflicts to be resolved.
+const PENDING_MERGE_FILE: &str = "gitbox-merge.json";
+
+#[derive(Debug)]
+pub enum MergeOutcome {
+    /// The remote had nothing new
+    UpToDate,
+    /// Local had nothing new; moved to the remote commit
+    FastForward,
+    /// Both sides changed and the changes merged cleanly
+    Merged,
+    /// Both sides changed the same files; nothing was written, see [`PendingMerge`]
+    Conflicted(Vec<String>),
+}
+
+/// How to settle a conflicted file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ResolveChoice {
+    /// Keep the local file as it is now
+    Ours,
+    /// Take the remote version
+    Theirs,
+    /// Edit the file with conflict markers in $EDITOR
+    Edit,
+    /// Run the merge tool from `merge_tool` in config.toml
+    Tool,
+}
+
+/// A merge with the remote that stopped on conflicts. The working tree (and so every
+/// original file) is left untouched until all conflicts have a resolution.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingMerge {
+    pub ours: String,
+    pub theirs: String,
+    pub conflicts: Vec<String>,
+    /// Blob chosen for each resolved path; `None` means the path is deleted
+    #[serde(default)]
+    pub resolved: BTreeMap<String, Option<String>>,
+}
+
+/// The three versions of a conflicted file, plus a merge of them with conflict markers.
+pub struct ConflictSides {
+    pub base: Option<Vec<u8>>,
+    pub ours: Option<Vec<u8>>,
+    pub theirs: Option<Vec<u8>>,
+    pub merged: Vec<u8>,
+}
+
+impl PendingMerge {
+    pub fn load(git_repo: &Repository) -> Result<Option<Self>> {
+        let path = Self::path(git_repo);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read pending merge: {:?}", path))?;
+        let pending = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse pending merge: {:?}", path))?;
+        Ok(Some(pending))
+    }
+
+    fn save(&self, git_repo: &Repository) -> Result<()> {
+        let path = Self::path(git_repo);
+        let content = serde_json::to_string_pretty(self)
+            .context("Failed to serialize pending merge")?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write pending merge: {:?}", path))?;
+        Ok(())
+    }
+
+    /// Forgets the pending merge. Nothing was written to the working tree, so this
+    /// leaves every file exactly as it is locally.
+    pub fn discard(git_repo: &Repository) -> Result<()> {
+        let path = Self::path(git_repo);
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove pending merge: {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    pub fn unresolved(&self) -> Vec<&str> {
+        self.conflicts.iter()
+            .filter(|path| !self.resolved.contains_key(*path))
+            .map(|path| path.as_str())
+            .collect()
+    }
+
+    fn path(git_repo: &Repository) -> PathBuf {
+        git_repo.path().join(PENDING_MERGE_FILE)
+    }
+
+    fn commits<'repo>(&self, git_repo: &'repo Repository) -> Result<(Commit<'repo>, Commit<'repo>)> {
+        let find = |id: &str| -> Result<Commit<'repo>> {
+            let oid = Oid::from_str(id).with_context(|| format!("Invalid commit id in pending merge: {}", id))?;
+            git_repo.find_commit(oid).with_context(|| format!("Commit {} of the pending merge is missing", id))
+        };
+        Ok((find(&self.ours)?, find(&self.theirs)?))
+    }
+}
+
+/// Merges `upstream` into HEAD without ever leaving conflict markers in tracked files.
+///
+/// Changed files are written in place so hard links to the originals survive. If any
+/// file conflicts, nothing is written and the merge is saved as a [`PendingMerge`].
+/// Conflicts in `.gitbox` are resolved automatically by merging the entries.
+pub fn merge_upstream(git_repo: &Repository, upstream: Oid, message: &str) -> Result<MergeOutcome> {
+    if PendingMerge::load(git_repo)?.is_some() {
+        return Err(anyhow::anyhow!("A previous merge still has unresolved conflicts"));
+    }
+
+    let theirs = git_repo.find_commit(upstream).context("Failed to find remote commit")?;
+    let theirs_tree = theirs.tree().context("Failed to read remote tree")?;
+    let Some(ours) = git_repo.head().ok().and_then(|head| head.peel_to_commit().ok()) else {
+        write_through(git_repo, None, &theirs_tree, &[])?;
+        move_head(git_repo, theirs.id(), "gitbox: fast-forward")?;
+        return Ok(MergeOutcome::FastForward);
+    };
+
+    if ours.id() == theirs.id() || git_repo.graph_descendant_of(ours.id(), theirs.id())? {
+        return Ok(MergeOutcome::UpToDate);
+    }
+    if git_repo.graph_descendant_of(theirs.id(), ours.id())? {
+        write_through(git_repo, Some(&ours.tree()?), &theirs_tree, &[])?;
+        move_head(git_repo, theirs.id(), "gitbox: fast-forward")?;
+        return Ok(MergeOutcome::FastForward);
+    }
+
+    let mut index = git_repo.merge_commits(&ours, &theirs, None)
+        .context("Failed to merge remote changes")?;
+    resolve_metadata_conflict(git_repo, &mut index)?;
+
+    if index.has_conflicts() {
+        let conflicts = conflicted_paths(&index)?;
+        let pending = PendingMerge {
+            ours: ours.id().to_string(),
+            theirs: theirs.id().to_string(),
+            conflicts: conflicts.clone(),
+            resolved: BTreeMap::new(),
+        };
+        pending.save(git_repo)?;
+        return Ok(MergeOutcome::Conflicted(conflicts));
+    }
+
+    commit_merge(git_repo, &mut index, &ours, &theirs, message, &[])?;
+    Ok(MergeOutcome::Merged)
+}
+
+/// Reads the versions of a conflicted path from a pending merge.
+pub fn conflict_sides(git_repo: &Repository, pending: &PendingMerge, path: &str) -> Result<ConflictSides> {
+    let (ours, theirs) = pending.commits(git_repo)?;
+    let mut index = git_repo.merge_commits(&ours, &theirs, None)
+        .context("Failed to merge remote changes")?;
+
+    let blob = |entry: Option<IndexEntry>| -> Result<Option<Vec<u8>>> {
+        entry.map(|entry| Ok(git_repo.find_blob(entry.id)?.content().to_vec())).transpose()
+    };
+    let conflict = index.conflicts()?
+        .filter_map(|conflict| conflict.ok())
+        .find(|conflict| conflict_path(conflict.our.as_ref().or(conflict.their.as_ref())).as_deref() == Some(path))
+        .with_context(|| format!("{} is not conflicted", path))?;
+    let base = blob(conflict.ancestor)?;
+    let ours = blob(conflict.our)?;
+    let theirs = blob(conflict.their)?;
+
+    let merged = marked_content(git_repo, &mut index, path)
+        .unwrap_or_else(|_| ours.clone().or_else(|| theirs.clone()).unwrap_or_default());
+
+    Ok(ConflictSides { base, ours, theirs, merged })
+}
+
+/// Records the content a conflicted path should have after the merge (`None` deletes it).
+pub fn record_resolution(git_repo: &Repository, pending: &mut PendingMerge, path: &str, content: Option<&[u8]>) -> Result<()> {
+    if !pending.conflicts.iter().any(|conflict| conflict == path) {
+        return Err(anyhow::anyhow!("{} is not conflicted", path));
+    }
+    let blob = content
+        .map(|content| git_repo.blob(content).context("Failed to store resolved content"))
+        .transpose()?;
+    pending.resolved.insert(path.to_string(), blob.map(|id| id.to_string()));
+    pending.save(git_repo)
+}
+
+/// Creates the merge commit once every conflict has a resolution and writes the
+/// merged result through to the working tree.
+pub fn finish_merge(git_repo: &Repository, pending: &PendingMerge, message: &str) -> Result<()> {
+    let unresolved = pending.unresolved();
+    if !unresolved.is_empty() {
+        return Err(anyhow::anyhow!("Unresolved conflicts remain: {}", unresolved.join(", ")));
+    }
+
+    let (ours, theirs) = pending.commits(git_repo)?;
+    let head = git_repo.head().ok().and_then(|head| head.target());
+    if head != Some(ours.id()) {
+        return Err(anyhow::anyhow!(
+            "HEAD moved since the merge started; abort it with 'gitbox resolve --abort' and sync again"
+        ));
+    }
+
+    let mut index = git_repo.merge_commits(&ours, &theirs, None)
+        .context("Failed to merge remote changes")?;
+    resolve_metadata_conflict(git_repo, &mut index)?;
+    for (path, blob) in &pending.resolved {
+        let mode = conflict_mode(&index, path)?;
+        let blob = blob.as_deref().map(Oid::from_str).transpose()
+            .with_context(|| format!("Invalid resolution recorded for {}", path))?;
+        stage_resolution(&mut index, path, blob.map(|id| (id, mode)))?;
+    }
+
+    let resolved: Vec<&str> = pending.resolved.keys().map(|path| path.as_str()).collect();
+    commit_merge(git_repo, &mut index, &ours, &theirs, message, &resolved)?;
+    PendingMerge::discard(git_repo)
+}
+
+fn commit_merge(
+    git_repo: &Repository,
+    index: &mut Index,
+    ours: &Commit,
+    theirs: &Commit,
+    message: &str,
+    overwrite: &[&str],
+) -> Result<()> {
+    if index.has_conflicts() {
+        return Err(anyhow::anyhow!("Merge still has conflicts"));
+    }
+    let tree_id = index.write_tree_to(git_repo).context("Failed to write merged tree")?;
+    let tree = git_repo.find_tree(tree_id).context("Failed to find merged tree")?;
+
+    write_through(git_repo, Some(&ours.tree()?), &tree, overwrite)?;
+    commit::create(git_repo, message, &tree, &[ours, theirs])
+        .context("Failed to create merge commit")?;
+    Ok(())
+}
+
+/// Updates the working tree from `from` to `to`, writing changed files in place so
+/// existing hard links keep pointing at the new content, then resets the index to `to`.
+///
+/// Refuses (before writing anything) if a file about to change has local content that
+/// matches neither version, unless its path is listed in `overwrite`.
+fn write_through(git_repo: &Repository, from: Option<&Tree>, to: &Tree, overwrite: &[&str]) -> Result<()> {
+    let workdir = git_repo.workdir().context("Repository has no working directory")?;
+    let diff = git_repo.diff_tree_to_tree(from, Some(to), None)
+        .context("Failed to compare trees")?;
+
+    let mut changes = Vec::new();
+    let mut blocked = Vec::new();
+    for delta in diff.deltas() {
+        let (old, new) = (delta.old_file(), delta.new_file());
+        let relative = new.path().or(old.path()).context("Change without a path")?.to_path_buf();
+        let full_path = workdir.join(&relative);
+
+        let expected = |file: &git2::DiffFile| -> Result<Option<(Vec<u8>, bool)>> {
+            if file.id().is_zero() {
+                return Ok(None);
+            }
+            let content = git_repo.find_blob(file.id())?.content().to_vec();
+            Ok(Some((content, file.mode() == FileMode::Link)))
+        };
+        let before = expected(&old)?;
+        let after = expected(&new)?;
+        let on_disk = disk_content(&full_path)?;
+
+        if on_disk == after {
+            continue;
+        }
+        let local_edit = on_disk.is_some() && on_disk != before;
+        let overwritten = overwrite.contains(&relative.to_string_lossy().as_ref());
+        if local_edit && !overwritten {
+            blocked.push(relative.to_string_lossy().to_string());
+        }
+        // Resolutions replace the local side of a conflict, which deserves a copy too
+        let keep_copy = local_edit || (overwritten && on_disk.is_some());
+        changes.push((full_path, after, new.mode(), keep_copy));
+    }
+
+    if !blocked.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Local changes to these files would be overwritten by remote changes:\n  {}\nCommit them first (e.g. 'gitbox sync-push').",
+            blocked.join("\n  ")
+        ));
+    }
+
+    for (path, content, mode, keep_copy) in changes {
+        if keep_copy {
+            let saved = Backup::current().save(&path, "overwritten by a merge")?;
+            outln!("Backed up {:?} to {:?}", path, saved);
+        }
+        match content {
+            Some((content, true)) => {
+                remove_path(&path)?;
+                create_parent(&path)?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::ffi::OsStrExt;
+                    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(&content), &path)
+                        .with_context(|| format!("Failed to create symlink: {:?}", path))?;
+                }
+            }
+            Some((content, false)) => {
+                if fs::symlink_metadata(&path).map(|meta| meta.is_dir() || meta.file_type().is_symlink()).unwrap_or(false) {
+                    remove_path(&path)?;
+                }
+                create_parent(&path)?;
+                // Truncate and rewrite rather than replace, keeping the inode (and hard links)
+                fs::write(&path, &content)
+                    .with_context(|| format!("Failed to write file: {:?}", path))?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    let executable = mode == FileMode::BlobExecutable;
+                    let mut permissions = fs::metadata(&path)?.permissions();
+                    let bits = if executable { permissions.mode() | 0o111 } else { permissions.mode() & !0o111 };
+                    permissions.set_mode(bits);
+                    fs::set_permissions(&path, permissions)
+                        .with_context(|| format!("Failed to set permissions: {:?}", path))?;
+                }
+            }
+            None => {
+                remove_path(&path)?;
+                prune_empty_parents(&path, workdir);
+            }
+        }
+    }
+
+    let mut index = git_repo.index().context("Failed to get git index")?;
+    index.read_tree(to).context("Failed to update git index")?;
+    index.write().context("Failed to write git index")?;
+    Ok(())
+}
+
+/// Content at `path` as git would store it: the bytes of a file or the target of a
+/// symlink (flagged `true`). `None` if nothing is there.
+fn disk_content(path: &Path) -> Result<Option<(Vec<u8>, bool)>> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path).with_context(|| format!("Failed to read symlink: {:?}", path))?;
+        return Ok(Some((target.to_string_lossy().as_bytes().to_vec(), true)));
+    }
+    if metadata.is_dir() {
+        // Never equal to a blob, so a directory in the way counts as a local change
+        return Ok(Some((Vec::new(), true)));
+    }
+    let content = fs::read(path).with_context(|| format!("Failed to read file: {:?}", path))?;
+    Ok(Some((content, false)))
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path)
+            .with_context(|| format!("Failed to remove directory: {:?}", path)),
+        Ok(_) => fs::remove_file(path)
+            .with_context(|| format!("Failed to remove file: {:?}", path)),
+        Err(_) => Ok(()),
+    }
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    Ok(())
+}
+
+fn prune_empty_parents(path: &Path, workdir: &Path) {
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if !dir.starts_with(workdir) || dir == workdir || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
+
+/// Points the current branch (or a detached HEAD) at `id`.
+fn move_head(git_repo: &Repository, id: Oid, message: &str) -> Result<()> {
+    let head = git_repo.find_reference("HEAD").context("Failed to read HEAD")?;
+    let target = head.symbolic_target().unwrap_or("HEAD").to_string();
+    git_repo.reference(&target, id, true, message)
+        .with_context(|| format!("Failed to update {}", target))?;
+    Ok(())
+}
+
+/// Both machines usually add entries to `.gitbox`; merge them instead of reporting a conflict.
+fn resolve_metadata_conflict(git_repo: &Repository, index: &mut Index) -> Result<()> {
+    let conflict = index.conflicts()?
+        .filter_map(|conflict| conflict.ok())
+        .find(|conflict| conflict_path(conflict.our.as_ref().or(conflict.their.as_ref())).as_deref() == Some(".gitbox"));
+    let Some(conflict) = conflict else {
+        return Ok(());
+    };
+    let (Some(ours), Some(theirs)) = (&conflict.our, &conflict.their) else {
+        return Ok(());
+    };
+
+    let base = conflict.ancestor.as_ref()
+        .map(|entry| git_repo.find_blob(entry.id).map(|blob| blob.content().to_vec()))
+        .transpose()?;
+    let merged = merge_metadata(
+        base.as_deref(),
+        git_repo.find_blob(ours.id)?.content(),
+        git_repo.find_blob(theirs.id)?.content(),
+    )?;
+    let blob = git_repo.blob(&merged).context("Failed to store merged metadata")?;
+    stage_resolution(index, ".gitbox", Some((blob, ours.mode)))
+}
+
+fn stage_resolution(index: &mut Index, path: &str, blob: Option<(Oid, u32)>) -> Result<()> {
+    index.remove_path(Path::new(path))
+        .with_context(|| format!("Failed to clear conflict for {}", path))?;
+    if let Some((id, mode)) = blob {
+        let entry = IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id,
+            flags: path.len().min(0xfff) as u16,
+            flags_extended: 0,
+            path: path.as_bytes().to_vec(),
+        };
+        index.add(&entry).with_context(|| format!("Failed to stage resolution for {}", path))?;
+    }
+    Ok(())
+}
+
+fn conflict_mode(index: &Index, path: &str) -> Result<u32> {
+    let conflict = index.conflicts()?
+        .filter_map(|conflict| conflict.ok())
+        .find(|conflict| conflict_path(conflict.our.as_ref().or(conflict.their.as_ref())).as_deref() == Some(path))
+        .with_context(|| format!("{} is no longer conflicted", path))?;
+    let entry = conflict.our.or(conflict.their).context("Conflict without versions")?;
+    Ok(entry.mode)
+}
+
+fn conflicted_paths(index: &Index) -> Result<Vec<String>> {
+    let mut paths: Vec<String> = index.conflicts()?
+        .filter_map(|conflict| conflict.ok())
+        .filter_map(|conflict| conflict_path(conflict.our.as_ref().or(conflict.their.as_ref()).or(conflict.ancestor.as_ref())))
+        .collect();
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+fn conflict_path(entry: Option<&IndexEntry>) -> Option<String> {
+    entry.map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+}
+
+/// Produces the conflicted file with `<<<<<<<`/`>>>>>>>` markers by checking the merge
+/// index out into a scratch directory, so the working tree is never touched.
+fn marked_content(git_repo: &Repository, index: &mut Index, path: &str) -> Result<Vec<u8>> {
+    let scratch = std::env::temp_dir().join(format!("gitbox-merge-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&scratch)
+        .with_context(|| format!("Failed to create directory: {:?}", scratch))?;
+
+    let mut checkout = CheckoutBuilder::new();
+    checkout.target_dir(&scratch)
+        .path(path)
+        .force()
+        .allow_conflicts(true)
+        .conflict_style_merge(true)
+        .our_label("local")
+        .their_label("remote");
+    let result = git_repo.checkout_index(Some(index), Some(&mut checkout))
+        .context("Failed to produce merged file")
+        .and_then(|()| fs::read(scratch.join(path)).context("Failed to read merged file"));
+
+    let _ = fs::remove_dir_all(&scratch);
+    result
+}
@@ -0,0 +1,79 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+thread_local! {
+    /// Repository whose sync this thread is running, when several sync at once
+    static PREFIX: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Cleared by [`disable_prompts`]
+static PROMPTS: AtomicBool = AtomicBool::new(true);
+
+/// Held for the whole of a question and its answer.
+static PROMPT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Prints a message like `println!`, with every line prefixed by the repository this
+/// thread is syncing (see [`with_prefix`]).
+macro_rules! outln {
+    () => { $crate::output::line(false, format_args!("")) };
+    ($($arg:tt)*) => { $crate::output::line(false, format_args!($($arg)*)) };
+}
+
+/// Like [`outln`], on stderr.
+macro_rules! errln {
+    ($($arg:tt)*) => { $crate::output::line(true, format_args!($($arg)*)) };
+}
+
+pub(crate) use {errln, outln};
+
+/// Runs `f` with each line it prints through `outln!` and `errln!` prefixed by
+/// `[name]`, so the output of repositories syncing side by side stays attributable.
+pub fn with_prefix<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            PREFIX.with(|prefix| prefix.take());
+        }
+    }
+
+    PREFIX.with(|prefix| prefix.replace(Some(name.to_string())));
+    let _reset = Reset;
+    f()
+}
+
+pub fn line(to_stderr: bool, message: fmt::Arguments) {
+    let text = PREFIX.with(|prefix| match prefix.borrow().as_deref() {
+        Some(name) => message.to_string()
+            .split('\n')
+            .map(|line| if line.is_empty() { format!("[{}]", name) } else { format!("[{}] {}", name, line) })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => message.to_string(),
+    });
+    // One call per message, so its lines are never split by another thread's
+    if to_stderr {
+        eprintln!("{}", text);
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// Stops asking questions, for when several repositories sync at once: each would wait
+/// on the same terminal. Questions get the answer they get without a terminal.
+pub fn disable_prompts() {
+    PROMPTS.store(false, Ordering::Relaxed);
+}
+
+/// Whether the user can be asked a question.
+pub fn can_prompt() -> bool {
+    PROMPTS.load(Ordering::Relaxed) && std::io::stdin().is_terminal()
+}
+
+/// Keeps other threads from asking anything until the guard is dropped, so one
+/// question and its answer are never mixed up with another.
+pub fn prompt_lock() -> MutexGuard<'static, ()> {
+    PROMPT_LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
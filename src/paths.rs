@@ -0,0 +1,200 @@
+use dirs::home_dir;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::Config;
+
+static CURRENT: OnceLock<PathMapper> = OnceLock::new();
+
+/// Translates between machine-specific absolute paths and portable paths that start
+/// with a named root token such as `$HOME/.bashrc` or `$XDG_CONFIG_HOME/nvim`.
+///
+/// Built-in roots are `HOME`, `XDG_CONFIG_HOME` and `GITBOX_REPOS`; additional roots
+/// come from the `[roots]` table in `config.toml`.
+#[derive(Debug, Clone)]
+pub struct PathMapper {
+    roots: Vec<(String, PathBuf)>,
+}
+
+impl PathMapper {
+    pub fn from_config(config: &Config) -> Self {
+        let mut roots = BTreeMap::new();
+
+        if let Some(home) = home_dir() {
+            let xdg_config = std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|path| path.is_absolute())
+                .unwrap_or_else(|| home.join(".config"));
+            roots.insert("XDG_CONFIG_HOME".to_string(), xdg_config);
+            roots.insert("HOME".to_string(), home);
+        }
+        roots.insert("GITBOX_REPOS".to_string(), config.repos_dir.clone());
+
+        for (name, path) in &config.roots {
+            roots.insert(name.clone(), path.clone());
+        }
+
+        // Canonicalize where possible so canonicalized synced paths still match
+        let roots = roots.into_iter()
+            .map(|(name, path)| {
+                let path = path.canonicalize().unwrap_or(path);
+                (name, path)
+            })
+            .collect();
+
+        Self { roots }
+    }
+
+    /// Makes `mapper` the process-wide mapper used when loading and saving metadata.
+    pub fn install(mapper: PathMapper) {
+        let _ = CURRENT.set(mapper);
+    }
+
+    /// Returns the installed mapper, or one built from the default config.
+    pub fn current() -> &'static PathMapper {
+        CURRENT.get_or_init(|| PathMapper::from_config(&Config::default()))
+    }
+
+    /// Rewrites `path` relative to the most specific matching root; of roots at the same
+    /// location, the one whose name sorts first. Paths outside every root are returned
+    /// unchanged.
+    pub fn contract(&self, path: &Path) -> PathBuf {
+        let best = self.roots.iter()
+            .filter_map(|(name, root)| path.strip_prefix(root).ok().map(|rest| (name, root, rest)))
+            .max_by_key(|(name, root, _)| (root.components().count(), Reverse(*name)));
+
+        match best {
+            Some((name, _, rest)) if rest.as_os_str().is_empty() => PathBuf::from(format!("${}", name)),
+            Some((name, _, rest)) => Path::new(&format!("${}", name)).join(rest),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Expands a leading `$NAME` token to this machine's location for that root.
+    /// Unknown tokens are left in place so the entry shows up as missing rather than
+    /// pointing somewhere unexpected.
+    pub fn expand(&self, path: &Path) -> PathBuf {
+        let mut components = path.components();
+        let token = match components.next() {
+            Some(Component::Normal(first)) => first.to_string_lossy(),
+            _ => return path.to_path_buf(),
+        };
+
+        match token.strip_prefix('$').and_then(|name| self.root(name)) {
+            Some(root) if components.as_path().as_os_str().is_empty() => root.to_path_buf(),
+            Some(root) => root.join(components.as_path()),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Like [`PathMapper::contract`], but also maps home directories of other machines
+    /// (`/home/<user>`, `/Users/<user>`, `/root`) to `$HOME`. Used when migrating
+    /// metadata that was written with absolute paths.
+    pub fn contract_foreign(&self, path: &Path) -> PathBuf {
+        let contracted = self.contract(path);
+        if !contracted.is_absolute() {
+            return contracted;
+        }
+
+        let parts: Vec<Component> = path.components().collect();
+        let rest_index = match parts.as_slice() {
+            [Component::RootDir, Component::Normal(dir), Component::Normal(_), ..]
+                if *dir == "home" || *dir == "Users" => 3,
+            [Component::RootDir, Component::Normal(dir), ..] if *dir == "root" => 2,
+            _ => return contracted,
+        };
+
+        let rest: PathBuf = parts[rest_index..].iter().collect();
+        if rest.as_os_str().is_empty() {
+            PathBuf::from("$HOME")
+        } else {
+            Path::new("$HOME").join(rest)
+        }
+    }
+
+    fn root(&self, name: &str) -> Option<&Path> {
+        self.roots.iter()
+            .find(|(root_name, _)| root_name == name)
+            .map(|(_, path)| path.as_path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::GitboxMetadata;
+
+    fn mapper(roots: &[(&str, &str)]) -> PathMapper {
+        PathMapper {
+            roots: roots.iter().map(|(name, path)| (name.to_string(), PathBuf::from(path))).collect(),
+        }
+    }
+
+    #[test]
+    fn contract_uses_the_most_specific_root() {
+        let mapper = mapper(&[("HOME", "/home/me"), ("XDG_CONFIG_HOME", "/home/me/.config")]);
+        assert_eq!(mapper.contract(Path::new("/home/me/.bashrc")), Path::new("$HOME/.bashrc"));
+        assert_eq!(mapper.contract(Path::new("/home/me/.config/nvim")), Path::new("$XDG_CONFIG_HOME/nvim"));
+        assert_eq!(mapper.contract(Path::new("/home/me")), Path::new("$HOME"));
+        assert_eq!(mapper.contract(Path::new("/etc/hosts")), Path::new("/etc/hosts"));
+    }
+
+    #[test]
+    fn roots_at_the_same_location_contract_to_the_first_name() {
+        let forward = mapper(&[("WORK", "/srv/work"), ("PROJECTS", "/srv/work")]);
+        let backward = mapper(&[("PROJECTS", "/srv/work"), ("WORK", "/srv/work")]);
+        for mapper in [forward, backward] {
+            assert_eq!(mapper.contract(Path::new("/srv/work/app")), Path::new("$PROJECTS/app"));
+        }
+    }
+
+    #[test]
+    fn expand_reverses_contract_and_keeps_unknown_tokens() {
+        let mapper = mapper(&[("HOME", "/home/me"), ("XDG_CONFIG_HOME", "/home/me/.config")]);
+        assert_eq!(mapper.expand(Path::new("$XDG_CONFIG_HOME/nvim")), Path::new("/home/me/.config/nvim"));
+        assert_eq!(mapper.expand(Path::new("$HOME")), Path::new("/home/me"));
+        assert_eq!(mapper.expand(Path::new("$MUSIC/song.mp3")), Path::new("$MUSIC/song.mp3"));
+        assert_eq!(mapper.expand(Path::new("/etc/hosts")), Path::new("/etc/hosts"));
+    }
+
+    #[test]
+    fn contract_foreign_maps_other_machines_homes() {
+        let mapper = mapper(&[("HOME", "/home/me")]);
+        assert_eq!(mapper.contract_foreign(Path::new("/home/alice/.vimrc")), Path::new("$HOME/.vimrc"));
+        assert_eq!(mapper.contract_foreign(Path::new("/Users/alice/.zshrc")), Path::new("$HOME/.zshrc"));
+        assert_eq!(mapper.contract_foreign(Path::new("/root/.profile")), Path::new("$HOME/.profile"));
+        assert_eq!(mapper.contract_foreign(Path::new("/home/alice")), Path::new("$HOME"));
+        assert_eq!(mapper.contract_foreign(Path::new("/home/me/.bashrc")), Path::new("$HOME/.bashrc"));
+        assert_eq!(mapper.contract_foreign(Path::new("/etc/hosts")), Path::new("/etc/hosts"));
+    }
+
+    #[test]
+    fn legacy_metadata_from_another_machine_migrates_to_this_home() {
+        let legacy = r#"{
+            "files": {
+                "/home/alice/.bashrc": {
+                    "id": "1",
+                    "original_path": "/home/alice/.bashrc",
+                    "synced_path": "/home/alice/.gitbox/repos/dots/files/.bashrc",
+                    "is_directory": false
+                }
+            },
+            "repo_name": "dots"
+        }"#;
+        let metadata: GitboxMetadata = serde_json::from_str(legacy).unwrap();
+        assert!(metadata.needs_migration());
+
+        let mapper = mapper(&[("HOME", "/home/me"), ("GITBOX_REPOS", "/home/me/.gitbox/repos")]);
+        let metadata = metadata.map_paths(|path| mapper.expand(&mapper.contract_foreign(path)));
+        let info = metadata.files.values().next().unwrap();
+        assert_eq!(info.original_path, Path::new("/home/me/.bashrc"));
+        assert_eq!(info.synced_path, Path::new("/home/me/.gitbox/repos/dots/files/.bashrc"));
+
+        let portable = metadata.to_portable(|path| mapper.contract(path));
+        let info = portable.files.values().next().unwrap();
+        assert_eq!(info.original_path, Path::new("$HOME/.bashrc"));
+        assert_eq!(info.synced_path, Path::new("$GITBOX_REPOS/dots/files/.bashrc"));
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use globset::GlobSet;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::sync::{FileInfo, GitboxMetadata, glob_set};
+use crate::template;
+
+/// A named set of entries and the machines they apply to. A machine matching any of
+/// `hosts`, `tags` or `os` uses the profile.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+    /// Patterns of entries, relative to the repository's `files/` directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+}
+
+impl Profile {
+    /// Whether this machine's hostname, tags or OS match the profile.
+    pub fn is_active(&self) -> bool {
+        let machine = template::machine();
+        self.hosts.contains(&machine.host)
+            || self.os.contains(&machine.os)
+            || self.tags.iter().any(|tag| machine.tags.contains(tag))
+    }
+
+    /// Adds the values not in the profile yet.
+    pub fn extend(&mut self, other: Profile) {
+        for (values, added) in [
+            (&mut self.hosts, other.hosts),
+            (&mut self.tags, other.tags),
+            (&mut self.os, other.os),
+            (&mut self.files, other.files),
+        ] {
+            for value in added {
+                if !values.contains(&value) {
+                    values.push(value);
+                }
+            }
+        }
+    }
+}
+
+/// Which entries of one repository apply to this machine. Entries no profile names
+/// apply everywhere; the others only where one of their profiles is active.
+pub struct Selection {
+    files_dir: PathBuf,
+    profiles: Vec<(String, GlobSet, bool)>,
+}
+
+impl Selection {
+    pub fn new(metadata: &GitboxMetadata, repo_path: &Path) -> Result<Self> {
+        let profiles = metadata.profiles.iter()
+            .map(|(name, profile)| Ok((name.clone(), glob_set(&profile.files)?, profile.is_active())))
+            .collect::<Result<_>>()?;
+        Ok(Self { files_dir: repo_path.join("files"), profiles })
+    }
+
+    pub fn includes(&self, info: &FileInfo) -> bool {
+        let relative = info.synced_path.strip_prefix(&self.files_dir).unwrap_or(&info.synced_path);
+        self.applies(relative)
+    }
+
+    /// Whether the item at `relative` under `files/` applies to this machine. A profile
+    /// selecting a directory selects everything in it.
+    pub fn applies(&self, relative: &Path) -> bool {
+        let mut named = false;
+        for (_, files, active) in &self.profiles {
+            if selects(files, relative) {
+                if *active {
+                    return true;
+                }
+                named = true;
+            }
+        }
+        !named
+    }
+
+    /// Names of the profiles that select the item at `relative` under `files/`.
+    pub fn profiles_of(&self, relative: &Path) -> Vec<&str> {
+        self.profiles.iter()
+            .filter(|(_, files, _)| selects(files, relative))
+            .map(|(name, _, _)| name.as_str())
+            .collect()
+    }
+
+    /// Why an entry that does not apply here is left alone.
+    pub fn reason(&self, info: &FileInfo) -> String {
+        let relative = info.synced_path.strip_prefix(&self.files_dir).unwrap_or(&info.synced_path);
+        format!("only for machines using profile {}", self.profiles_of(relative).join(", "))
+    }
+}
+
+fn selects(files: &GlobSet, relative: &Path) -> bool {
+    relative.ancestors()
+        .filter(|path| !path.as_os_str().is_empty())
+        .any(|path| files.is_match(path))
+}
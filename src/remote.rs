@@ -0,0 +1,259 @@
+use anyhow::{Context, Result};
+use git2::{Repository, RepositoryInitOptions};
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, RemoteConfig, RemoteKind};
+use crate::git::{self, Auth};
+use crate::gitea::GiteaClient;
+use crate::github::{self, GitHubClient};
+use crate::gitlab::GitLabClient;
+
+/// Somewhere gitbox can create the remote repositories it syncs with.
+pub trait RemoteProvider {
+    /// Creates the private repository `name`, which must not exist yet.
+    fn create_repo(&self, name: &str) -> Result<()>;
+    /// Whether the repository `name` exists.
+    fn repo_exists(&self, name: &str) -> Result<bool>;
+    /// The URL to add as the repository's git remote.
+    fn clone_url(&self, name: &str) -> Result<String>;
+    /// The account gitbox acts as on the remote.
+    fn whoami(&self) -> Result<String>;
+    /// Repositories gitbox manages there: those with a `.gitbox` file at the top.
+    fn list_repos(&self) -> Result<Vec<String>>;
+    /// A command that deletes the repository `name` there, if the provider has one.
+    fn delete_command(&self, _name: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Number of items requested per page from paginated APIs.
+pub const PAGE_SIZE: usize = 100;
+
+/// The provider for `repo_name`: the one recorded for it in `repo_remotes`, else
+/// the default provider.
+pub fn provider_for(config: &Config, repo_name: &str) -> Result<Box<dyn RemoteProvider>> {
+    match config.repo_remotes.get(repo_name) {
+        Some(name) => named_provider(config, name),
+        None => default_provider(config),
+    }
+}
+
+/// `default_remote` if set, else `local_remote`, else GitHub.
+pub fn default_provider(config: &Config) -> Result<Box<dyn RemoteProvider>> {
+    if let Some(name) = &config.default_remote {
+        return named_provider(config, name);
+    }
+    match &config.local_remote {
+        Some(dir) => {
+            let url = dir.to_string_lossy();
+            Ok(Box::new(GitRemote::new(&url, &config.default_branch, auth(config))))
+        }
+        None => Ok(Box::new(GitHubClient::new(config.github_token(), config.github_api_url.as_deref(), None)?)),
+    }
+}
+
+/// The provider called `name` in the `[remotes]` table.
+pub fn named_provider(config: &Config, name: &str) -> Result<Box<dyn RemoteProvider>> {
+    let remote = match config.remotes.get(name) {
+        Some(remote) => remote.clone(),
+        None if name == "github" => RemoteConfig { kind: RemoteKind::Github, url: None, token: None, owner: None },
+        None => {
+            return Err(anyhow::anyhow!(
+                "Unknown remote '{}'. Define it under [remotes.{}] in ~/.gitbox/config.toml",
+                name,
+                name
+            ));
+        }
+    };
+
+    match remote.kind {
+        RemoteKind::Github => {
+            let token = remote.token.clone().or_else(|| config.github_token());
+            let api_url = remote.url.as_deref().or(config.github_api_url.as_deref());
+            Ok(Box::new(GitHubClient::new(token, api_url, remote.owner.clone())?))
+        }
+        RemoteKind::Gitlab => Ok(Box::new(GitLabClient::new(&remote)?)),
+        RemoteKind::Gitea => Ok(Box::new(GiteaClient::new(&remote)?)),
+        RemoteKind::Git => {
+            let url = remote.url.as_deref()
+                .with_context(|| format!("Remote '{}' needs a `url` to keep repositories under", name))?;
+            Ok(Box::new(GitRemote::new(url, &config.default_branch, auth(config))))
+        }
+    }
+}
+
+/// Credentials for git itself: the GitHub token for the host of `github_api_url`, and
+/// each GitHub remote's token for the host of its API URL.
+pub fn auth(config: &Config) -> Auth {
+    let default_token = config.github_token();
+    let mut github_tokens = Vec::new();
+    let mut add = |api_url: Option<&str>, token: Option<String>| {
+        let host = github::git_host(api_url.unwrap_or(github::DEFAULT_API_URL));
+        if let Some(token) = token {
+            if !github_tokens.iter().any(|(known, _)| *known == host) {
+                github_tokens.push((host, token));
+            }
+        }
+    };
+    // Remotes first, so their own tokens win over the default for the same host
+    let mut names: Vec<&String> = config.remotes.keys().collect();
+    names.sort();
+    for name in names {
+        let remote = &config.remotes[name];
+        if remote.kind == RemoteKind::Github {
+            let api_url = remote.url.as_deref().or(config.github_api_url.as_deref());
+            add(api_url, remote.token.clone().or_else(|| default_token.clone()));
+        }
+    }
+    add(config.github_api_url.as_deref(), default_token);
+    Auth { github_tokens }
+}
+
+/// Sends a request to a forge's REST API and decodes the JSON reply.
+/// A 404 becomes `None`; other failures include the server's message.
+pub fn api_call<T: DeserializeOwned>(request: ureq::Request, body: Option<serde_json::Value>) -> Result<Option<T>> {
+    let url = request.url().to_string();
+    let result = match body {
+        Some(body) => request.send_json(body),
+        None => request.call(),
+    };
+    match result {
+        Ok(response) => {
+            let value = response.into_json()
+                .with_context(|| format!("Invalid JSON in response from {}", url))?;
+            Ok(Some(value))
+        }
+        Err(ureq::Error::Status(404, _)) => Ok(None),
+        Err(ureq::Error::Status(code, response)) => {
+            let message = response.into_string().unwrap_or_default();
+            Err(anyhow::anyhow!("{} returned HTTP {}: {}", url, code, message.trim()))
+        }
+        Err(e) => Err(anyhow::Error::new(e).context(format!("Failed to reach {}", url))),
+    }
+}
+
+/// Bare repositories named `<name>.git` under a plain git URL, such as
+/// `ssh://nas/srv/git`, or in a local directory (a path or `file://` URL).
+/// Only local directories can have repositories created in them.
+pub struct GitRemote {
+    base: String,
+    default_branch: String,
+    auth: Auth,
+}
+
+impl GitRemote {
+    pub fn new(base: &str, default_branch: &str, auth: Auth) -> Self {
+        Self {
+            base: base.trim_end_matches('/').to_string(),
+            default_branch: default_branch.to_string(),
+            auth,
+        }
+    }
+
+    fn local_dir(&self) -> Option<PathBuf> {
+        match self.base.strip_prefix("file://") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Path::new(&self.base).is_absolute().then(|| PathBuf::from(&self.base)),
+        }
+    }
+}
+
+impl RemoteProvider for GitRemote {
+    fn create_repo(&self, name: &str) -> Result<()> {
+        let Some(dir) = self.local_dir() else {
+            return Err(anyhow::anyhow!(
+                "gitbox cannot create repositories at {}. Create {} on the server first (e.g. with 'git init --bare').",
+                self.base,
+                self.clone_url(name)?
+            ));
+        };
+        let path = dir.join(format!("{}.git", name));
+        let mut options = RepositoryInitOptions::new();
+        options.bare(true)
+            .no_reinit(true)
+            .mkpath(true)
+            .initial_head(&self.default_branch);
+        Repository::init_opts(&path, &options)
+            .with_context(|| format!("Failed to create bare repository: {:?}", path))?;
+        Ok(())
+    }
+
+    fn repo_exists(&self, name: &str) -> Result<bool> {
+        match self.local_dir() {
+            Some(dir) => Ok(dir.join(format!("{}.git", name)).exists()),
+            None => git::repository_exists(&self.clone_url(name)?, &self.auth)
+                .with_context(|| format!("Failed to check for '{}' at {}", name, self.base)),
+        }
+    }
+
+    fn clone_url(&self, name: &str) -> Result<String> {
+        match self.local_dir() {
+            Some(dir) => Ok(dir.join(format!("{}.git", name)).to_string_lossy().into_owned()),
+            None => Ok(format!("{}/{}.git", self.base, name)),
+        }
+    }
+
+    fn whoami(&self) -> Result<String> {
+        // The user in `ssh://user@host/...` or `user@host:...`, else the local user
+        let authority = self.base.split_once("://").map_or(self.base.as_str(), |(_, rest)| rest);
+        let user = authority.split(['/', ':']).next()
+            .and_then(|host| host.split_once('@'))
+            .map(|(user, _)| user.to_string());
+        user.or_else(|| std::env::var("USER").ok())
+            .context("Could not determine the user name for this remote")
+    }
+
+    fn list_repos(&self) -> Result<Vec<String>> {
+        let dir = self.local_dir()
+            .with_context(|| format!("Cannot list repositories at {}; name the repository instead", self.base))?;
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("Failed to read directory: {:?}", dir))? {
+            let path = entry.context("Failed to read directory entry")?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()).and_then(|name| name.strip_suffix(".git")) else {
+                continue;
+            };
+            let has_metadata = Repository::open_bare(&path).ok()
+                .and_then(|repo| repo.head().ok()?.peel_to_tree().ok()?.get_name(".gitbox").map(|_| ()))
+                .is_some();
+            if has_metadata {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn github_remote(url: Option<&str>, token: Option<&str>) -> RemoteConfig {
+        RemoteConfig {
+            kind: RemoteKind::Github,
+            url: url.map(str::to_string),
+            token: token.map(str::to_string),
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn git_gets_each_token_only_for_its_github_host() {
+        let mut config = Config {
+            github_token: Some("default".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(auth(&config).github_tokens, [("https://github.com/".to_string(), "default".to_string())]);
+
+        config.github_api_url = Some("https://ghe.example.com/api/v3/".to_string());
+        config.remotes.insert("work".to_string(), github_remote(Some("https://git.work.example/api/v3"), Some("work")));
+        config.remotes.insert("public".to_string(), github_remote(Some("https://api.github.com"), None));
+        let tokens = auth(&config).github_tokens;
+        assert_eq!(tokens, [
+            ("https://github.com/".to_string(), "default".to_string()),
+            ("https://git.work.example/".to_string(), "work".to_string()),
+            ("https://ghe.example.com/".to_string(), "default".to_string()),
+        ]);
+    }
+}
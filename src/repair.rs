@@ -0,0 +1,395 @@
+use anyhow::{Context, Result};
+use git2::{ObjectType, Oid, Repository};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backup::save_before_replacing;
+use crate::crypt;
+use crate::output::{self, outln};
+use crate::status::same_file;
+use crate::template;
+use crate::sync::{FileInfo, GitboxMetadata, SyncMode, content_hash, copy_recursively, create_link, detach_link, list_files};
+
+#[derive(Debug)]
+pub enum RepairOutcome {
+    /// The original and the repository copy are still the same file
+    Intact,
+    /// The link was recreated; the message says which content was kept
+    Repaired(String),
+    /// Nothing was changed; the message says why
+    Skipped(String),
+}
+
+/// Which copy wins when reconciling a broken link.
+enum Winner {
+    Original,
+    Repository,
+}
+
+/// How to decide which copy wins when the original and the repository copy differ.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepairPolicy {
+    /// Commit both copies are compared against; HEAD when `None`. After a pull this
+    /// is the commit from before the pull, so pulled changes count as repository edits.
+    pub baseline: Option<Oid>,
+    /// Always keep the repository content (used when local edits are being discarded)
+    pub prefer_repository: bool,
+}
+
+/// Detects a broken hard link between a tracked item and its repository copy
+/// (typically caused by an editor replacing the file on save, or by git rewriting
+/// the repository copy) and relinks them.
+///
+/// The side that differs from the baseline commit wins. When both sides changed, the
+/// user is asked which copy to keep; without a terminal the entry is skipped.
+pub fn repair_entry(git_repo: &Repository, repo_path: &Path, info: &FileInfo, policy: RepairPolicy) -> Result<RepairOutcome> {
+    if info.encrypted {
+        return reconcile_encrypted(git_repo, repo_path, info, policy);
+    }
+    match info.mode {
+        SyncMode::Copy => return reconcile_copy(git_repo, repo_path, info, policy),
+        SyncMode::Template => return render_template(git_repo, repo_path, info, policy),
+        SyncMode::Link => {}
+    }
+    let original = &info.original_path;
+    let synced = &info.synced_path;
+
+    let original_exists = fs::symlink_metadata(original).is_ok();
+    let synced_exists = fs::symlink_metadata(synced).is_ok();
+
+    if !original_exists {
+        return Ok(RepairOutcome::Skipped("original location does not exist".to_string()));
+    }
+    if !synced_exists {
+        create_link(original, synced)?;
+        return Ok(RepairOutcome::Repaired("recreated missing repository copy".to_string()));
+    }
+    if same_file(original, synced) {
+        return Ok(RepairOutcome::Intact);
+    }
+    if info.is_directory || original.is_dir() || synced.is_dir() {
+        // Directories are symlinked into the repository; point the link back at the original
+        if fs::symlink_metadata(synced)?.file_type().is_symlink() {
+            create_link(original, synced)?;
+            return Ok(RepairOutcome::Repaired("re-pointed directory link".to_string()));
+        }
+        return Ok(RepairOutcome::Skipped("repository copy is not a link".to_string()));
+    }
+
+    let original_content = fs::read(original)
+        .with_context(|| format!("Failed to read file: {:?}", original))?;
+    let synced_content = fs::read(synced)
+        .with_context(|| format!("Failed to read file: {:?}", synced))?;
+
+    let winner = if original_content == synced_content {
+        Winner::Original
+    } else {
+        let relative = synced.strip_prefix(repo_path).unwrap_or(synced);
+        let committed = committed_content(git_repo, policy.baseline, relative)?;
+        match choose_winner(original, Some(synced), &original_content, &synced_content, committed.as_deref(), policy)? {
+            Some(winner) => winner,
+            None => return Ok(RepairOutcome::Skipped("both copies changed".to_string())),
+        }
+    };
+
+    // Keep the original's inode (and permissions); bring the repository content into it if needed
+    let message = match winner {
+        Winner::Original => "kept original content",
+        Winner::Repository => {
+            save_before_replacing(original, synced, "replaced by the repository copy")?;
+            fs::write(original, &synced_content)
+                .with_context(|| format!("Failed to write file: {:?}", original))?;
+            "kept repository content"
+        }
+    };
+    create_link(original, synced)?;
+
+    Ok(RepairOutcome::Repaired(message.to_string()))
+}
+
+/// Brings an encrypted entry's original and repository copy back in step: a changed
+/// original is encrypted into the repository, a changed repository copy (e.g. pulled)
+/// is decrypted over the original.
+fn reconcile_encrypted(git_repo: &Repository, repo_path: &Path, info: &FileInfo, policy: RepairPolicy) -> Result<RepairOutcome> {
+    let original = &info.original_path;
+    let synced = &info.synced_path;
+
+    if !original.is_file() {
+        return Ok(RepairOutcome::Skipped("original location does not exist".to_string()));
+    }
+    if !synced.is_file() {
+        crypt::encrypt_file(original, synced)?;
+        return Ok(RepairOutcome::Repaired("recreated missing encrypted copy".to_string()));
+    }
+
+    let original_content = fs::read(original)
+        .with_context(|| format!("Failed to read file: {:?}", original))?;
+    let synced_content = crypt::read_decrypted(synced)?;
+    if original_content == synced_content {
+        return Ok(RepairOutcome::Intact);
+    }
+
+    let relative = synced.strip_prefix(repo_path).unwrap_or(synced);
+    let committed = committed_content(git_repo, policy.baseline, relative)?
+        .map(|ciphertext| crypt::decrypt(&ciphertext))
+        .transpose()?;
+    let winner = match choose_winner(original, None, &original_content, &synced_content, committed.as_deref(), policy)? {
+        Some(winner) => winner,
+        None => return Ok(RepairOutcome::Skipped("both copies changed".to_string())),
+    };
+
+    let message = match winner {
+        Winner::Original => {
+            fs::write(synced, crypt::encrypt(&original_content)?)
+                .with_context(|| format!("Failed to write file: {:?}", synced))?;
+            "encrypted changed original"
+        }
+        Winner::Repository => {
+            save_before_replacing(original, synced, "replaced by the decrypted repository copy")?;
+            fs::write(original, &synced_content)
+                .with_context(|| format!("Failed to write file: {:?}", original))?;
+            "decrypted repository content"
+        }
+    };
+    Ok(RepairOutcome::Repaired(message.to_string()))
+}
+
+/// Brings a copy-mode entry's original and repository copy back in step. Each file
+/// whose content hash differs is copied in the direction it changed since the baseline;
+/// inside a directory, a file deleted on one side is deleted on the other.
+fn reconcile_copy(git_repo: &Repository, repo_path: &Path, info: &FileInfo, policy: RepairPolicy) -> Result<RepairOutcome> {
+    let original = &info.original_path;
+    let synced = &info.synced_path;
+
+    if fs::symlink_metadata(original).is_err() {
+        return Ok(RepairOutcome::Skipped("original location does not exist".to_string()));
+    }
+    if fs::symlink_metadata(synced).is_err() {
+        copy_recursively(original, synced)?;
+        return Ok(RepairOutcome::Repaired("recreated missing repository copy".to_string()));
+    }
+    // An original still linked to the repository copy (e.g. from before the entry was
+    // copied) gets its own copy
+    if same_file(original, synced) {
+        detach_link(original)?;
+        return Ok(RepairOutcome::Repaired("replaced link with a copy".to_string()));
+    }
+
+    let mut copied = 0;
+    let mut conflicts = 0;
+    for (original_file, synced_file, relative) in copied_files(git_repo, repo_path, info)? {
+        let original_hash = content_hash(&original_file);
+        let synced_hash = content_hash(&synced_file);
+        if original_hash == synced_hash {
+            continue;
+        }
+        let committed = committed_content(git_repo, policy.baseline, &relative)?;
+        let committed_hash = committed.as_deref()
+            .and_then(|content| Oid::hash_object(ObjectType::Blob, content).ok());
+
+        let winner = match (original_hash, synced_hash) {
+            (Some(_), Some(_)) => {
+                let original_content = fs::read(&original_file)
+                    .with_context(|| format!("Failed to read file: {:?}", original_file))?;
+                let synced_content = fs::read(&synced_file)
+                    .with_context(|| format!("Failed to read file: {:?}", synced_file))?;
+                choose_winner(&original_file, Some(&synced_file), &original_content, &synced_content, committed.as_deref(), policy)?
+            }
+            // A file that is gone on one side was deleted there if the other side still
+            // has the committed content, and is new on the other side otherwise
+            (Some(hash), None) if !policy.prefer_repository && committed_hash != Some(hash) => Some(Winner::Original),
+            (None, Some(hash)) if policy.prefer_repository || committed_hash != Some(hash) => Some(Winner::Repository),
+            (Some(_), None) => Some(Winner::Repository),
+            (None, Some(_)) => Some(Winner::Original),
+            (None, None) => continue,
+        };
+        match winner {
+            Some(Winner::Original) => copy_over(&original_file, &synced_file, "")?,
+            Some(Winner::Repository) => copy_over(&synced_file, &original_file, "overwritten by a copy from the repository")?,
+            None => {
+                conflicts += 1;
+                continue;
+            }
+        }
+        copied += 1;
+    }
+
+    match (copied, conflicts) {
+        (0, 0) => Ok(RepairOutcome::Intact),
+        (0, _) => Ok(RepairOutcome::Skipped(format!("both copies of {} file(s) changed", conflicts))),
+        _ => Ok(RepairOutcome::Repaired(format!("copied {} changed file(s)", copied))),
+    }
+}
+
+/// Renders a template entry into its original location. An original edited since the
+/// baseline's rendering is left alone, since the edit belongs in the template.
+fn render_template(git_repo: &Repository, repo_path: &Path, info: &FileInfo, policy: RepairPolicy) -> Result<RepairOutcome> {
+    let original = &info.original_path;
+    let synced = &info.synced_path;
+
+    if !synced.is_file() {
+        if !original.is_file() {
+            return Ok(RepairOutcome::Skipped("neither the original nor the template exists".to_string()));
+        }
+        copy_recursively(original, synced)?;
+        return Ok(RepairOutcome::Repaired("recreated missing template from the original".to_string()));
+    }
+
+    let rendered = template::render_file(synced)?;
+    let current = fs::read(original).ok();
+    if current.as_deref() == Some(rendered.as_slice()) {
+        return Ok(RepairOutcome::Intact);
+    }
+    if let Some(current) = &current {
+        let previous = template::render_with(synced, |variant| {
+            let relative = variant.strip_prefix(repo_path).unwrap_or(variant);
+            committed_content(git_repo, policy.baseline, relative)
+        })?;
+        if policy.prefer_repository {
+            save_before_replacing(original, synced, "replaced by a rendered template")?;
+        } else if previous.as_deref() != Some(current.as_slice()) {
+            return Ok(RepairOutcome::Skipped(format!("edited locally; make the change in the template {:?}", synced)));
+        }
+    }
+
+    fs::write(original, rendered)
+        .with_context(|| format!("Failed to write file: {:?}", original))?;
+    Ok(RepairOutcome::Repaired("rendered template".to_string()))
+}
+
+/// Turns directory entries from before directories were mirrored, whose repository copy
+/// is a symlink (which git stores as just the link), into copy-mode entries holding the
+/// directory's files. Returns how many were converted.
+pub fn mirror_linked_directories(metadata: &mut GitboxMetadata) -> Result<usize> {
+    let mut converted = 0;
+    for info in metadata.files.values_mut() {
+        if !info.is_directory || info.mode != SyncMode::Link || info.encrypted || !info.original_path.is_dir() {
+            continue;
+        }
+        let synced = &info.synced_path;
+        if fs::symlink_metadata(synced).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            fs::remove_file(synced)
+                .with_context(|| format!("Failed to remove directory link: {:?}", synced))?;
+        }
+        if !synced.exists() {
+            copy_recursively(&info.original_path, synced)?;
+        }
+        info.mode = SyncMode::Copy;
+        outln!("Now storing the contents of {:?} in the repository", info.original_path);
+        converted += 1;
+    }
+    Ok(converted)
+}
+
+/// Whether a copy-mode entry's original and repository copy differ anywhere.
+pub fn copies_differ(git_repo: &Repository, repo_path: &Path, info: &FileInfo) -> Result<bool> {
+    Ok(copied_files(git_repo, repo_path, info)?.into_iter()
+        .any(|(original, synced, _)| content_hash(&original) != content_hash(&synced)))
+}
+
+/// The files a copy-mode entry consists of, as (original, repository copy, path in the
+/// repository). For a directory, every file on either side that is not ignored.
+fn copied_files(git_repo: &Repository, repo_path: &Path, info: &FileInfo) -> Result<Vec<(PathBuf, PathBuf, PathBuf)>> {
+    let original = &info.original_path;
+    let synced = &info.synced_path;
+    let relative = synced.strip_prefix(repo_path).unwrap_or(synced);
+    if !info.is_directory {
+        return Ok(vec![(original.clone(), synced.clone(), relative.to_path_buf())]);
+    }
+
+    let mut names = list_files(original)?;
+    names.extend(list_files(synced)?);
+    Ok(names.into_iter()
+        .map(|name| (original.join(&name), synced.join(&name), relative.join(&name)))
+        .filter(|(_, _, relative)| !git_repo.is_path_ignored(relative).unwrap_or(false))
+        .collect())
+}
+
+/// Makes `destination` a copy of `source`, or removes it if `source` is gone. Unless
+/// `reason` is empty, whatever is replaced is backed up first.
+fn copy_over(source: &Path, destination: &Path, reason: &str) -> Result<()> {
+    if !reason.is_empty() && destination.is_file() {
+        save_before_replacing(destination, source, reason)?;
+    }
+    if !source.is_file() {
+        return fs::remove_file(destination)
+            .with_context(|| format!("Failed to remove file: {:?}", destination));
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::copy(source, destination)
+        .with_context(|| format!("Failed to copy {:?} to {:?}", source, destination))?;
+    Ok(())
+}
+
+/// Picks the copy that changed since `committed`, asking when both did. `None` means skip.
+fn choose_winner(
+    original: &Path,
+    synced: Option<&Path>,
+    original_content: &[u8],
+    synced_content: &[u8],
+    committed: Option<&[u8]>,
+    policy: RepairPolicy,
+) -> Result<Option<Winner>> {
+    if policy.prefer_repository {
+        return Ok(Some(Winner::Repository));
+    }
+    let original_changed = committed != Some(original_content);
+    let synced_changed = committed != Some(synced_content);
+
+    match (original_changed, synced_changed) {
+        (_, false) => Ok(Some(Winner::Original)),
+        (false, true) => Ok(Some(Winner::Repository)),
+        (true, true) => ask_winner(original, synced),
+    }
+}
+
+fn committed_content(git_repo: &Repository, commit_id: Option<Oid>, relative: &Path) -> Result<Option<Vec<u8>>> {
+    let commit = match commit_id {
+        Some(id) => git_repo.find_commit(id).ok(),
+        None => git_repo.head().ok().and_then(|head| head.peel_to_commit().ok()),
+    };
+    let Some(commit) = commit else {
+        return Ok(None);
+    };
+    let tree = commit.tree().context("Failed to read commit tree")?;
+    let Ok(entry) = tree.get_path(relative) else {
+        return Ok(None);
+    };
+    let blob = git_repo.find_blob(entry.id()).context("Failed to read committed file")?;
+    Ok(Some(blob.content().to_vec()))
+}
+
+/// Asks which copy to keep, showing a diff when the repository copy is readable (`synced`).
+fn ask_winner(original: &Path, synced: Option<&Path>) -> Result<Option<Winner>> {
+    if !output::can_prompt() {
+        return Ok(None);
+    }
+    let _prompt = output::prompt_lock();
+
+    outln!("Both {:?} and its repository copy changed since the last commit.", original);
+    let diff_output = synced.map(|synced| std::process::Command::new("diff")
+        .args(["-u", &synced.to_string_lossy(), &original.to_string_lossy()])
+        .output());
+    if let Some(Ok(diff)) = diff_output {
+        let diff_text = String::from_utf8_lossy(&diff.stdout);
+        if !diff_text.trim().is_empty() {
+            outln!("\nDifferences (repository -> original):");
+            outln!("{}", diff_text);
+        }
+    }
+
+    print!("Keep which copy? [o]riginal / [r]epository / [s]kip: ");
+    use std::io::{self, Write};
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    match input.trim().to_lowercase().as_str() {
+        "o" | "original" => Ok(Some(Winner::Original)),
+        "r" | "repo" | "repository" => Ok(Some(Winner::Repository)),
+        _ => Ok(None),
+    }
+}
@@ -1,12 +1,33 @@
 use anyhow::{Context, Result};
-use git2::{Repository, Signature, IndexAddOption};
+use git2::{IndexAddOption, ObjectType, Oid, Repository, ResetType, StashFlags};
 use std::fs;
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 use std::os::unix::fs::MetadataExt;
 
-use crate::config::{Config, AppInfo};
-use crate::github::GitHubClient;
-use crate::sync::{GitboxMetadata, create_link};
+use crate::backup::Backup;
+use crate::commit;
+use crate::config::{Config, AppInfo, DirtyPullStrategy};
+use crate::crypt;
+use crate::git::{self, Auth, GitError, REMOTE};
+use crate::history::{self, Change};
+use crate::ignore;
+use crate::merge::{
+    ConflictSides, MergeOutcome, PendingMerge, ResolveChoice, conflict_sides, finish_merge, merge_upstream, record_resolution,
+};
+use crate::output::{errln, outln};
+use crate::paths::PathMapper;
+use crate::profile::{Profile, Selection};
+use crate::remote::{self, RemoteProvider};
+use crate::restore::{LinkAction, apply_link, plan_links};
+use crate::repair::{RepairOutcome, RepairPolicy, copies_differ, mirror_linked_directories, repair_entry};
+use crate::secrets::{self, Finding};
+use crate::template;
+use crate::status::{RepoStatus, has_uncommitted_changes, repo_status, same_file, uncommitted_paths};
+use crate::sync::{
+    FileInfo, GitboxMetadata, SyncMode, content_hash, copy_recursively, create_link, detach_link, glob_set, list_files, list_synced_entries,
+    remove_synced_item, repo_relative_path, validate_repo_relative,
+};
 
 #[derive(Debug)]
 pub struct SyncedFile {
@@ -16,9 +37,47 @@ pub struct SyncedFile {
     pub is_directory: bool,
 }
 
+/// How a pull treats uncommitted edits to tracked files.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PullOptions {
+    /// Overrides the configured strategy for this pull
+    pub on_dirty: Option<DirtyPullStrategy>,
+    /// Discard local edits instead (`git reset --hard`)
+    pub force: bool,
+}
+
+/// What syncing one repository did.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Remote commits merged into the local branch
+    pub pulled: usize,
+    /// Local commits the remote did not have before the push
+    pub pushed: usize,
+    /// Originals the pulled changes were written to
+    pub written: Vec<PathBuf>,
+}
+
+/// How `sync` stores a new item in the repository.
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Directory the repository layout is relative to (the home directory by default)
+    pub root: Option<PathBuf>,
+    /// Store an encrypted copy, even if no `[encryption]` pattern matches
+    pub encrypt: bool,
+    /// Ignore patterns for a synced directory, relative to it
+    pub exclude: Vec<String>,
+    /// Keep an independent copy in the repository instead of a link. Files are linked
+    /// by default; directories are always copied.
+    pub mode: Option<SyncMode>,
+}
+
 pub struct RepoManager {
     config: Config,
     app_info: AppInfo,
+    /// Print the planned actions instead of carrying them out
+    dry_run: bool,
+    /// Subject for the commits this run makes (`-m`), instead of a generated one
+    commit_message: Option<String>,
 }
 
 impl RepoManager {
@@ -29,6 +88,12 @@ impl RepoManager {
                 .with_context(|| format!("Failed to create repos directory: {:?}", config.repos_dir))?;
         }
 
+        // Resolve portable metadata paths with this machine's roots
+        PathMapper::install(PathMapper::from_config(config));
+        crypt::install(&config.encryption);
+        template::install(&config.machine);
+        commit::install(&config.commit);
+
         // Load or create app info
         let mut app_info = AppInfo::load_or_create()?;
         
@@ -38,10 +103,24 @@ impl RepoManager {
         Ok(Self {
             config: config.clone(),
             app_info,
+            dry_run: false,
+            commit_message: None,
         })
     }
 
-    pub async fn add_repo(&mut self, repo_name: &str) -> Result<()> {
+    /// In a dry run, mutating commands print what they would do and change nothing.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Uses `message` as the subject of every commit instead of a generated summary.
+    pub fn set_commit_message(&mut self, message: Option<String>) {
+        self.commit_message = message;
+    }
+
+    /// Creates the repository locally and on its remote. `remote` names a provider
+    /// from the `[remotes]` config table and is remembered for this repository.
+    pub async fn add_repo(&mut self, repo_name: &str, remote: Option<&str>) -> Result<()> {
         // Validate repo name
         if repo_name.trim().is_empty() {
             return Err(anyhow::anyhow!("Repository name cannot be empty"));
@@ -57,6 +136,24 @@ impl RepoManager {
             return Err(anyhow::anyhow!("Repository '{}' already exists", repo_name));
         }
 
+        let provider = match remote {
+            Some(remote) => remote::named_provider(&self.config, remote)?,
+            None => remote::provider_for(&self.config, repo_name)?,
+        };
+
+        if self.dry_run {
+            outln!("Would create local repository {:?} with an initial commit", repo_path);
+            if provider.repo_exists(repo_name)? {
+                outln!("Would merge the existing remote repository {}", provider.clone_url(repo_name)?);
+            } else {
+                outln!("Would create remote repository '{}' and push the initial commit", repo_name);
+            }
+            if let Some(remote) = remote {
+                outln!("Would remember remote '{}' for '{}' in config.toml", remote, repo_name);
+            }
+            return Ok(());
+        }
+
         // Create repository directory
         fs::create_dir_all(&repo_path)
             .with_context(|| format!("Failed to create repository directory: {:?}", repo_path))?;
@@ -70,10 +167,9 @@ impl RepoManager {
         metadata.repo_name = Some(repo_name.to_string());
         metadata.save_to_dir(&repo_path)?;
 
-        // Create initial commit
-        let signature = Signature::now("gitbox", "gitbox@local")
-            .context("Failed to create git signature")?;
-        
+        // Create the initial commit on the default branch
+        git_repo.set_head(&format!("refs/heads/{}", self.config.default_branch))
+            .context("Failed to set HEAD")?;
         let mut index = git_repo.index()
             .context("Failed to get git index")?;
         index.add_path(Path::new(".gitbox"))
@@ -85,73 +181,35 @@ impl RepoManager {
             .context("Failed to write git tree")?;
         let tree = git_repo.find_tree(tree_id)
             .context("Failed to find git tree")?;
+        commit::create(&git_repo, "Initial commit", &tree, &[])
+            .context("Failed to create initial commit")?;
 
-        // For the first commit, we need to update the HEAD reference manually
-        let commit_id = git_repo.commit(
-            None, // Don't update any reference yet
-            &signature,
-            &signature,
-            "Initial commit",
-            &tree,
-            &[],
-        ).context("Failed to create initial commit")?;
-
-        // Set HEAD to point to the new commit
-        git_repo.reference(&format!("refs/heads/{}", self.config.default_branch), commit_id, false, "Initial commit")
-            .context("Failed to create branch reference")?;
-        
-        // Set HEAD to point to the branch
-        git_repo.set_head(&format!("refs/heads/{}", self.config.default_branch))
-            .context("Failed to set HEAD")?;
-
-        // Create or get existing GitHub repository
-        let github_client = GitHubClient::new(self.config.github_token.as_deref())?;
-        let clone_url = match github_client.create_private_repo(repo_name).await {
-            Ok(url) => {
-                println!("Created new GitHub repository");
-                url
-            }
-            Err(e) => {
-                // Check if the error is because the repository already exists
-                let error_msg = format!("{}", e);
-                if error_msg.contains("Name already exists") || error_msg.contains("already exists") {
-                    println!("GitHub repository already exists, syncing with existing repository...");
-                    
-                    // Get the authenticated user to construct the clone URL
-                    let username = github_client.get_authenticated_user().await?;
-                    format!("git@github.com:{}/{}.git", username, repo_name)
-                } else {
-                    return Err(e);
-                }
-            }
-        };
+        // Create or get the existing remote repository
+        if provider.repo_exists(repo_name)? {
+            outln!("Remote repository already exists, syncing with existing repository...");
+        } else {
+            provider.create_repo(repo_name)?;
+            outln!("Created new remote repository");
+        }
+        let clone_url = provider.clone_url(repo_name)?;
 
-        // Add remote and sync with GitHub
+        // Add remote and sync with it
         let _remote = git_repo.remote("origin", &clone_url)
             .context("Failed to add remote origin")?;
 
-        // Try to pull first in case the remote repository has content
-        let pull_output = std::process::Command::new("git")
-            .args(&["pull", "origin", &self.config.default_branch, "--allow-unrelated-histories"])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to execute git pull")?;
-
-        if pull_output.status.success() {
-            println!("Synced with existing remote repository");
+        // Merge first in case the remote repository has content
+        if self.merge_remote_changes(&repo_path)?.is_some() {
+            outln!("Synced with existing remote repository");
         } else {
-            // If pull fails, the remote might be empty, so try to push our initial commit
-            let push_output = std::process::Command::new("git")
-                .args(&["push", "-u", "origin", &self.config.default_branch])
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to execute git push")?;
+            // The remote is empty, so push our initial commit
+            self.push_branch(&git_repo)
+                .context("Failed to push to remote")?;
+            outln!("Pushed initial commit to remote repository");
+        }
 
-            if !push_output.status.success() {
-                let stderr = String::from_utf8_lossy(&push_output.stderr);
-                return Err(anyhow::anyhow!("Failed to push to GitHub: {}", stderr));
-            }
-            println!("Pushed initial commit to remote repository");
+        if let Some(remote) = remote {
+            self.config.repo_remotes.insert(repo_name.to_string(), remote.to_string());
+            self.config.save()?;
         }
 
         // Update app info with new repository
@@ -160,6 +218,107 @@ impl RepoManager {
         Ok(())
     }
 
+    /// Sets this machine up from the remote: clones `repo_name` (or every gitbox
+    /// repository the remote has) and links each tracked file into its original
+    /// location. Files it replaces are backed up first. A dry run only prints the plan.
+    pub fn restore(&mut self, repo_name: Option<&str>, remote: Option<&str>) -> Result<()> {
+        let provider = match (remote, repo_name) {
+            (Some(remote), _) => remote::named_provider(&self.config, remote)?,
+            (None, Some(name)) => remote::provider_for(&self.config, name)?,
+            (None, None) => remote::default_provider(&self.config)?,
+        };
+        let repo_names = match repo_name {
+            Some(name) => vec![name.to_string()],
+            None => provider.list_repos()?,
+        };
+        if repo_names.is_empty() {
+            outln!("No gitbox repositories found on the remote");
+        }
+
+        for name in repo_names {
+            self.restore_repo(&name, provider.as_ref(), remote)
+                .with_context(|| format!("Failed to restore '{}'", name))?;
+        }
+        Ok(())
+    }
+
+    fn restore_repo(
+        &mut self,
+        repo_name: &str,
+        provider: &dyn RemoteProvider,
+        remote: Option<&str>,
+    ) -> Result<()> {
+        let dry_run = self.dry_run;
+        let repo_path = self.config.get_repo_path(repo_name);
+        let mut scratch = None;
+        let checkout = if repo_path.exists() {
+            outln!("Using existing clone of '{}'", repo_name);
+            repo_path.clone()
+        } else {
+            if !provider.repo_exists(repo_name)? {
+                return Err(anyhow::anyhow!("Remote repository '{}' does not exist", repo_name));
+            }
+            let clone_url = provider.clone_url(repo_name)?;
+            // A dry run still needs the repository's files to compare against
+            let target = if dry_run {
+                let dir = std::env::temp_dir().join(format!("gitbox-restore-{}", uuid::Uuid::new_v4()));
+                scratch = Some(dir.clone());
+                dir
+            } else {
+                repo_path.clone()
+            };
+            git::clone(&clone_url, &target, &self.config.default_branch, &self.auth())
+                .with_context(|| format!("Failed to clone {}", clone_url))?;
+
+            if dry_run {
+                outln!("Would clone '{}' from {}", repo_name, clone_url);
+            } else {
+                outln!("Cloned '{}' from {}", repo_name, clone_url);
+                if let Some(remote) = remote {
+                    self.config.repo_remotes.insert(repo_name.to_string(), remote.to_string());
+                    self.config.save()?;
+                }
+                self.app_info.add_repository(repo_name, Some(clone_url))?;
+            }
+            target
+        };
+
+        let result = self.restore_links(&repo_path, &checkout);
+        if let Some(dir) = scratch {
+            let _ = fs::remove_dir_all(dir);
+        }
+        result
+    }
+
+    fn restore_links(&self, repo_path: &Path, checkout: &Path) -> Result<()> {
+        let dry_run = self.dry_run;
+        let metadata = GitboxMetadata::load_from_dir(checkout)?;
+        let plan = plan_links(&metadata, repo_path, checkout)?;
+        let verb = |action: &str| if dry_run { format!("Would {}", action) } else { action.to_string() };
+
+        for link in &plan {
+            match &link.action {
+                LinkAction::Create if link.encrypted => outln!("  {} {:?}", verb("decrypt"), link.original),
+                LinkAction::Create if link.mode == SyncMode::Copy => outln!("  {} {:?}", verb("copy"), link.original),
+                LinkAction::Create if link.mode == SyncMode::Template => outln!("  {} {:?}", verb("render"), link.original),
+                LinkAction::Create => outln!("  {} {:?}", verb("link"), link.original),
+                LinkAction::Relink => outln!("  {} {:?} (same content)", verb("relink"), link.original),
+                LinkAction::Replace => {
+                    outln!("  {} {:?} (backup in {:?})", verb("replace"), link.original, Backup::current().destination(&link.original))
+                }
+                LinkAction::Intact => outln!("  Already linked {:?}", link.original),
+                LinkAction::Skip(reason) => outln!("  Skipping {:?}: {}", link.original, reason),
+            }
+            if !dry_run {
+                apply_link(link)?;
+            }
+        }
+        if plan.is_empty() {
+            outln!("  No tracked files");
+        }
+        Ok(())
+    }
+
     pub fn delete_repo(&mut self, repo_name: &str, force: bool) -> Result<()> {
         // Try to find the repository with fuzzy matching
         let actual_repo_name = self.find_repository(repo_name)?;
@@ -171,45 +330,55 @@ impl RepoManager {
 
         // Load metadata to show what will be deleted
         let metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+
+        if self.dry_run {
+            outln!("Would delete repository '{}' at {:?}", actual_repo_name, repo_path);
+            if !metadata.files.is_empty() {
+                outln!("  {} synced item(s) would lose their repository copy:", metadata.files.len());
+                for original_path in metadata.files.keys() {
+                    outln!("    {}", original_path);
+                }
+            }
+            outln!("  The remote repository would be kept");
+            return Ok(());
+        }
         
         if !force {
             if actual_repo_name != repo_name {
-                println!("Found repository '{}' matching '{}'", actual_repo_name, repo_name);
+                outln!("Found repository '{}' matching '{}'", actual_repo_name, repo_name);
             }
-            println!("Repository '{}' will be deleted:", actual_repo_name);
-            println!("  Path: {:?}", repo_path);
+            outln!("Repository '{}' will be deleted:", actual_repo_name);
+            outln!("  Path: {:?}", repo_path);
             
             if !metadata.files.is_empty() {
-                println!("  Synced files ({}):", metadata.files.len());
+                outln!("  Synced files ({}):", metadata.files.len());
                 for (original_path, file_info) in &metadata.files {
                     let file_type = if file_info.is_directory { "dir" } else { "file" };
-                    println!("    {} -> {} ({})", 
+                    outln!("    {} -> {} ({})", 
                         original_path,
                         file_info.synced_path.display(),
                         file_type
                     );
                 }
             } else {
-                println!("  No synced files");
+                outln!("  No synced files");
             }
             
-            // Check if GitHub repository exists
-            let remote_check = std::process::Command::new("git")
-                .args(&["remote", "get-url", "origin"])
-                .current_dir(&repo_path)
-                .output();
-            
-            if let Ok(output) = remote_check {
-                if output.status.success() {
-                    let remote_url = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                    println!("  GitHub repository: {}", remote_url);
-                    println!("\n⚠️  WARNING: This will only delete the LOCAL repository.");
-                    println!("   The GitHub repository will remain online.");
-                    println!("   To delete it from GitHub, use: gh repo delete <repo-name>");
+            // The remote repository is kept
+            let remote_url = Repository::open(&repo_path).ok()
+                .and_then(|git_repo| git::remote_url(&git_repo, REMOTE));
+            if let Some(remote_url) = remote_url {
+                outln!("  Remote repository: {}", remote_url);
+                outln!("\n⚠️  WARNING: This will only delete the LOCAL repository.");
+                outln!("   The remote repository will remain online.");
+                let delete_command = remote::provider_for(&self.config, &actual_repo_name).ok()
+                    .and_then(|provider| provider.delete_command(&actual_repo_name));
+                if let Some(command) = delete_command {
+                    outln!("   To delete it there too, use: {}", command);
                 }
             }
             
-            println!("\nThis action cannot be undone!");
+            outln!("\nThis action cannot be undone!");
             print!("Are you sure you want to delete this repository? (y/N): ");
             
             use std::io::{self, Write};
@@ -220,7 +389,7 @@ impl RepoManager {
             let input = input.trim().to_lowercase();
             
             if input != "y" && input != "yes" {
-                println!("Repository deletion cancelled.");
+                outln!("Repository deletion cancelled.");
                 return Ok(());
             }
         }
@@ -232,14 +401,14 @@ impl RepoManager {
         // Update app info by removing the repository
         self.app_info.remove_repository(&actual_repo_name)?;
 
-        println!("Repository '{}' has been deleted from local storage.", actual_repo_name);
+        outln!("Repository '{}' has been deleted from local storage.", actual_repo_name);
         
         if !metadata.files.is_empty() {
-            println!("\nNote: Symbolic links to synced files may now be broken.");
-            println!("You may need to manually clean up broken symlinks in:");
-            for (original_path, _) in &metadata.files {
+            outln!("\nNote: Symbolic links to synced files may now be broken.");
+            outln!("You may need to manually clean up broken symlinks in:");
+            for original_path in metadata.files.keys() {
                 if let Some(parent) = std::path::Path::new(original_path).parent() {
-                    println!("  {:?}", parent);
+                    outln!("  {:?}", parent);
                 }
             }
         }
@@ -247,77 +416,68 @@ impl RepoManager {
         Ok(())
     }
 
-    pub async fn sync_file_with_default(&mut self, file_path: &str, repo_name: &str) -> Result<()> {
+    async fn create_missing_repo(&mut self, repo_name: &str) -> Result<()> {
+        outln!("Repository '{}' doesn't exist. Creating it...", repo_name);
+        self.add_repo(repo_name, None).await?;
+        if !self.dry_run {
+            outln!("Repository '{}' created successfully", repo_name);
+        }
+        Ok(())
+    }
+
+    pub async fn sync_file_with_default(&mut self, file_path: &str, repo_name: &str, options: &SyncOptions) -> Result<()> {
         let repo_path = self.config.get_repo_path(repo_name);
         
         // If repository doesn't exist, create it
         if !repo_path.exists() {
-            println!("Repository '{}' doesn't exist. Creating it...", repo_name);
-            self.add_repo(repo_name).await?;
-            println!("Repository '{}' created successfully", repo_name);
+            self.create_missing_repo(repo_name).await?;
         }
 
         // Now sync the file
-        self.sync_file(file_path, repo_name)
+        self.sync_file(file_path, repo_name, options)
     }
 
-    pub async fn sync_from_remote(&mut self, filename: &str, repo_name: &str) -> Result<()> {
+    pub async fn sync_from_remote(&mut self, filename: &str, repo_name: &str, options: PullOptions) -> Result<()> {
         let repo_path = self.config.get_repo_path(repo_name);
         
         // If repository doesn't exist, create it
         if !repo_path.exists() {
-            println!("Repository '{}' doesn't exist. Creating it...", repo_name);
-            self.add_repo(repo_name).await?;
-            println!("Repository '{}' created successfully", repo_name);
+            self.create_missing_repo(repo_name).await?;
         }
 
-        // First, pull from remote to get latest changes (without pushing)
-        self.pull_from_remote(&repo_path)?;
-
-        // Check if the file exists in the repository
-        let synced_file_path = repo_path.join("files").join(filename);
-        if !synced_file_path.exists() {
-            return Err(anyhow::anyhow!("File '{}' not found in repository '{}'", filename, repo_name));
+        if self.dry_run {
+            return self.plan_sync_from_remote(filename, repo_name, &repo_path, options);
         }
 
-        // Get current directory for the destination
+        // First, pull from remote to get latest changes (without pushing)
+        self.pull_from_remote(&repo_path, options)?;
+
+        // Resolve the file inside the repository (full relative path or unique file name)
+        let relative_path = self.resolve_repo_file(&repo_path, filename, repo_name)?;
+        ensure_selected(&repo_path, &relative_path, repo_name)?;
+        let synced_file_path = repo_path.join("files").join(&relative_path);
+        let destination_path = pull_destination(&relative_path, filename)?;
+        let (encrypted, mode) = self.stored_as(&repo_path, &synced_file_path)?;
         let current_dir = std::env::current_dir()
             .context("Failed to get current directory")?;
-        let destination_path = current_dir.join(filename);
 
         // Check if file already exists locally
         if destination_path.exists() {
-            // Check if files are different
-            let files_differ = if synced_file_path.is_dir() != destination_path.is_dir() {
-                true // Different types (file vs directory)
-            } else if synced_file_path.is_dir() {
-                // For directories, we'll assume they're different if both exist
-                // (proper directory comparison would be too complex for this context)
-                false
-            } else {
-                // Compare file contents
-                let remote_content = fs::read(&synced_file_path)
-                    .with_context(|| format!("Failed to read remote file: {:?}", synced_file_path))?;
-                let local_content = fs::read(&destination_path)
-                    .with_context(|| format!("Failed to read local file: {:?}", destination_path))?;
-                remote_content != local_content
-            };
-
-            if files_differ {
+            if differs_from_repository(&synced_file_path, &destination_path, encrypted, mode)? {
                 // Show diff and ask for confirmation
-                println!("File '{}' already exists locally but differs from remote version.", filename);
+                outln!("File '{}' already exists locally but differs from remote version.", filename);
                 
-                if !synced_file_path.is_dir() && !destination_path.is_dir() {
+                if !synced_file_path.is_dir() && !destination_path.is_dir() && !encrypted && mode != SyncMode::Template {
                     // Show diff for files
                     let diff_output = std::process::Command::new("diff")
-                        .args(&["-u", &destination_path.to_string_lossy(), &synced_file_path.to_string_lossy()])
+                        .args(["-u", &destination_path.to_string_lossy(), &synced_file_path.to_string_lossy()])
                         .output();
                     
                     if let Ok(diff) = diff_output {
                         let diff_text = String::from_utf8_lossy(&diff.stdout);
                         if !diff_text.trim().is_empty() {
-                            println!("\nDifferences:");
-                            println!("{}", diff_text);
+                            outln!("\nDifferences:");
+                            outln!("{}", diff_text);
                         }
                     }
                 }
@@ -331,12 +491,18 @@ impl RepoManager {
                 let input = input.trim().to_lowercase();
                 
                 if input != "y" && input != "yes" {
-                    println!("Sync cancelled.");
+                    outln!("Sync cancelled.");
                     return Ok(());
                 }
-                
-                // Remove the existing file/directory
-                if destination_path.is_dir() {
+
+                let saved = Backup::current().save(&destination_path, "overwritten by sync-from-remote")?;
+                outln!("Backed up {:?} to {:?}", destination_path, saved);
+
+                // Remove the existing file/directory; a copied directory is updated in place
+                let update_in_place = mode == SyncMode::Copy && destination_path.is_dir() && synced_file_path.is_dir();
+                if update_in_place {
+                    outln!("Updating {:?} in place", destination_path);
+                } else if destination_path.is_dir() {
                     fs::remove_dir_all(&destination_path)
                         .with_context(|| format!("Failed to remove existing directory: {:?}", destination_path))?;
                 } else {
@@ -345,125 +511,922 @@ impl RepoManager {
                 }
             } else {
                 // Files are the same, just update metadata if needed
-                println!("Local file '{}' is already up to date with remote version.", filename);
+                outln!("Local file '{}' is already up to date with remote version.", filename);
                 return Ok(());
             }
         }
 
-        // Create hard link from repository to current directory
+        // Create hard link from repository to current directory, or decrypt or copy the repository copy there
         let is_directory = synced_file_path.is_dir();
-        create_link(&synced_file_path, &destination_path)?;
+        if encrypted {
+            crypt::decrypt_file(&synced_file_path, &destination_path)?;
+            outln!("Decrypted {} -> {}", synced_file_path.display(), destination_path.display());
+        } else if mode == SyncMode::Copy {
+            copy_recursively(&synced_file_path, &destination_path)?;
+            outln!("Copied {} -> {}", synced_file_path.display(), destination_path.display());
+        } else if mode == SyncMode::Template {
+            fs::write(&destination_path, template::render_file(&synced_file_path)?)
+                .with_context(|| format!("Failed to write file: {:?}", destination_path))?;
+            outln!("Rendered {} -> {}", synced_file_path.display(), destination_path.display());
+        } else {
+            create_link(&synced_file_path, &destination_path)?;
+        }
+
+        // Update local metadata
+        let mut local_metadata = GitboxMetadata::load_from_dir(&current_dir)?;
+        local_metadata.add_file(&destination_path, &synced_file_path, is_directory, encrypted).mode = mode;
+        local_metadata.repo_name = Some(repo_name.to_string());
+        local_metadata.save_to_dir(&current_dir)?;
+
+        Ok(())
+    }
+
+    /// Prints what `sync_from_remote` would do, judged from the local clone as it is
+    /// before the pull.
+    fn plan_sync_from_remote(&self, filename: &str, repo_name: &str, repo_path: &Path, options: PullOptions) -> Result<()> {
+        if let Ok(git_repo) = Repository::open(repo_path) {
+            ignore::apply(&git_repo)?;
+            let dirty_files = uncommitted_paths(&git_repo, None)?;
+            if !dirty_files.is_empty() {
+                let dirty = dirty_files.join(", ");
+                if options.force {
+                    outln!("Would discard local changes to: {}", dirty);
+                } else {
+                    match options.on_dirty.unwrap_or(self.config.on_dirty_pull) {
+                        DirtyPullStrategy::Commit => outln!("Would commit local changes to: {}", dirty),
+                        DirtyPullStrategy::Stash => outln!("Would stash and afterwards restore local changes to: {}", dirty),
+                        DirtyPullStrategy::Abort => {
+                            outln!("Would stop: repository has uncommitted changes to: {}", dirty);
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+        outln!("Would pull remote changes into '{}'", repo_name);
+
+        let relative_path = match self.resolve_repo_file(repo_path, filename, repo_name) {
+            Ok(relative_path) => relative_path,
+            Err(_) => {
+                outln!("'{}' is not in the local copy yet; it would be linked into the current directory if the pull brings it", filename);
+                return Ok(());
+            }
+        };
+        if let Err(e) = ensure_selected(repo_path, &relative_path, repo_name) {
+            outln!("Would stop: {}", e);
+            return Ok(());
+        }
+        let synced_file_path = repo_path.join("files").join(&relative_path);
+        let destination_path = pull_destination(&relative_path, filename)?;
+        let (encrypted, mode) = self.stored_as(repo_path, &synced_file_path)?;
+        if !destination_path.exists() {
+            if encrypted {
+                outln!("Would decrypt {:?} into {:?}", synced_file_path, destination_path);
+            } else if mode == SyncMode::Copy {
+                outln!("Would copy {:?} to {:?}", synced_file_path, destination_path);
+            } else if mode == SyncMode::Template {
+                outln!("Would render {:?} into {:?}", synced_file_path, destination_path);
+            } else {
+                outln!("Would link {:?} to {:?}", destination_path, synced_file_path);
+            }
+        } else if differs_from_repository(&synced_file_path, &destination_path, encrypted, mode)? {
+            outln!("Would ask to overwrite {:?} with the repository version", destination_path);
+        } else {
+            outln!("Local file '{}' is already up to date with remote version.", filename);
+        }
+        Ok(())
+    }
+
+    /// How the repository stores the item at `synced_path`: encrypted, and in which mode.
+    fn stored_as(&self, repo_path: &Path, synced_path: &Path) -> Result<(bool, SyncMode)> {
+        let metadata = GitboxMetadata::load_from_dir(repo_path)?;
+        Ok(metadata.files.values()
+            .find(|info| info.synced_path == synced_path)
+            .map_or((false, SyncMode::Link), |info| (info.encrypted, info.mode)))
+    }
+
+    pub fn sync_file(&mut self, file_path: &str, repo_name: &str, options: &SyncOptions) -> Result<()> {
+        let repo_path = self.config.get_repo_path(repo_name);
+        // A dry run may have only planned to create the repository
+        if !repo_path.exists() && !self.dry_run {
+            return Err(anyhow::anyhow!("Repository '{}' does not exist", repo_name));
+        }
+
+        let original_path = PathBuf::from(file_path);
+        if !original_path.exists() {
+            return Err(anyhow::anyhow!("File or directory does not exist: {}", file_path));
+        }
+
+        let original_path = original_path.canonicalize()
+            .with_context(|| format!("Failed to canonicalize path: {}", file_path))?;
+
+        // Load metadata from the current directory
+        let current_dir = std::env::current_dir()
+            .context("Failed to get current directory")?;
+        let mut local_metadata = GitboxMetadata::load_from_dir(&current_dir)?;
+
+        // Check if file is already synced
+        let already_synced = local_metadata.get_file(&original_path).is_some();
+        
+        if already_synced {
+            // File is already synced, check if hard link is still intact and reconcile it if not
+            let file_info = local_metadata.get_file(&original_path).unwrap();
+            // Local metadata may predate the entry's current settings in the repository
+            let repo_metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+            let file_info = repo_metadata.get_file(&original_path).unwrap_or(file_info);
+            if options.mode.is_some_and(|mode| mode != file_info.mode) {
+                return Err(anyhow::anyhow!(
+                    "{:?} is already synced in {} mode; unsync it first to change the mode",
+                    original_path,
+                    file_info.mode
+                ));
+            }
+            if !options.exclude.is_empty() {
+                self.add_excludes(&repo_path, &original_path, &options.exclude)?;
+            }
+            if self.dry_run {
+                return self.plan_commit_and_push(&repo_path);
+            }
+            let git_repo = Repository::open(&repo_path)
+                .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+            match repair_entry(&git_repo, &repo_path, file_info, RepairPolicy::default())? {
+                RepairOutcome::Intact => outln!("File is already synced. Committing updated content..."),
+                RepairOutcome::Repaired(how) => outln!("File is already synced but its link was broken. Restored link ({})", how),
+                RepairOutcome::Skipped(reason) => outln!("File is already synced but its link is broken and was not repaired: {}", reason),
+            }
+            
+            // Commit changes
+            self.commit_repo_changes(&repo_path, None)?;
+            
+            // Push changes to remote repository
+            self.push_repo_changes(&repo_path)?;
+            
+            return Ok(());
+        }
+
+        // Mirror the item's location relative to the sync root inside files/
+        let root = match &options.root {
+            Some(root) => root.canonicalize()
+                .with_context(|| format!("Failed to canonicalize root: {:?}", root))?,
+            None => dirs::home_dir()
+                .context("Could not find home directory")?
+                .canonicalize()
+                .context("Failed to canonicalize home directory")?,
+        };
+        let relative_path = repo_relative_path(&original_path, &root)?;
+        let files_dir = repo_path.join("files");
+        let synced_path = files_dir.join(&relative_path);
+
+        // Refuse to overwrite another tracked item that maps to the same location
+        let mut repo_metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+        if let Some(existing) = repo_metadata.find_conflicting_entry(&original_path, &synced_path) {
+            return Err(anyhow::anyhow!(
+                "Cannot sync {:?}: its repository path 'files/{}' collides with already synced {:?}",
+                original_path,
+                relative_path.display(),
+                existing.original_path
+            ));
+        }
+        if repo_metadata.get_file(&original_path).is_none() && fs::symlink_metadata(&synced_path).is_ok() {
+            return Err(anyhow::anyhow!(
+                "Cannot sync {:?}: 'files/{}' already exists in repository '{}'",
+                original_path,
+                relative_path.display(),
+                repo_name
+            ));
+        }
+
+        let is_directory = original_path.is_dir();
+        let encrypted = options.encrypt || crypt::matches_pattern(&relative_path)?;
+        if encrypted && is_directory {
+            return Err(anyhow::anyhow!("Cannot encrypt {:?}: only files can be encrypted", original_path));
+        }
+        if !options.exclude.is_empty() && !is_directory {
+            return Err(anyhow::anyhow!("Cannot exclude paths from {:?}: it is not a directory", original_path));
+        }
+        // Git stores a symlink as just the link, so directories are mirrored file by file
+        let mode = match options.mode {
+            Some(mode @ (SyncMode::Link | SyncMode::Template)) if is_directory => {
+                return Err(anyhow::anyhow!("Cannot sync {:?} in {} mode: directories are always synced as copies", original_path, mode));
+            }
+            Some(SyncMode::Template) if encrypted => {
+                return Err(anyhow::anyhow!("Cannot sync {:?} as a template: encrypted files are stored as copies", original_path));
+            }
+            _ if is_directory => SyncMode::Copy,
+            mode => mode.unwrap_or_default(),
+        };
+
+        if self.dry_run {
+            if encrypted {
+                outln!("Would store an encrypted copy of {:?} at {:?}", original_path, synced_path);
+            } else if mode == SyncMode::Copy {
+                outln!("Would copy {:?} to {:?}", original_path, synced_path);
+            } else if mode == SyncMode::Template {
+                outln!("Would store {:?} as a template at {:?}", original_path, synced_path);
+            } else {
+                outln!("Would link {:?} to {:?}", original_path, synced_path);
+            }
+            if !options.exclude.is_empty() {
+                outln!("Would exclude {} from it", options.exclude.join(", "));
+            }
+            outln!("Would record it in the .gitbox metadata of {:?} and of the repository", current_dir);
+            let name = relative_path.file_name().unwrap_or_default().to_string_lossy();
+            let subject = self.commit_message.clone()
+                .unwrap_or_else(|| format!("Add {} on {}", name, template::machine().host));
+            outln!("Would commit '{}'", subject);
+            self.plan_push(&repo_path);
+            return Ok(());
+        }
+
+        // Create files directory in repo if it doesn't exist
+        if !files_dir.exists() {
+            fs::create_dir_all(&files_dir)
+                .with_context(|| format!("Failed to create files directory: {:?}", files_dir))?;
+        }
+
+        if encrypted {
+            // A link would expose the plaintext, so the repository gets an encrypted copy
+            crypt::encrypt_file(&original_path, &synced_path)?;
+            outln!("Stored encrypted copy: {} -> {}", original_path.display(), synced_path.display());
+        } else if mode == SyncMode::Copy {
+            copy_recursively(&original_path, &synced_path)?;
+            outln!("Stored copy: {} -> {}", original_path.display(), synced_path.display());
+        } else if mode == SyncMode::Template {
+            copy_recursively(&original_path, &synced_path)?;
+            outln!("Stored template: {} -> {}", original_path.display(), synced_path.display());
+            outln!("Edit it there, or add overlays such as {}##host.<hostname>, and sync again to render it", synced_path.display());
+        } else {
+            // Create link (hard link for files, symlink for directories)
+            create_link(&original_path, &synced_path)?;
+        }
+
+        // Update local metadata
+        local_metadata.add_file(&original_path, &synced_path, is_directory, encrypted).mode = mode;
+        local_metadata.repo_name = Some(repo_name.to_string());
+        local_metadata.save_to_dir(&current_dir)?;
+
+        // Update repository metadata
+        let entry = repo_metadata.add_file(&original_path, &synced_path, is_directory, encrypted);
+        entry.exclude = options.exclude.clone();
+        entry.mode = mode;
+        repo_metadata.save_to_dir(&repo_path)?;
+
+        // Commit changes
+        self.commit_repo_changes(&repo_path, None)?;
+        
+        // Push changes to remote repository
+        self.push_repo_changes(&repo_path)?;
+
+        // Update app info with new file count
+        let file_count = list_synced_entries(&files_dir)?.len();
+        self.app_info.update_repository(repo_name, file_count)?;
+
+        Ok(())
+    }
+
+    /// Adds ignore patterns to an already synced directory's repository entry.
+    fn add_excludes(&self, repo_path: &Path, original_path: &Path, patterns: &[String]) -> Result<()> {
+        let mut repo_metadata = GitboxMetadata::load_from_dir(repo_path)?;
+        let Some(info) = repo_metadata.files.get_mut(&original_path.to_string_lossy().to_string()) else {
+            return Err(anyhow::anyhow!("{:?} is not tracked by the repository", original_path));
+        };
+        if !info.is_directory {
+            return Err(anyhow::anyhow!("Cannot exclude paths from {:?}: it is not a directory", original_path));
+        }
+        let new: Vec<&String> = patterns.iter().filter(|pattern| !info.exclude.contains(pattern)).collect();
+        if new.is_empty() {
+            return Ok(());
+        }
+        if self.dry_run {
+            outln!("Would exclude {} from {:?}", patterns.join(", "), original_path);
+            return Ok(());
+        }
+        info.exclude.extend(new.into_iter().cloned());
+        repo_metadata.save_to_dir(repo_path)?;
+        outln!("Excluding {} from {:?}", patterns.join(", "), original_path);
+        Ok(())
+    }
+
+    /// Stops tracking a synced item. The original location is left holding an
+    /// independent regular file or directory, the item is removed from the repository
+    /// (unless `keep_in_repo`), and the removal is committed and pushed.
+    pub fn unsync(&mut self, file_path: &str, repo_name: Option<&str>, keep_in_repo: bool) -> Result<()> {
+        let current_dir = std::env::current_dir()
+            .context("Failed to get current directory")?;
+        let requested = current_dir.join(file_path);
+
+        // Metadata stores canonical paths, but the item itself may be a link into the repo
+        let mut candidates = vec![requested.clone()];
+        if let (Some(parent), Some(name)) = (requested.parent(), requested.file_name()) {
+            if let Ok(parent) = parent.canonicalize() {
+                candidates.push(parent.join(name));
+            }
+        }
+        if let Ok(canonical) = requested.canonicalize() {
+            candidates.push(canonical);
+        }
+
+        let repo_names = match repo_name {
+            Some(name) => vec![self.find_repository(name)?],
+            None => self.list_repos()?,
+        };
+
+        let mut found = None;
+        for name in repo_names {
+            let repo_path = self.config.get_repo_path(&name);
+            let metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+            if let Some(info) = candidates.iter().find_map(|path| metadata.get_file(path).cloned()) {
+                found = Some((name, repo_path, metadata, info));
+                break;
+            }
+        }
+        let (repo_name, repo_path, mut repo_metadata, file_info) = found
+            .ok_or_else(|| anyhow::anyhow!("'{}' is not synced to any repository", file_path))?;
+
+        let original_path = &file_info.original_path;
+        let synced_path = &file_info.synced_path;
+        let files_dir = repo_path.join("files");
+
+        // Leave an independent copy at the original location
+        match fs::symlink_metadata(original_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() || (metadata.is_file() && metadata.nlink() > 1) => {
+                detach_link(original_path)?;
+                outln!("Replaced link at {:?} with a regular copy", original_path);
+            }
+            Ok(_) => {}
+            Err(_) if synced_path.exists() => {
+                copy_recursively(synced_path, original_path)?;
+                outln!("Restored missing {:?} from repository copy", original_path);
+            }
+            Err(_) => {
+                errln!("Warning: {:?} no longer exists and has no repository copy", original_path);
+            }
+        }
+
+        // Remove the item from the repository, or turn its link into a standalone copy
+        if keep_in_repo {
+            if let Ok(metadata) = fs::symlink_metadata(synced_path) {
+                if metadata.file_type().is_symlink() || metadata.nlink() > 1 {
+                    detach_link(synced_path)?;
+                }
+            }
+        } else {
+            remove_synced_item(synced_path, &files_dir)?;
+        }
+
+        repo_metadata.remove_file(original_path);
+        repo_metadata.save_to_dir(&repo_path)?;
+
+        // Drop the entry from local metadata next to the item and in the current directory
+        let mut local_dirs = vec![current_dir.clone()];
+        if let Some(parent) = original_path.parent() {
+            if parent != current_dir {
+                local_dirs.push(parent.to_path_buf());
+            }
+        }
+        for dir in local_dirs {
+            if !dir.join(".gitbox").is_file() {
+                continue;
+            }
+            let mut local_metadata = GitboxMetadata::load_from_dir(&dir)?;
+            if local_metadata.remove_file(original_path).is_some() {
+                local_metadata.save_to_dir(&dir)?;
+            }
+        }
+
+        let relative_path = synced_path.strip_prefix(&files_dir).unwrap_or(synced_path);
+        self.commit_repo_changes(&repo_path, Some(&format!("Stop syncing {}", relative_path.display())))?;
+        self.push_repo_changes(&repo_path)?;
+
+        let file_count = list_synced_entries(&files_dir)?.len();
+        self.app_info.update_repository(&repo_name, file_count)?;
+
+        outln!("Stopped syncing {:?} (repository '{}')", original_path, repo_name);
+        Ok(())
+    }
+
+    /// Checks every tracked item for a broken link (e.g. after an editor replaced the
+    /// file on save) and relinks it. Returns the number of repaired items.
+    pub fn repair(&self, repo_name: Option<&str>) -> Result<usize> {
+        let repo_names = match repo_name {
+            Some(name) => vec![self.find_repository(name)?],
+            None => self.list_repos()?,
+        };
+
+        let mut repaired = 0;
+        for name in repo_names {
+            repaired += self.repair_repo(&name)?;
+        }
+        Ok(repaired)
+    }
+
+    pub fn repair_repo(&self, repo_name: &str) -> Result<usize> {
+        Ok(self.repair_links(&self.config.get_repo_path(repo_name), RepairPolicy::default())?.len())
+    }
+
+    /// Repairs every entry for this machine. Returns the originals of those it repaired.
+    fn repair_links(&self, repo_path: &Path, policy: RepairPolicy) -> Result<Vec<PathBuf>> {
+        let git_repo = Repository::open(repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        let mut metadata = GitboxMetadata::load_from_dir(repo_path)?;
+        if mirror_linked_directories(&mut metadata)? > 0 {
+            metadata.save_to_dir(repo_path)?;
+        }
+        // Copy-mode directories skip ignored files
+        ignore::refresh(repo_path)?;
+
+        // Entries for other machines are neither recreated nor updated here
+        let selection = Selection::new(&metadata, repo_path)?;
+        let mut repaired = Vec::new();
+        for info in metadata.files.values().filter(|info| selection.includes(info)) {
+            match repair_entry(&git_repo, repo_path, info, policy)? {
+                RepairOutcome::Intact => {}
+                RepairOutcome::Repaired(how) => {
+                    outln!("Repaired link for {:?} ({})", info.original_path, how);
+                    repaired.push(info.original_path.clone());
+                }
+                RepairOutcome::Skipped(reason) => {
+                    errln!("Warning: could not repair {:?}: {}", info.original_path, reason);
+                }
+            }
+        }
+        Ok(repaired)
+    }
+
+    /// Every item tracked by each repository that applies to this machine, as
+    /// (repository name, file info) pairs.
+    pub fn tracked_items(&self) -> Result<Vec<(String, FileInfo)>> {
+        let mut items = Vec::new();
+        for repo_name in self.list_repos()? {
+            let repo_path = self.config.get_repo_path(&repo_name);
+            let metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+            let selection = Selection::new(&metadata, &repo_path)?;
+            items.extend(metadata.files.into_values()
+                .filter(|info| selection.includes(info))
+                .map(|info| (repo_name.clone(), info)));
+        }
+        Ok(items)
+    }
+
+    /// Repairs broken links and commits whatever changed in the repository.
+    /// Returns the commit's subject, or `None` if there was nothing to commit.
+    pub fn commit_pending(&self, repo_name: &str) -> Result<Option<String>> {
+        let repo_path = self.config.get_repo_path(repo_name);
+        self.repair_repo(repo_name)?;
+
+        let git_repo = Repository::open(&repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        if !has_uncommitted_changes(&git_repo, None)? {
+            return Ok(None);
+        }
+
+        self.commit_repo_changes(&repo_path, None)
+    }
+
+    pub fn push_repo(&self, repo_name: &str) -> Result<()> {
+        self.push_repo_changes(&self.config.get_repo_path(repo_name))
+    }
+
+    /// Reports the state of every synced item, optionally limited to one repository.
+    /// With `fetch`, remote-tracking branches are updated first so ahead/behind is current.
+    pub fn status(&self, repo_name: Option<&str>, fetch: bool) -> Result<Vec<RepoStatus>> {
+        let repo_names = match repo_name {
+            Some(name) => vec![self.find_repository(name)?],
+            None => self.list_repos()?,
+        };
+
+        let mut statuses = Vec::new();
+        for name in repo_names {
+            let repo_path = self.config.get_repo_path(&name);
+            if fetch {
+                let git_repo = Repository::open(&repo_path)
+                    .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+                if let Err(e) = git::fetch(&git_repo, REMOTE, &self.config.default_branch, &self.auth()) {
+                    errln!("Warning: failed to fetch '{}': {}", name, e);
+                }
+            }
+            statuses.push(repo_status(&name, &repo_path, &self.config.default_branch)?);
+        }
+
+        Ok(statuses)
+    }
+
+    /// Repositories with a merge waiting for conflicts to be resolved.
+    pub fn pending_merges(&self) -> Result<Vec<(String, PendingMerge)>> {
+        let mut merges = Vec::new();
+        for name in self.list_repos()? {
+            let repo_path = self.config.get_repo_path(&name);
+            let Ok(git_repo) = Repository::open(&repo_path) else {
+                continue;
+            };
+            if let Some(pending) = PendingMerge::load(&git_repo)? {
+                merges.push((name, pending));
+            }
+        }
+        Ok(merges)
+    }
+
+    /// Settles one file that conflicted during a pull. Once every conflict in the
+    /// repository has a resolution, the merge is committed, written through to the
+    /// original files and pushed.
+    pub fn resolve(&self, file: &str, repo_name: Option<&str>, choice: Option<ResolveChoice>) -> Result<()> {
+        let (repo_name, path) = self.find_conflict(file, repo_name)?;
+        let repo_path = self.config.get_repo_path(&repo_name);
+        let git_repo = Repository::open(&repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        let mut pending = PendingMerge::load(&git_repo)?
+            .with_context(|| format!("Repository '{}' has no pending merge", repo_name))?;
+        let sides = conflict_sides(&git_repo, &pending, &path)?;
+
+        let choice = match choice {
+            Some(choice) => choice,
+            None => match ask_resolution(&path, &sides)? {
+                Some(choice) => choice,
+                None => {
+                    outln!("Left {} unresolved", path);
+                    return Ok(());
+                }
+            },
+        };
+        let content = match choice {
+            ResolveChoice::Ours => self.local_content(&repo_path, &path)?,
+            ResolveChoice::Theirs => sides.theirs.clone(),
+            ResolveChoice::Edit => Some(edit_conflict(&path, &sides)?),
+            ResolveChoice::Tool => Some(self.run_merge_tool(&path, &sides)?),
+        };
+        record_resolution(&git_repo, &mut pending, &path, content.as_deref())?;
+        outln!("Resolved {} in '{}'", path, repo_name);
+
+        let remaining = pending.unresolved();
+        if !remaining.is_empty() {
+            outln!("Still conflicted: {}", remaining.join(", "));
+            return Ok(());
+        }
+
+        let message = format!("Merge remote changes from origin/{}", self.config.default_branch);
+        finish_merge(&git_repo, &pending, &message)?;
+        outln!("All conflicts in '{}' resolved; merged remote changes", repo_name);
+
+        // Resume the sync that stopped on the conflicts
+        self.push_repo_changes(&repo_path)
+    }
+
+    /// Forgets pending merges, leaving every local file as it is.
+    pub fn abort_merge(&self, repo_name: Option<&str>) -> Result<()> {
+        let repo_names = match repo_name {
+            Some(name) => vec![self.find_repository(name)?],
+            None => self.pending_merges()?.into_iter().map(|(name, _)| name).collect(),
+        };
+        if repo_names.is_empty() {
+            outln!("No pending merges");
+        }
+        for name in repo_names {
+            let git_repo = Repository::open(self.config.get_repo_path(&name))
+                .with_context(|| format!("Failed to open git repository for '{}'", name))?;
+            PendingMerge::discard(&git_repo)?;
+            outln!("Aborted pending merge in '{}'; local files are unchanged", name);
+        }
+        Ok(())
+    }
+
+    /// Finds the conflicted repository path `file` refers to: an original path, a path
+    /// inside the repository (with or without `files/`), or a unique file name.
+    fn find_conflict(&self, file: &str, repo_name: Option<&str>) -> Result<(String, String)> {
+        let merges = match repo_name {
+            Some(name) => {
+                let name = self.find_repository(name)?;
+                self.pending_merges()?.into_iter().filter(|(pending_name, _)| *pending_name == name).collect()
+            }
+            None => self.pending_merges()?,
+        };
+
+        let absolute = std::env::current_dir()
+            .context("Failed to get current directory")?
+            .join(file);
+        let canonical = absolute.canonicalize().unwrap_or_else(|_| absolute.clone());
+
+        let mut exact = Vec::new();
+        let mut by_name = Vec::new();
+        for (name, pending) in merges {
+            let repo_path = self.config.get_repo_path(&name);
+            let metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+            for path in &pending.conflicts {
+                let synced = repo_path.join(path);
+                let original = metadata.files.values()
+                    .find(|info| info.synced_path == synced)
+                    .map(|info| &info.original_path);
+                let in_files = Path::new(path).strip_prefix("files").ok();
+
+                if path == file || in_files == Some(Path::new(file)) || original.is_some_and(|o| *o == absolute || *o == canonical) {
+                    exact.push((name.clone(), path.clone()));
+                } else if Path::new(path).file_name() == Some(std::ffi::OsStr::new(file)) {
+                    by_name.push((name.clone(), path.clone()));
+                }
+            }
+        }
+
+        let candidates = if exact.is_empty() { by_name } else { exact };
+        match candidates.len() {
+            0 => Err(anyhow::anyhow!("No conflict found for '{}'. Run 'gitbox resolve' to list conflicts.", file)),
+            1 => Ok(candidates.into_iter().next().unwrap()),
+            _ => {
+                let listed: Vec<String> = candidates.iter().map(|(name, path)| format!("{} ({})", path, name)).collect();
+                Err(anyhow::anyhow!("'{}' matches several conflicts: {}. Pass the full path or --repo.", file, listed.join(", ")))
+            }
+        }
+    }
+
+    /// The local version of a conflicted path: the user's original file if it is tracked
+    /// (it may have been edited since the pull), otherwise the repository copy.
+    fn local_content(&self, repo_path: &Path, path: &str) -> Result<Option<Vec<u8>>> {
+        let synced = repo_path.join(path);
+        let metadata = GitboxMetadata::load_from_dir(repo_path)?;
+        let source = metadata.files.values()
+            .find(|info| info.synced_path == synced && !info.is_directory)
+            .map(|info| info.original_path.clone())
+            .unwrap_or(synced);
+
+        match fs::symlink_metadata(&source) {
+            Err(_) => Ok(None),
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&source)
+                    .with_context(|| format!("Failed to read symlink: {:?}", source))?;
+                Ok(Some(target.to_string_lossy().as_bytes().to_vec()))
+            }
+            Ok(_) => Ok(Some(fs::read(&source).with_context(|| format!("Failed to read file: {:?}", source))?)),
+        }
+    }
+
+    /// Runs the configured merge tool on a conflicted file and returns the merged result.
+    fn run_merge_tool(&self, path: &str, sides: &ConflictSides) -> Result<Vec<u8>> {
+        let tool = self.config.merge_tool.as_deref().context(
+            "No merge tool configured. Set merge_tool in ~/.gitbox/config.toml, e.g. merge_tool = 'meld \"$LOCAL\" \"$BASE\" \"$REMOTE\" --output \"$MERGED\"'",
+        )?;
+
+        let scratch = std::env::temp_dir().join(format!("gitbox-resolve-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&scratch)
+            .with_context(|| format!("Failed to create directory: {:?}", scratch))?;
+        let file_name = Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let write_side = |label: &str, content: &[u8]| -> Result<PathBuf> {
+            let side_path = scratch.join(format!("{}.{}", label, file_name));
+            fs::write(&side_path, content)
+                .with_context(|| format!("Failed to write file: {:?}", side_path))?;
+            Ok(side_path)
+        };
+        let base = write_side("BASE", sides.base.as_deref().unwrap_or_default())?;
+        let local = write_side("LOCAL", sides.ours.as_deref().unwrap_or_default())?;
+        let remote = write_side("REMOTE", sides.theirs.as_deref().unwrap_or_default())?;
+        let merged = write_side("MERGED", &sides.merged)?;
+
+        let result = std::process::Command::new("sh")
+            .args(["-c", tool])
+            .env("BASE", &base)
+            .env("LOCAL", &local)
+            .env("REMOTE", &remote)
+            .env("MERGED", &merged)
+            .status()
+            .context("Failed to run merge tool")
+            .and_then(|status| {
+                if !status.success() {
+                    return Err(anyhow::anyhow!("Merge tool exited with {}; {} left unresolved", status, path));
+                }
+                read_resolved(&merged)
+            });
+
+        let _ = fs::remove_dir_all(&scratch);
+        result
+    }
+
+    /// Rewrites legacy `.gitbox` files that still contain absolute paths into the
+    /// portable `$ROOT/...` form, committing and pushing each migrated repository.
+    pub fn migrate_metadata(&mut self) -> Result<()> {
+        let mut migrated = 0;
+
+        for repo_name in self.list_repos()? {
+            let repo_path = self.config.get_repo_path(&repo_name);
+            let metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+            if !metadata.needs_migration() {
+                continue;
+            }
+
+            metadata.save_to_dir(&repo_path)?;
+            self.commit_repo_changes(&repo_path, Some("Migrate .gitbox to portable paths"))?;
+            if let Err(e) = self.push_repo_changes(&repo_path) {
+                errln!("Warning: migrated '{}' locally but failed to push: {}", repo_name, e);
+            }
+            outln!("Migrated metadata in repository '{}'", repo_name);
+            migrated += 1;
+        }
+
+        // Local metadata in the current directory is never committed, just rewritten
+        let current_dir = std::env::current_dir()
+            .context("Failed to get current directory")?;
+        let local_metadata = GitboxMetadata::load_from_dir(&current_dir)?;
+        if local_metadata.needs_migration() && current_dir.join(".gitbox").is_file() {
+            local_metadata.save_to_dir(&current_dir)?;
+            outln!("Migrated local metadata in {:?}", current_dir);
+            migrated += 1;
+        }
+
+        if migrated == 0 {
+            outln!("All metadata already uses portable paths");
+        }
+
+        Ok(())
+    }
+
+    /// Lets a flagged secret (by fingerprint) or every file matching a path pattern
+    /// be committed to `repo_name`. The allowance is stored in the repository's `.gitbox`.
+    pub fn allow_secret(&self, entry: &str, repo_name: &str) -> Result<()> {
+        let repo_name = self.find_repository(repo_name)?;
+        let repo_path = self.config.get_repo_path(&repo_name);
+        if !entry.starts_with("secret:") {
+            glob_set(&[entry.to_string()])?;
+        }
+
+        let mut metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+        if metadata.allowed_secrets.iter().any(|allowed| allowed == entry) {
+            outln!("'{}' is already allowed in repository '{}'", entry, repo_name);
+            return Ok(());
+        }
+        if self.dry_run {
+            outln!("Would allow '{}' to be committed to repository '{}'", entry, repo_name);
+            return Ok(());
+        }
+        metadata.allowed_secrets.push(entry.to_string());
+        metadata.save_to_dir(&repo_path)?;
+        outln!("Allowed '{}' to be committed to repository '{}'", entry, repo_name);
+        Ok(())
+    }
+
+    /// Creates the profile `name` in `repo_name`, or adds the given machines and file
+    /// patterns to it. Profiles are stored in the repository's `.gitbox`.
+    pub fn set_profile(&self, name: &str, repo_name: &str, added: Profile) -> Result<()> {
+        let repo_name = self.find_repository(repo_name)?;
+        let repo_path = self.config.get_repo_path(&repo_name);
+        glob_set(&added.files)?;
+
+        let mut metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+        let exists = metadata.profiles.contains_key(name);
+        let mut profile = metadata.profiles.get(name).cloned().unwrap_or_default();
+        profile.extend(added);
+        if exists && metadata.profiles.get(name) == Some(&profile) {
+            outln!("Profile '{}' in repository '{}' is unchanged", name, repo_name);
+            return Ok(());
+        }
+        let verb = match (self.dry_run, exists) {
+            (true, true) => "Would update",
+            (true, false) => "Would create",
+            (false, true) => "Updated",
+            (false, false) => "Created",
+        };
+        outln!("{} profile '{}' in repository '{}': {}", verb, name, repo_name, describe_profile(&profile));
+        if self.dry_run {
+            return Ok(());
+        }
+        metadata.profiles.insert(name.to_string(), profile);
+        metadata.save_to_dir(&repo_path)?;
+        Ok(())
+    }
+
+    /// Describes this machine and, for `repo_name` or every repository, its profiles
+    /// and the entries they keep from this machine.
+    pub fn profile_report(&self, repo_name: Option<&str>) -> Result<String> {
+        let repo_names = match repo_name {
+            Some(name) => vec![self.find_repository(name)?],
+            None => self.list_repos()?,
+        };
+
+        let machine = template::machine();
+        let mut report = format!("This machine: host {}, os {}, user {}", machine.host, machine.os, machine.user);
+        if !machine.tags.is_empty() {
+            report.push_str(&format!(", tags {}", machine.tags.join(", ")));
+        }
+        for name in repo_names {
+            let repo_path = self.config.get_repo_path(&name);
+            let metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+            report.push_str(&format!("\nRepository '{}':", name));
+            if metadata.profiles.is_empty() {
+                report.push_str("\n  No profiles; every entry applies to every machine");
+                continue;
+            }
+            for (profile_name, profile) in &metadata.profiles {
+                let state = if profile.is_active() { "active" } else { "inactive" };
+                report.push_str(&format!("\n  {} ({}): {}", profile_name, state, describe_profile(profile)));
+            }
+            let selection = Selection::new(&metadata, &repo_path)?;
+            let skipped: Vec<&FileInfo> = metadata.files.values()
+                .filter(|info| !selection.includes(info))
+                .collect();
+            if !skipped.is_empty() {
+                report.push_str("\n  Not used on this machine:");
+            }
+            for info in skipped {
+                report.push_str(&format!("\n    {} ({})", info.original_path.display(), selection.reason(info)));
+            }
+        }
+        Ok(report)
+    }
 
-        // Update local metadata
-        let mut local_metadata = GitboxMetadata::load_from_dir(&current_dir)?;
-        local_metadata.add_file(&destination_path, &synced_file_path, is_directory);
-        local_metadata.repo_name = Some(repo_name.to_string());
-        local_metadata.save_to_dir(&current_dir)?;
+    /// The commits that changed the synced item `file`, newest first.
+    pub fn file_log(&self, file: &str, repo_name: Option<&str>) -> Result<Vec<Change>> {
+        let (repo_path, item) = self.find_tracked(file, repo_name)?;
+        let git_repo = Repository::open(&repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        history::changes(&git_repo, &item.repo_path)
+    }
 
-        Ok(())
+    /// A unified diff of the synced item `file` from its version in `rev` to what is on
+    /// this machine now.
+    pub fn file_diff(&self, file: &str, rev: &str, repo_name: Option<&str>) -> Result<String> {
+        let (repo_path, item) = self.find_tracked(file, repo_name)?;
+        let git_repo = Repository::open(&repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        let commit = history::resolve_commit(&git_repo, rev)?;
+        let old = item.readable(item.stored_at(&git_repo, &commit)?)?;
+        item.diff(&old, &item.current(&git_repo)?)
     }
 
-    pub fn sync_file(&mut self, file_path: &str, repo_name: &str) -> Result<()> {
-        let repo_path = self.config.get_repo_path(repo_name);
-        if !repo_path.exists() {
-            return Err(anyhow::anyhow!("Repository '{}' does not exist", repo_name));
+    /// Puts the version of the synced item `file` from `rev` back in place (the original
+    /// too, through its hard link), then commits and pushes the rollback.
+    pub fn restore_version(&self, file: &str, rev: &str, repo_name: Option<&str>) -> Result<()> {
+        let (repo_path, item) = self.find_tracked(file, repo_name)?;
+        let git_repo = Repository::open(&repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        let commit = history::resolve_commit(&git_repo, rev)?;
+        let stored = item.stored_at(&git_repo, &commit)?;
+        if stored.is_empty() {
+            return Err(anyhow::anyhow!("{} is not in the repository at {}", item.repo_path.display(), rev));
         }
+        let short_id = commit.as_object().short_id()?.as_str().unwrap_or_default().to_string();
 
-        let original_path = PathBuf::from(file_path);
-        if !original_path.exists() {
-            return Err(anyhow::anyhow!("File or directory does not exist: {}", file_path));
+        if self.dry_run {
+            outln!("Would restore {:?} to its version from {} ({})", item.local, short_id, commit.summary().unwrap_or_default());
+            return Ok(());
         }
-
-        let original_path = original_path.canonicalize()
-            .with_context(|| format!("Failed to canonicalize path: {}", file_path))?;
-
-        // Load metadata from the current directory
-        let current_dir = std::env::current_dir()
-            .context("Failed to get current directory")?;
-        let mut local_metadata = GitboxMetadata::load_from_dir(&current_dir)?;
-
-        // Check if file is already synced
-        let already_synced = local_metadata.get_file(&original_path).is_some();
-        
-        if already_synced {
-            // File is already synced, check if hard link is still intact
-            let file_info = local_metadata.get_file(&original_path).unwrap();
-            let synced_path = &file_info.synced_path;
-            
-            // Check if files are still hard linked by comparing inodes
-            let original_metadata = fs::metadata(&original_path)?;
-            let synced_metadata = fs::metadata(synced_path)?;
-            
-            let links_intact = original_metadata.ino() == synced_metadata.ino();
-            
-            if !links_intact {
-                println!("File is already synced but hard link was broken. Re-copying content...");
-                
-                // Copy the updated content to the synced location
-                fs::copy(&original_path, synced_path)?;
-                
-                // Recreate the hard link
-                let _ = fs::remove_file(&original_path);
-                fs::hard_link(synced_path, &original_path)?;
-                
-                println!("Restored hard link between files");
-            } else {
-                println!("File is already synced. Committing updated content...");
-            }
-            
-            // Commit changes
-            self.commit_repo_changes(&repo_path, &format!("Update file: {}", original_path.file_name().unwrap().to_string_lossy()))?;
-            
-            // Push changes to remote repository
-            self.push_repo_changes(&repo_path)?;
-            
+        let changed = item.write_back(&git_repo, &stored)?;
+        if changed == 0 {
+            outln!("{:?} already matches its version from {}", item.local, short_id);
             return Ok(());
         }
+        outln!("Restored {} file(s) of {:?} to their version from {}", changed, item.local, short_id);
 
-        // Create symlink path in repository
-        let file_name = original_path.file_name()
-            .context("Failed to get file name")?;
-        let synced_path = repo_path.join("files").join(file_name);
+        let relative = item.repo_path.strip_prefix("files").unwrap_or(&item.repo_path);
+        self.commit_repo_changes(&repo_path, Some(&format!("Restore {} to {}", relative.display(), short_id)))?;
+        self.push_repo_changes(&repo_path)
+    }
 
-        // Create files directory in repo if it doesn't exist
-        let files_dir = repo_path.join("files");
-        if !files_dir.exists() {
-            fs::create_dir_all(&files_dir)
-                .with_context(|| format!("Failed to create files directory: {:?}", files_dir))?;
+    /// Finds the synced item `file` names: a local path of a tracked item or of a file
+    /// inside a tracked directory, or a path under a repository's `files/`.
+    fn find_tracked(&self, file: &str, repo_name: Option<&str>) -> Result<(PathBuf, history::Item)> {
+        let requested = std::env::current_dir()
+            .context("Failed to get current directory")?
+            .join(file);
+        // Metadata stores canonical paths, but the item itself may be a link into the repo
+        let mut candidates = vec![requested.clone()];
+        if let (Some(parent), Some(name)) = (requested.parent(), requested.file_name()) {
+            if let Ok(parent) = parent.canonicalize() {
+                candidates.push(parent.join(name));
+            }
+        }
+        if let Ok(canonical) = requested.canonicalize() {
+            candidates.push(canonical);
         }
 
-        // Create link (hard link for files, symlink for directories)
-        let is_directory = original_path.is_dir();
-        create_link(&original_path, &synced_path)?;
-
-        // Update local metadata
-        local_metadata.add_file(&original_path, &synced_path, is_directory);
-        local_metadata.repo_name = Some(repo_name.to_string());
-        local_metadata.save_to_dir(&current_dir)?;
-
-        // Update repository metadata
-        let mut repo_metadata = GitboxMetadata::load_from_dir(&repo_path)?;
-        repo_metadata.add_file(&original_path, &synced_path, is_directory);
-        repo_metadata.save_to_dir(&repo_path)?;
-
-        // Commit changes
-        self.commit_repo_changes(&repo_path, &format!("Add file: {}", file_name.to_string_lossy()))?;
-        
-        // Push changes to remote repository
-        self.push_repo_changes(&repo_path)?;
-
-        // Update app info with new file count
-        let files_dir = repo_path.join("files");
-        let file_count = if files_dir.exists() {
-            fs::read_dir(&files_dir)
-                .map(|entries| entries.count())
-                .unwrap_or(0)
-        } else {
-            0
+        let repo_names = match repo_name {
+            Some(name) => vec![self.find_repository(name)?],
+            None => self.list_repos()?,
         };
-        self.app_info.update_repository(repo_name, file_count)?;
-
-        Ok(())
+        for name in repo_names {
+            let repo_path = self.config.get_repo_path(&name);
+            let in_repo = repo_path.join("files").join(file);
+            let metadata = GitboxMetadata::load_from_dir(&repo_path)?;
+            for info in metadata.files.values() {
+                let inside = |path: &Path, base: &Path| {
+                    path.strip_prefix(base).ok()
+                        .filter(|rest| rest.as_os_str().is_empty() || info.is_directory)
+                        // `dir/../../x` starts with `dir` but does not stay inside it
+                        .filter(|rest| rest.as_os_str().is_empty() || validate_repo_relative(rest).is_ok())
+                        .map(Path::to_path_buf)
+                };
+                let rest = candidates.iter()
+                    .find_map(|candidate| inside(candidate, &info.original_path))
+                    .or_else(|| inside(&in_repo, &info.synced_path));
+                let Some(rest) = rest else {
+                    continue;
+                };
+                let synced_relative = info.synced_path.strip_prefix(&repo_path).unwrap_or(&info.synced_path);
+                let join = |base: &Path| if rest.as_os_str().is_empty() { base.to_path_buf() } else { base.join(&rest) };
+                let item = history::Item {
+                    repo_path: join(synced_relative),
+                    local: join(&info.original_path),
+                    synced: join(&info.synced_path),
+                    info: info.clone(),
+                };
+                return Ok((repo_path, item));
+            }
+        }
+        Err(anyhow::anyhow!("'{}' is not synced to any repository", file))
     }
 
     pub fn list_repos(&self) -> Result<Vec<String>> {
@@ -547,9 +1510,7 @@ impl RepoManager {
         
         // If repository doesn't exist, create it
         if !repo_path.exists() {
-            println!("Repository '{}' doesn't exist. Creating it...", repo_name);
-            self.add_repo(repo_name).await?;
-            println!("Repository '{}' created successfully", repo_name);
+            self.create_missing_repo(repo_name).await?;
         }
 
         // First sync with remote to get latest files
@@ -561,17 +1522,10 @@ impl RepoManager {
             return Ok(vec![]);
         }
 
-        let mut files = vec![];
-        for entry in fs::read_dir(&files_dir)
-            .with_context(|| format!("Failed to read files directory: {:?}", files_dir))? {
-            let entry = entry.context("Failed to read directory entry")?;
-            let path = entry.path();
-            if let Some(name) = path.file_name() {
-                files.push(name.to_string_lossy().to_string());
-            }
-        }
-
-        files.sort();
+        let files = list_synced_entries(&files_dir)?
+            .into_iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
         Ok(files)
     }
 
@@ -580,9 +1534,7 @@ impl RepoManager {
         
         // If repository doesn't exist, create it
         if !repo_path.exists() {
-            println!("Repository '{}' doesn't exist. Creating it...", repo_name);
-            self.add_repo(repo_name).await?;
-            println!("Repository '{}' created successfully", repo_name);
+            self.create_missing_repo(repo_name).await?;
         }
 
         if let Some(file) = file_path {
@@ -596,50 +1548,25 @@ impl RepoManager {
             }
 
             // Use the existing sync logic to sync the specific file
-            self.sync_file_with_default(file, repo_name).await?;
-            println!("File '{}' pushed to repository '{}'", file, repo_name);
+            self.sync_file_with_default(file, repo_name, &SyncOptions::default()).await?;
+            if !self.dry_run {
+                outln!("File '{}' pushed to repository '{}'", file, repo_name);
+            }
+        } else if self.dry_run {
+            self.plan_commit_and_push(&repo_path)?;
         } else {
-            // Handle repository-wide push (existing logic)
-            let status_output = std::process::Command::new("git")
-                .args(&["status", "--porcelain"])
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to execute git status")?;
-
-            if !status_output.status.success() {
-                return Err(anyhow::anyhow!("Failed to check git status"));
-            }
-
-            let has_changes = !status_output.stdout.is_empty();
-
-            if has_changes {
-                // Add all changes
-                let add_output = std::process::Command::new("git")
-                    .args(&["add", "."])
-                    .current_dir(&repo_path)
-                    .output()
-                    .context("Failed to execute git add")?;
-
-                if !add_output.status.success() {
-                    let stderr = String::from_utf8_lossy(&add_output.stderr);
-                    return Err(anyhow::anyhow!("Failed to add changes: {}", stderr));
-                }
-
-                // Commit changes
-                let commit_output = std::process::Command::new("git")
-                    .args(&["commit", "-m", "Push local changes to remote"])
-                    .current_dir(&repo_path)
-                    .output()
-                    .context("Failed to execute git commit")?;
+            let git_repo = Repository::open(&repo_path)
+                .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+            ensure_no_pending_merge(&git_repo)?;
 
-                if !commit_output.status.success() {
-                    let stderr = String::from_utf8_lossy(&commit_output.stderr);
-                    return Err(anyhow::anyhow!("Failed to commit changes: {}", stderr));
-                }
+            // Reconnect files whose links were broken so their edits are included
+            self.repair_repo(repo_name)?;
 
-                println!("Committed local changes");
+            // Handle repository-wide push
+            if let Some(subject) = self.commit_repo_changes(&repo_path, None)? {
+                outln!("Committed local changes: {}", subject);
             } else {
-                println!("No local changes to push");
+                outln!("No local changes to push");
             }
 
             // Push to remote repository
@@ -649,21 +1576,10 @@ impl RepoManager {
         Ok(())
     }
 
-    pub async fn sync_pull(&mut self, repo_name: &str, file_name: &str) -> Result<()> {
-        let repo_path = self.config.get_repo_path(repo_name);
-        
-        // If repository doesn't exist, create it
-        if !repo_path.exists() {
-            println!("Repository '{}' doesn't exist. Creating it...", repo_name);
-            self.add_repo(repo_name).await?;
-            println!("Repository '{}' created successfully", repo_name);
-        }
-
-        // Pull from remote to get latest changes (without pushing)
-        self.pull_from_remote(&repo_path)?;
-
-        // Now sync the specific file from remote to current directory
-        self.sync_from_remote(file_name, repo_name).await?;
+    pub async fn sync_pull(&mut self, repo_name: &str, file_name: &str, options: PullOptions) -> Result<()> {
+        // Pulls from remote (creating the repository if needed), then syncs the
+        // specific file from remote to current directory
+        self.sync_from_remote(file_name, repo_name, options).await?;
 
         Ok(())
     }
@@ -681,18 +1597,50 @@ impl RepoManager {
             return Ok(vec![]);
         }
 
-        let mut files = vec![];
-        for entry in fs::read_dir(&files_dir)
-            .with_context(|| format!("Failed to read files directory: {:?}", files_dir))? {
-            let entry = entry.context("Failed to read directory entry")?;
-            let path = entry.path();
-            if let Some(name) = path.file_name() {
-                files.push(name.to_string_lossy().to_string());
-            }
+        let files = list_synced_entries(&files_dir)?
+            .into_iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        Ok(files)
+    }
+
+    /// Resolves `name` to a path relative to the repository's `files/` directory.
+    ///
+    /// `name` may be the full relative path (e.g. `.config/nvim/init.vim`) or just a
+    /// file name, as long as exactly one synced item carries that name.
+    fn resolve_repo_file(&self, repo_path: &Path, name: &str, repo_name: &str) -> Result<PathBuf> {
+        let requested = PathBuf::from(name);
+        validate_repo_relative(&requested)?;
+
+        let files_dir = repo_path.join("files");
+        if files_dir.join(&requested).exists() {
+            return Ok(requested);
         }
 
-        files.sort();
-        Ok(files)
+        // Synced directories are found by name as well as the files inside them
+        let mut entries = list_synced_entries(&files_dir)?;
+        entries.extend(GitboxMetadata::load_from_dir(repo_path)?.files.values()
+            .filter(|info| info.is_directory)
+            .filter_map(|info| info.synced_path.strip_prefix(&files_dir).ok().map(Path::to_path_buf)));
+        let matches: Vec<PathBuf> = entries
+            .into_iter()
+            .filter(|path| path.file_name() == requested.file_name() && path.ends_with(&requested))
+            .collect();
+
+        match matches.len() {
+            0 => Err(anyhow::anyhow!("File '{}' not found in repository '{}'", name, repo_name)),
+            1 => Ok(matches.into_iter().next().unwrap()),
+            _ => {
+                let candidates: Vec<String> = matches.iter()
+                    .map(|path| path.to_string_lossy().to_string())
+                    .collect();
+                Err(anyhow::anyhow!("Multiple files match '{}' in repository '{}': {}. Please use the full path.",
+                    name,
+                    repo_name,
+                    candidates.join(", ")
+                ))
+            }
+        }
     }
 
     fn find_repository(&self, partial_name: &str) -> Result<String> {
@@ -783,15 +1731,8 @@ impl RepoManager {
         }
 
         // Get file count
-        let files_dir = repo_path.join("files");
-        if files_dir.exists() {
-            let file_count = fs::read_dir(&files_dir)
-                .map(|entries| entries.count())
-                .unwrap_or(0);
-            info.push_str(&format!("Synced files: {}\n", file_count));
-        } else {
-            info.push_str("Synced files: 0\n");
-        }
+        let file_count = list_synced_entries(&repo_path.join("files"))?.len();
+        info.push_str(&format!("Synced files: {}\n", file_count));
 
         // Load metadata
         if let Ok(metadata) = GitboxMetadata::load_from_dir(&repo_path) {
@@ -799,7 +1740,12 @@ impl RepoManager {
             if !metadata.files.is_empty() {
                 info.push_str("Files:\n");
                 for (original_path, file_info) in &metadata.files {
-                    let file_type = if file_info.is_directory { "dir" } else { "file" };
+                    let mut file_type = if file_info.is_directory { "dir" } else { "file" }.to_string();
+                    if file_info.encrypted {
+                        file_type.push_str(", encrypted");
+                    } else if file_info.mode != SyncMode::Link {
+                        file_type.push_str(&format!(", {}", file_info.mode));
+                    }
                     info.push_str(&format!("  {} -> {} ({})\n", 
                         original_path,
                         file_info.synced_path.display(),
@@ -812,7 +1758,7 @@ impl RepoManager {
         Ok(info.trim_end().to_string())
     }
 
-    pub fn sync_repo(&self, repo_name: &str) -> Result<()> {
+    pub fn sync_repo(&self, repo_name: &str) -> Result<SyncReport> {
         // Try to find the repository with fuzzy matching
         let actual_repo_name = self.find_repository(repo_name)?;
         let repo_path = self.config.get_repo_path(&actual_repo_name);
@@ -821,322 +1767,547 @@ impl RepoManager {
         }
 
         if actual_repo_name != repo_name {
-            println!("Found repository '{}' matching '{}'", actual_repo_name, repo_name);
+            outln!("Found repository '{}' matching '{}'", actual_repo_name, repo_name);
         }
 
-        // Check if remote origin exists
-        let remote_check = std::process::Command::new("git")
-            .args(&["remote", "get-url", "origin"])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to check remote origin")?;
+        let git_repo = Repository::open(&repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        ensure_no_pending_merge(&git_repo)?;
 
-        if !remote_check.status.success() {
+        if git::remote_url(&git_repo, REMOTE).is_none() {
             return Err(anyhow::anyhow!("Repository '{}' has no remote origin configured. Please run 'gitbox add-repo {}' first or manually configure the remote.", actual_repo_name, actual_repo_name));
         }
+        if self.dry_run {
+            self.plan_commit_and_push(&repo_path)?;
+            return Ok(SyncReport::default());
+        }
 
-        // Check if we're on the default branch, create it if it doesn't exist
-        let branch_check = std::process::Command::new("git")
-            .args(&["rev-parse", "--verify", &self.config.default_branch])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to check current branch")?;
-
-        if !branch_check.status.success() {
-            // Create the default branch if it doesn't exist
-            let create_branch = std::process::Command::new("git")
-                .args(&["checkout", "-b", &self.config.default_branch])
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to create default branch")?;
-
-            if !create_branch.status.success() {
-                let stderr = String::from_utf8_lossy(&create_branch.stderr);
-                return Err(anyhow::anyhow!("Failed to create branch '{}': {}", self.config.default_branch, stderr));
-            }
-            println!("Created branch '{}'", self.config.default_branch);
-        }
-
-        // First, try to pull from remote to get latest changes
-        let pull_output = std::process::Command::new("git")
-            .args(&["pull", "--no-rebase", "--allow-unrelated-histories", "origin", &self.config.default_branch])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to execute git pull")?;
-
-        if !pull_output.status.success() {
-            let stderr = String::from_utf8_lossy(&pull_output.stderr);
-            // If pull fails due to no upstream, set it up
-            if stderr.contains("no upstream") || stderr.contains("couldn't find remote ref") {
-                println!("Setting up upstream branch...");
-            } else {
-                eprintln!("Warning: git pull failed: {}", stderr);
-            }
+        // Reconnect files whose links were broken so their edits are included
+        self.repair_repo(&actual_repo_name)?;
+        self.ensure_default_branch(&git_repo)?;
+
+        // Commit local changes first
+        if let Some(subject) = self.commit_repo_changes(&repo_path, None)? {
+            outln!("Committed local changes: {}", subject);
         } else {
-            println!("Pulled latest changes from GitHub");
+            outln!("No local changes to commit");
         }
 
-        // Check if there are any changes to commit
-        let status_output = std::process::Command::new("git")
-            .args(&["status", "--porcelain"])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to execute git status")?;
-
-        if !status_output.status.success() {
-            return Err(anyhow::anyhow!("Failed to check git status"));
+        // Merge remote changes; local ones are committed first so they are never overwritten
+        let previous_head = head_commit_id(&repo_path);
+        match self.merge_remote_changes(&repo_path)? {
+            None => outln!("Setting up upstream branch..."),
+            Some(MergeOutcome::UpToDate) => {}
+            Some(_) => outln!("Pulled latest changes from the remote"),
         }
+        let pulled = match self.tracking_id(&git_repo) {
+            Some(upstream) => history::commits_since(&git_repo, upstream, previous_head)?,
+            None => 0,
+        };
 
-        let has_changes = !status_output.stdout.is_empty();
+        let pushed = self.push_with_retry(&git_repo)?;
 
-        if has_changes {
-            // Add all changes
-            let add_output = std::process::Command::new("git")
-                .args(&["add", "."])
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to execute git add")?;
+        // Pulled changes rewrite repository files; bring them out to the originals
+        let mut written = changed_originals(&git_repo, &repo_path, previous_head)?;
+        written.extend(self.repair_links(&repo_path, RepairPolicy { baseline: previous_head, ..Default::default() })?);
 
-            if !add_output.status.success() {
-                let stderr = String::from_utf8_lossy(&add_output.stderr);
-                return Err(anyhow::anyhow!("Failed to add changes: {}", stderr));
-            }
+        Ok(SyncReport { pulled, pushed, written })
+    }
 
-            // Commit changes
-            let commit_output = std::process::Command::new("git")
-                .args(&["commit", "-m", "Update synced files"])
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to execute git commit")?;
+    /// Paths with unresolved conflicts from the last pull into `repo_name`.
+    pub fn unresolved_conflicts(&self, repo_name: &str) -> Result<Vec<String>> {
+        let repo_path = self.config.get_repo_path(repo_name);
+        let Ok(git_repo) = Repository::open(&repo_path) else {
+            return Ok(Vec::new());
+        };
+        Ok(PendingMerge::load(&git_repo)?
+            .map(|pending| pending.unresolved().into_iter().map(str::to_string).collect())
+            .unwrap_or_default())
+    }
+
+    /// Prints what committing every local change and pushing would do.
+    fn plan_commit_and_push(&self, repo_path: &Path) -> Result<()> {
+        // A dry run may have only planned to create the repository
+        let Ok(git_repo) = Repository::open(repo_path) else {
+            self.plan_push(repo_path);
+            return Ok(());
+        };
+        ensure_no_pending_merge(&git_repo)?;
+        ignore::apply(&git_repo)?;
 
-            if !commit_output.status.success() {
-                let stderr = String::from_utf8_lossy(&commit_output.stderr);
-                return Err(anyhow::anyhow!("Failed to commit changes: {}", stderr));
+        let metadata = GitboxMetadata::load_from_dir(repo_path)?;
+        for info in metadata.files.values() {
+            if info.encrypted || !info.original_path.exists() {
+                continue;
+            }
+            if info.mode == SyncMode::Copy {
+                if copies_differ(&git_repo, repo_path, info)? {
+                    outln!("Would copy changes between {:?} and its repository copy", info.original_path);
+                }
+            } else if info.mode == SyncMode::Template {
+                if fs::read(&info.original_path).ok() != Some(template::render_file(&info.synced_path)?) {
+                    outln!("Would render {:?} into {:?} unless it was edited locally", info.synced_path, info.original_path);
+                }
+            } else if !info.is_directory && !same_file(&info.original_path, &info.synced_path) {
+                outln!("Would repair the broken link for {:?}", info.original_path);
             }
+        }
 
-            println!("Committed local changes");
+        let changed = uncommitted_paths(&git_repo, None)?;
+        if changed.is_empty() {
+            outln!("No local changes to commit");
         } else {
-            println!("No local changes to commit");
+            match &self.commit_message {
+                Some(subject) => outln!("Would commit '{}' with changes to: {}", subject, changed.join(", ")),
+                None => outln!("Would commit changes to: {}", changed.join(", ")),
+            }
         }
+        self.plan_push(repo_path);
+        Ok(())
+    }
 
-        // Push to remote (with upstream setup if needed)
-        let push_output = std::process::Command::new("git")
-            .args(&["push", "-u", "origin", &self.config.default_branch])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to execute git push")?;
+    fn plan_push(&self, repo_path: &Path) {
+        let remote_url = Repository::open(repo_path).ok()
+            .and_then(|git_repo| git::remote_url(&git_repo, REMOTE));
+        match remote_url {
+            Some(url) => outln!("Would merge remote changes and push to {}", url),
+            None => outln!("Would push to the new remote repository"),
+        }
+    }
 
-        if !push_output.status.success() {
-            let stderr = String::from_utf8_lossy(&push_output.stderr);
-            
-            // If push was rejected due to non-fast-forward, try to merge and push again
-            if stderr.contains("non-fast-forward") || stderr.contains("rejected") {
-                println!("Push rejected, pulling and merging remote changes...");
-                
-                // Pull with merge strategy, allowing unrelated histories
-                let pull_merge_output = std::process::Command::new("git")
-                    .args(&["pull", "--no-rebase", "--allow-unrelated-histories", "origin", &self.config.default_branch])
-                    .current_dir(&repo_path)
-                    .output()
-                    .context("Failed to execute git pull for merge")?;
-                
-                if !pull_merge_output.status.success() {
-                    let pull_stderr = String::from_utf8_lossy(&pull_merge_output.stderr);
-                    return Err(anyhow::anyhow!("Failed to pull and merge: {}", pull_stderr));
-                }
-                
-                // Try push again
-                let retry_push_output = std::process::Command::new("git")
-                    .args(&["push", "origin", &self.config.default_branch])
-                    .current_dir(&repo_path)
-                    .output()
-                    .context("Failed to execute retry git push")?;
-                
-                if !retry_push_output.status.success() {
-                    let retry_stderr = String::from_utf8_lossy(&retry_push_output.stderr);
-                    return Err(anyhow::anyhow!("Failed to push after merge: {}", retry_stderr));
-                }
-                
-                println!("Successfully merged and pushed changes");
+    fn pull_from_remote(&self, repo_path: &Path, options: PullOptions) -> Result<()> {
+        let mut git_repo = Repository::open(repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        if git::remote_url(&git_repo, REMOTE).is_none() {
+            return Err(anyhow::anyhow!("Repository has no remote origin configured."));
+        }
+        self.ensure_default_branch(&git_repo)?;
+
+        // Never lose edits to tracked files (they are hard links to the user's real files)
+        ignore::refresh(repo_path)?;
+        let dirty_files = uncommitted_paths(&git_repo, None)?;
+        let mut stashed = false;
+        if !dirty_files.is_empty() {
+            let strategy = options.on_dirty.unwrap_or(self.config.on_dirty_pull);
+            if options.force {
+                errln!("Warning: discarding local changes to: {}", dirty_files.join(", "));
+                save_linked_originals(repo_path, &dirty_files, "discarded by sync-pull --force")?;
+                let head = git_repo.head()
+                    .and_then(|head| head.peel(ObjectType::Commit))
+                    .context("Failed to get HEAD")?;
+                git_repo.reset(&head, ResetType::Hard, None)
+                    .context("Failed to reset local changes")?;
             } else {
-                return Err(anyhow::anyhow!("Failed to push to GitHub: {}", stderr));
+                match strategy {
+                    DirtyPullStrategy::Commit => {
+                        self.commit_repo_changes(repo_path, None)?;
+                        outln!("Committed local changes to: {}", dirty_files.join(", "));
+                    }
+                    DirtyPullStrategy::Stash => {
+                        let signature = commit::signature(&git_repo)?;
+                        git_repo.stash_save(&signature, "gitbox: local changes before pull", Some(StashFlags::INCLUDE_UNTRACKED))
+                            .context("Failed to stash local changes")?;
+                        outln!("Stashed local changes to: {}", dirty_files.join(", "));
+                        stashed = true;
+                    }
+                    DirtyPullStrategy::Abort => {
+                        return Err(anyhow::anyhow!(
+                            "Pull aborted: repository has uncommitted changes to:\n  {}\nPush them with 'gitbox sync-push', choose --on-dirty commit|stash, or pass --force to discard them.",
+                            dirty_files.join("\n  ")
+                        ));
+                    }
+                }
             }
-        } else {
-            println!("Pushed changes to GitHub");
         }
 
+        // Merge remote changes to get the latest versions
+        let previous_head = head_commit_id(repo_path);
+        match self.merge_remote_changes(repo_path)? {
+            None => return Err(anyhow::anyhow!("No upstream branch found. Repository may not be properly initialized.")),
+            Some(MergeOutcome::UpToDate) => {}
+            Some(_) => outln!("Pulled latest changes from the remote"),
+        }
+
+        if stashed {
+            if let Err(e) = git_repo.stash_pop(0, None) {
+                return Err(anyhow::anyhow!(
+                    "Pulled, but restoring your stashed changes failed: {}\nThey are kept in the stash of {:?}.",
+                    e.message(),
+                    repo_path
+                ));
+            }
+            outln!("Restored stashed local changes");
+        }
+
+        // Checkout and stash rewrite repository files, which breaks their hard links
+        self.repair_links(repo_path, RepairPolicy {
+            baseline: previous_head,
+            prefer_repository: options.force,
+        })?;
+
         Ok(())
     }
 
-    fn pull_from_remote(&self, repo_path: &Path) -> Result<()> {
-        // Check if remote origin exists
-        let remote_check = std::process::Command::new("git")
-            .args(&["remote", "get-url", "origin"])
-            .current_dir(repo_path)
-            .output()
-            .context("Failed to check remote origin")?;
+    /// Fetches the default branch from origin and merges it into HEAD. Returns `None`
+    /// if the remote does not have the branch yet.
+    ///
+    /// On conflicts nothing is written and an error explains how to run `gitbox resolve`.
+    fn merge_remote_changes(&self, repo_path: &Path) -> Result<Option<MergeOutcome>> {
+        let git_repo = Repository::open(repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        ensure_no_pending_merge(&git_repo)?;
 
-        if !remote_check.status.success() {
-            return Err(anyhow::anyhow!("Repository has no remote origin configured."));
+        let fetched = git::fetch(&git_repo, REMOTE, &self.config.default_branch, &self.auth())
+            .context("Failed to fetch from remote")?;
+        let Some(upstream) = fetched else {
+            return Ok(None);
+        };
+        let message = format!("Merge remote changes from origin/{}", self.config.default_branch);
+        let outcome = merge_upstream(&git_repo, upstream, &message)?;
+
+        if let MergeOutcome::Conflicted(paths) = &outcome {
+            return Err(anyhow::anyhow!(
+                "Local and remote changes conflict in:\n  {}\nYour files were left as they are. Run 'gitbox resolve <file>' for each to finish the sync.",
+                paths.join("\n  ")
+            ));
         }
+        Ok(Some(outcome))
+    }
 
-        // Check if we're on the default branch, create it if it doesn't exist
-        let branch_check = std::process::Command::new("git")
-            .args(&["rev-parse", "--verify", &self.config.default_branch])
-            .current_dir(repo_path)
-            .output()
-            .context("Failed to check current branch")?;
-
-        if !branch_check.status.success() {
-            // Create the default branch if it doesn't exist
-            let create_branch = std::process::Command::new("git")
-                .args(&["checkout", "-b", &self.config.default_branch])
-                .current_dir(repo_path)
-                .output()
-                .context("Failed to create default branch")?;
-
-            if !create_branch.status.success() {
-                let stderr = String::from_utf8_lossy(&create_branch.stderr);
-                return Err(anyhow::anyhow!("Failed to create branch '{}': {}", self.config.default_branch, stderr));
-            }
-            println!("Created branch '{}'", self.config.default_branch);
-        }
-
-        // Reset any local changes to avoid conflicts with pull
-        let reset_output = std::process::Command::new("git")
-            .args(&["reset", "--hard", "HEAD"])
-            .current_dir(repo_path)
-            .output()
-            .context("Failed to execute git reset")?;
-
-        if !reset_output.status.success() {
-            let stderr = String::from_utf8_lossy(&reset_output.stderr);
-            eprintln!("Warning: failed to reset local changes: {}", stderr);
-        }
-
-        // Pull from remote to get latest changes
-        let pull_output = std::process::Command::new("git")
-            .args(&["pull", "--no-rebase", "--allow-unrelated-histories", "origin", &self.config.default_branch])
-            .current_dir(repo_path)
-            .output()
-            .context("Failed to execute git pull")?;
-
-        if !pull_output.status.success() {
-            let stderr = String::from_utf8_lossy(&pull_output.stderr);
-            // If pull fails due to no upstream, that's an error for pull-only operation
-            if stderr.contains("no upstream") || stderr.contains("couldn't find remote ref") {
-                return Err(anyhow::anyhow!("No upstream branch found. Repository may not be properly initialized."));
-            } else {
-                return Err(anyhow::anyhow!("Failed to pull from remote: {}", stderr));
+    /// Pushes the default branch, merging remote changes and retrying once if the
+    /// remote has commits we do not have yet. Returns the number of commits pushed.
+    fn push_with_retry(&self, git_repo: &Repository) -> Result<usize> {
+        let branch = &self.config.default_branch;
+        let unpushed = self.unpushed_commits(git_repo)?;
+        match git::push(git_repo, REMOTE, branch, &self.auth()) {
+            Ok(()) => {
+                outln!("Pushed changes to the remote");
+                Ok(unpushed)
             }
-        } else {
-            println!("Pulled latest changes from GitHub");
+            Err(e) if e.is_rejected() => {
+                outln!("Push rejected, pulling and merging remote changes...");
+                let repo_path = git_repo.workdir().context("Repository has no working directory")?;
+                self.merge_remote_changes(repo_path)
+                    .context("Failed to pull and merge")?;
+                let unpushed = self.unpushed_commits(git_repo)?;
+                git::push(git_repo, REMOTE, branch, &self.auth())
+                    .context("Failed to push after merge")?;
+                outln!("Successfully merged and pushed changes");
+                Ok(unpushed)
+            }
+            Err(e) => Err(anyhow::Error::new(e).context("Failed to push to remote")),
+        }
+    }
+
+    /// The last fetched commit of the default branch on the remote.
+    fn tracking_id(&self, git_repo: &Repository) -> Option<Oid> {
+        git_repo.refname_to_id(&format!("refs/remotes/{}/{}", REMOTE, self.config.default_branch)).ok()
+    }
+
+    /// Commits on HEAD that the remote did not have when last fetched.
+    fn unpushed_commits(&self, git_repo: &Repository) -> Result<usize> {
+        match git_repo.head().ok().and_then(|head| head.target()) {
+            Some(head) => history::commits_since(git_repo, head, self.tracking_id(git_repo)),
+            None => Ok(0),
         }
+    }
+
+    fn push_branch(&self, git_repo: &Repository) -> Result<(), GitError> {
+        git::push(git_repo, REMOTE, &self.config.default_branch, &self.auth())
+    }
 
+    /// Puts HEAD on the configured default branch, creating it if needed.
+    fn ensure_default_branch(&self, git_repo: &Repository) -> Result<()> {
+        let created = git::ensure_branch(git_repo, &self.config.default_branch)
+            .with_context(|| format!("Failed to create branch '{}'", self.config.default_branch))?;
+        if created {
+            outln!("Created branch '{}'", self.config.default_branch);
+        }
         Ok(())
     }
 
-    fn commit_repo_changes(&self, repo_path: &Path, message: &str) -> Result<()> {
+    fn auth(&self) -> Auth {
+        remote::auth(&self.config)
+    }
+
+    /// Stages every change in the repository (including deletions) and commits it.
+    /// The subject is `-m` if given, else `subject`, else a summary of the changes.
+    /// Returns the subject, or `None` if nothing differed from HEAD.
+    fn commit_repo_changes(&self, repo_path: &Path, subject: Option<&str>) -> Result<Option<String>> {
         let git_repo = Repository::open(repo_path)
             .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
-
-        let signature = Signature::now("gitbox", "gitbox@local")
-            .context("Failed to create git signature")?;
+        ensure_no_pending_merge(&git_repo)?;
 
         let mut index = git_repo.index()
             .context("Failed to get git index")?;
-        
+
+        // Ignored files are skipped by git itself once the rules are in .git/info/exclude
+        ignore::refresh(repo_path)?;
         index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)
             .context("Failed to add files to index")?;
-        index.write()
-            .context("Failed to write git index")?;
+        index.update_all(["*"].iter(), None)
+            .context("Failed to stage removed files")?;
+        // Files committed before they were ignored leave the repository but stay on disk
+        let newly_ignored: Vec<PathBuf> = index.iter()
+            .map(|entry| PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned()))
+            .filter(|path| git_repo.is_path_ignored(path).unwrap_or(false))
+            .collect();
+        for path in &newly_ignored {
+            index.remove_path(path)
+                .with_context(|| format!("Failed to unstage ignored file: {:?}", path))?;
+        }
 
         let tree_id = index.write_tree()
             .context("Failed to write git tree")?;
         let tree = git_repo.find_tree(tree_id)
             .context("Failed to find git tree")?;
 
-        let parent_commit = git_repo.head()
-            .context("Failed to get HEAD")?
-            .peel_to_commit()
-            .context("Failed to peel to commit")?;
+        let parent_commit = git_repo.head().ok()
+            .and_then(|head| head.peel_to_commit().ok());
+        if parent_commit.as_ref().is_some_and(|parent| parent.tree_id() == tree_id) {
+            return Ok(None);
+        }
+
+        // Nothing that looks like a credential is committed unless the user allowed it
+        let parent_tree = parent_commit.as_ref().map(|parent| parent.tree()).transpose()
+            .context("Failed to read commit tree")?;
+        let allowed = GitboxMetadata::load_from_dir(repo_path)?.allowed_secrets;
+        let findings = secrets::scan_changes(&git_repo, parent_tree.as_ref(), &tree, &allowed)?;
+        if !findings.is_empty() {
+            return Err(secrets_found(repo_path, &findings));
+        }
 
-        git_repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            message,
-            &tree,
-            &[&parent_commit],
-        ).context("Failed to create commit")?;
+        index.write()
+            .context("Failed to write git index")?;
+        let parents: Vec<&git2::Commit> = parent_commit.iter().collect();
+        let message = commit::message(&git_repo, parent_tree.as_ref(), &tree, self.commit_message.as_deref().or(subject))?;
+        commit::create(&git_repo, &message, &tree, &parents)?;
 
-        Ok(())
+        Ok(message.lines().next().map(str::to_string))
     }
 
     fn push_repo_changes(&self, repo_path: &Path) -> Result<()> {
-        // First, try to pull from remote to get latest changes
-        let pull_output = std::process::Command::new("git")
-            .args(&["pull", "--no-rebase", "--allow-unrelated-histories", "origin", &self.config.default_branch])
-            .current_dir(repo_path)
-            .output()
-            .context("Failed to execute git pull")?;
-
-        if !pull_output.status.success() {
-            let stderr = String::from_utf8_lossy(&pull_output.stderr);
-            // If pull fails due to no upstream, that's OK, we'll set it up during push
-            if !stderr.contains("no upstream") && !stderr.contains("couldn't find remote ref") {
-                eprintln!("Warning: git pull failed: {}", stderr);
-            }
-        } else {
-            println!("Pulled latest changes from GitHub");
+        // First, merge remote changes; a missing upstream branch is set up by the push
+        let previous_head = head_commit_id(repo_path);
+        if let Some(MergeOutcome::FastForward | MergeOutcome::Merged) = self.merge_remote_changes(repo_path)? {
+            outln!("Pulled latest changes from the remote");
         }
 
-        // Push to remote (with upstream setup if needed)
-        let push_output = std::process::Command::new("git")
-            .args(&["push", "-u", "origin", &self.config.default_branch])
-            .current_dir(repo_path)
-            .output()
-            .context("Failed to execute git push")?;
+        let git_repo = Repository::open(repo_path)
+            .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+        self.push_with_retry(&git_repo)?;
 
-        if !push_output.status.success() {
-            let stderr = String::from_utf8_lossy(&push_output.stderr);
-            
-            // If push was rejected due to non-fast-forward, try to merge and push again
-            if stderr.contains("non-fast-forward") || stderr.contains("rejected") {
-                println!("Push rejected, pulling and merging remote changes...");
-                
-                // Pull with merge strategy, allowing unrelated histories
-                let pull_merge_output = std::process::Command::new("git")
-                    .args(&["pull", "--no-rebase", "--allow-unrelated-histories", "origin", &self.config.default_branch])
-                    .current_dir(repo_path)
-                    .output()
-                    .context("Failed to execute git pull for merge")?;
-                
-                if !pull_merge_output.status.success() {
-                    let pull_stderr = String::from_utf8_lossy(&pull_merge_output.stderr);
-                    return Err(anyhow::anyhow!("Failed to pull and merge: {}", pull_stderr));
-                }
-                
-                // Try push again
-                let retry_push_output = std::process::Command::new("git")
-                    .args(&["push", "origin", &self.config.default_branch])
-                    .current_dir(repo_path)
-                    .output()
-                    .context("Failed to execute retry git push")?;
-                
-                if !retry_push_output.status.success() {
-                    let retry_stderr = String::from_utf8_lossy(&retry_push_output.stderr);
-                    return Err(anyhow::anyhow!("Failed to push after merge: {}", retry_stderr));
-                }
-                
-                println!("Successfully merged and pushed changes");
-            } else {
-                return Err(anyhow::anyhow!("Failed to push to remote: {}", stderr));
+        // Pulled changes rewrite repository files; bring them out to the originals
+        self.repair_links(repo_path, RepairPolicy { baseline: previous_head, ..Default::default() })?;
+
+        Ok(())
+    }
+}
+
+/// The error that stops a commit containing possible secrets.
+fn secrets_found(repo_path: &Path, findings: &[Finding]) -> anyhow::Error {
+    let repo_name = repo_path.file_name().unwrap_or_default().to_string_lossy();
+    let mut report = format!("Refusing to commit: possible secrets found in '{}'\n", repo_name);
+    for finding in findings {
+        report.push_str(&format!("  {}\n", finding));
+    }
+    report.push_str(&format!(
+        "Remove them, store the file encrypted (gitbox sync --encrypt), or, if they are safe to publish, \
+         run 'gitbox allow-secret <fingerprint or path pattern> --repo {}' and sync again.",
+        repo_name
+    ));
+    anyhow::anyhow!(report)
+}
+
+/// Shows a conflict and asks how to resolve it. `None` means leave it for later.
+fn ask_resolution(path: &str, sides: &ConflictSides) -> Result<Option<ResolveChoice>> {
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!("Choose how to resolve {} with --use ours|theirs|edit|tool", path));
+    }
+
+    match (&sides.ours, &sides.theirs) {
+        (None, _) => outln!("{} was deleted locally and changed on the remote.", path),
+        (_, None) => outln!("{} was changed locally and deleted on the remote.", path),
+        _ => {
+            outln!("Conflicting changes in {} (local above, remote below):\n", path);
+            outln!("{}", String::from_utf8_lossy(&sides.merged));
+        }
+    }
+
+    print!("Keep [o]urs (local) / take [t]heirs (remote) / [e]dit / [m]erge tool / [q]uit: ");
+    use std::io::{self, Write};
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    match input.trim().to_lowercase().as_str() {
+        "o" | "ours" => Ok(Some(ResolveChoice::Ours)),
+        "t" | "theirs" => Ok(Some(ResolveChoice::Theirs)),
+        "e" | "edit" => Ok(Some(ResolveChoice::Edit)),
+        "m" | "tool" => Ok(Some(ResolveChoice::Tool)),
+        _ => Ok(None),
+    }
+}
+
+/// Opens the conflicted file, with conflict markers, in `$VISUAL`/`$EDITOR`.
+fn edit_conflict(path: &str, sides: &ConflictSides) -> Result<Vec<u8>> {
+    let file_name = Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let scratch = std::env::temp_dir().join(format!("gitbox-resolve-{}-{}", uuid::Uuid::new_v4(), file_name));
+    fs::write(&scratch, &sides.merged)
+        .with_context(|| format!("Failed to write file: {:?}", scratch))?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let result = std::process::Command::new("sh")
+        .args(["-c", &format!("{} \"$1\"", editor), "sh"])
+        .arg(&scratch)
+        .status()
+        .context("Failed to run editor")
+        .and_then(|status| {
+            if !status.success() {
+                return Err(anyhow::anyhow!("Editor exited with {}; {} left unresolved", status, path));
             }
-        } else {
-            println!("Pushed changes to GitHub");
+            read_resolved(&scratch)
+        });
+
+    let _ = fs::remove_file(&scratch);
+    result
+}
+
+/// Reads an edited merge result, refusing it while conflict markers remain.
+fn read_resolved(path: &Path) -> Result<Vec<u8>> {
+    let content = fs::read(path).with_context(|| format!("Failed to read file: {:?}", path))?;
+    let has_markers = String::from_utf8_lossy(&content).lines()
+        .any(|line| line.starts_with("<<<<<<< ") || line.starts_with(">>>>>>> "));
+    if has_markers {
+        return Err(anyhow::anyhow!("The result still contains conflict markers; left unresolved"));
+    }
+    Ok(content)
+}
+
+/// Stops anything that would move HEAD while a merge waits for `gitbox resolve`.
+fn ensure_no_pending_merge(git_repo: &Repository) -> Result<()> {
+    if let Some(pending) = PendingMerge::load(git_repo)? {
+        return Err(anyhow::anyhow!(
+            "Unresolved conflicts from a previous pull:\n  {}\nRun 'gitbox resolve <file>' for each, or 'gitbox resolve --abort'.",
+            pending.unresolved().join("\n  ")
+        ));
+    }
+    Ok(())
+}
+
+/// One line summary of the machines a profile applies to and the files it selects.
+fn describe_profile(profile: &Profile) -> String {
+    let parts: Vec<String> = [
+        ("hosts", &profile.hosts),
+        ("tags", &profile.tags),
+        ("os", &profile.os),
+        ("files", &profile.files),
+    ]
+    .into_iter()
+    .filter(|(_, values)| !values.is_empty())
+    .map(|(label, values)| format!("{} {}", label, values.join(", ")))
+    .collect();
+    if parts.is_empty() { "no machines or files yet".to_string() } else { parts.join("; ") }
+}
+
+/// Fails if the profiles of the repository at `repo_path` keep the item at
+/// `relative_path` (under `files/`) from this machine.
+fn ensure_selected(repo_path: &Path, relative_path: &Path, repo_name: &str) -> Result<()> {
+    let metadata = GitboxMetadata::load_from_dir(repo_path)?;
+    let selection = Selection::new(&metadata, repo_path)?;
+    if !selection.applies(relative_path) {
+        return Err(anyhow::anyhow!(
+            "'{}' in repository '{}' is only for machines using profile {}; see 'gitbox profile show'",
+            relative_path.display(), repo_name, selection.profiles_of(relative_path).join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Where `sync_from_remote` puts the repository item at `relative_path` (under `files/`):
+/// a full relative path keeps its directory structure below the current directory, a
+/// bare file name lands directly in it.
+fn pull_destination(relative_path: &Path, filename: &str) -> Result<PathBuf> {
+    let current_dir = std::env::current_dir()
+        .context("Failed to get current directory")?;
+    if relative_path == Path::new(filename) {
+        Ok(current_dir.join(relative_path))
+    } else {
+        Ok(current_dir.join(relative_path.file_name().context("Failed to get file name")?))
+    }
+}
+
+/// Whether an existing local item differs from the repository copy it would be replaced with.
+fn differs_from_repository(synced_path: &Path, local_path: &Path, encrypted: bool, mode: SyncMode) -> Result<bool> {
+    if synced_path.is_dir() != local_path.is_dir() {
+        return Ok(true); // Different types (file vs directory)
+    }
+    if synced_path.is_dir() {
+        // Local files the repository does not have are left alone, so they do not count
+        return Ok(list_files(synced_path)?.iter()
+            .any(|file| content_hash(&synced_path.join(file)) != content_hash(&local_path.join(file))));
+    }
+    let remote_content = if encrypted {
+        crypt::read_decrypted(synced_path)?
+    } else if mode == SyncMode::Template {
+        template::render_file(synced_path)?
+    } else {
+        fs::read(synced_path).with_context(|| format!("Failed to read remote file: {:?}", synced_path))?
+    };
+    let local_content = fs::read(local_path)
+        .with_context(|| format!("Failed to read local file: {:?}", local_path))?;
+    Ok(remote_content != local_content)
+}
+
+/// Backs up the originals still hard-linked to the repository files `dirty_files`
+/// (relative to the repository), which a reset is about to overwrite in place.
+fn save_linked_originals(repo_path: &Path, dirty_files: &[String], reason: &str) -> Result<()> {
+    let metadata = GitboxMetadata::load_from_dir(repo_path)?;
+    for info in metadata.files.values() {
+        let synced = info.synced_path.strip_prefix(repo_path).unwrap_or(&info.synced_path);
+        let dirty = dirty_files.iter().any(|file| Path::new(file) == synced);
+        if dirty && same_file(&info.original_path, &info.synced_path) {
+            let saved = Backup::current().save(&info.original_path, reason)?;
+            outln!("Backed up {:?} to {:?}", info.original_path, saved);
         }
+    }
+    Ok(())
+}
 
-        Ok(())
+/// Originals of the files that changed in the repository since the commit `from`
+/// (everything, without one), as far as they are tracked.
+fn changed_originals(git_repo: &Repository, repo_path: &Path, from: Option<Oid>) -> Result<Vec<PathBuf>> {
+    let old = from.map(|id| git_repo.find_commit(id).and_then(|commit| commit.tree()))
+        .transpose()
+        .context("Failed to read the previous commit")?;
+    let Ok(new) = git_repo.head().and_then(|head| head.peel_to_tree()) else {
+        return Ok(Vec::new());
+    };
+    let diff = git_repo.diff_tree_to_tree(old.as_ref(), Some(&new), None)
+        .context("Failed to compare trees")?;
+
+    let metadata = GitboxMetadata::load_from_dir(repo_path)?;
+    let mut originals = Vec::new();
+    for delta in diff.deltas() {
+        let Some(changed) = delta.new_file().path().or(delta.old_file().path()) else {
+            continue;
+        };
+        for info in metadata.files.values() {
+            let synced = info.synced_path.strip_prefix(repo_path).unwrap_or(&info.synced_path);
+            match changed.strip_prefix(synced) {
+                Ok(rest) if rest.as_os_str().is_empty() => originals.push(info.original_path.clone()),
+                Ok(rest) if info.is_directory => originals.push(info.original_path.join(rest)),
+                _ => {}
+            }
+        }
     }
-}
\ No newline at end of file
+    Ok(originals)
+}
+
+fn head_commit_id(repo_path: &Path) -> Option<Oid> {
+    Repository::open(repo_path).ok()?
+        .head().ok()?
+        .target()
+}
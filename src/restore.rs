@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backup::Backup;
+use crate::crypt;
+use crate::output::outln;
+use crate::profile::Selection;
+use crate::status::same_file;
+use crate::template;
+use crate::sync::{GitboxMetadata, SyncMode, content_hash, copy_recursively, create_link, list_files};
+
+/// What restoring one tracked item does to its original location.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkAction {
+    /// Nothing is there yet
+    Create,
+    /// Something with identical content is there; it is replaced by the link
+    Relink,
+    /// Something different is there; it is backed up, then replaced
+    Replace,
+    /// Already linked to the repository copy (or, if encrypted, has its content)
+    Intact,
+    /// Left alone; the message says why
+    Skip(String),
+}
+
+#[derive(Debug)]
+pub struct PlannedLink {
+    pub original: PathBuf,
+    /// The repository copy to link to
+    pub source: PathBuf,
+    /// The repository copy is encrypted and gets decrypted instead of linked
+    pub encrypted: bool,
+    /// A copy-mode entry gets copied instead of linked
+    pub mode: SyncMode,
+    pub action: LinkAction,
+}
+
+/// Decides what to do for every entry in `metadata`. Entries describe the repository at
+/// `repo_path`; its files are read from `checkout`, which differs for a dry run that
+/// cloned into a scratch directory. Entries the repository's profiles keep from this
+/// machine are skipped.
+pub fn plan_links(metadata: &GitboxMetadata, repo_path: &Path, checkout: &Path) -> Result<Vec<PlannedLink>> {
+    let selection = Selection::new(metadata, repo_path)?;
+    Ok(metadata.files.values()
+        .map(|info| {
+            let relative = info.synced_path.strip_prefix(repo_path).unwrap_or(&info.synced_path);
+            let source = checkout.join(relative);
+            let action = if !selection.includes(info) {
+                LinkAction::Skip(selection.reason(info))
+            } else if info.encrypted {
+                decrypt_action(&info.original_path, &source)
+            } else if info.mode == SyncMode::Copy {
+                copy_action(&info.original_path, &source)
+            } else if info.mode == SyncMode::Template {
+                render_action(&info.original_path, &source)
+            } else {
+                link_action(&info.original_path, &source, info.is_directory)
+            };
+            PlannedLink { original: info.original_path.clone(), source, encrypted: info.encrypted, mode: info.mode, action }
+        })
+        .collect())
+}
+
+fn link_action(original: &Path, source: &Path, is_directory: bool) -> LinkAction {
+    if is_directory {
+        // Synced before directories were mirrored: the repository only holds a symlink
+        return LinkAction::Skip("directory contents are not stored in the repository yet; sync the repository on the machine that added it".to_string());
+    }
+    if !source.is_file() {
+        return LinkAction::Skip("not present in the repository".to_string());
+    }
+    if fs::symlink_metadata(original).is_err() {
+        return LinkAction::Create;
+    }
+    if same_file(original, source) {
+        return LinkAction::Intact;
+    }
+    let identical = original.is_file()
+        && matches!((fs::read(original), fs::read(source)), (Ok(a), Ok(b)) if a == b);
+    if identical { LinkAction::Relink } else { LinkAction::Replace }
+}
+
+fn decrypt_action(original: &Path, source: &Path) -> LinkAction {
+    if !source.is_file() {
+        return LinkAction::Skip("not present in the repository".to_string());
+    }
+    let content = match crypt::read_decrypted(source) {
+        Ok(content) => content,
+        Err(e) => return LinkAction::Skip(format!("{:#}", e)),
+    };
+    if fs::symlink_metadata(original).is_err() {
+        return LinkAction::Create;
+    }
+    match fs::read(original) {
+        Ok(existing) if existing == content => LinkAction::Intact,
+        _ => LinkAction::Replace,
+    }
+}
+
+fn render_action(original: &Path, source: &Path) -> LinkAction {
+    if !source.is_file() {
+        return LinkAction::Skip("not present in the repository".to_string());
+    }
+    let content = match template::render_file(source) {
+        Ok(content) => content,
+        Err(e) => return LinkAction::Skip(format!("{:#}", e)),
+    };
+    if fs::symlink_metadata(original).is_err() {
+        return LinkAction::Create;
+    }
+    match fs::read(original) {
+        Ok(existing) if existing == content => LinkAction::Intact,
+        _ => LinkAction::Replace,
+    }
+}
+
+fn copy_action(original: &Path, source: &Path) -> LinkAction {
+    if fs::symlink_metadata(source).is_err() {
+        return LinkAction::Skip("not present in the repository".to_string());
+    }
+    if fs::symlink_metadata(original).is_err() {
+        return LinkAction::Create;
+    }
+    let same = if source.is_dir() {
+        // Extra local files are kept, so only the repository's files have to match
+        original.is_dir() && list_files(source).is_ok_and(|files| files.iter()
+            .all(|file| content_hash(&source.join(file)) == content_hash(&original.join(file))))
+    } else {
+        content_hash(source) == content_hash(original)
+    };
+    if same { LinkAction::Intact } else { LinkAction::Replace }
+}
+
+/// Carries out a planned link, backing up whatever it replaces with different content.
+pub fn apply_link(link: &PlannedLink) -> Result<()> {
+    match link.action {
+        LinkAction::Intact | LinkAction::Skip(_) => return Ok(()),
+        LinkAction::Create => {}
+        LinkAction::Relink | LinkAction::Replace => {
+            if link.action == LinkAction::Replace {
+                let saved = Backup::current().save(&link.original, "replaced by restore")?;
+                outln!("Backed up {:?} to {:?}", link.original, saved);
+            }
+            if link.encrypted && link.original.is_file() {
+                // Decrypting rewrites the file in place
+                return crypt::decrypt_file(&link.source, &link.original);
+            }
+            if link.mode == SyncMode::Template && link.original.is_file() {
+                return write_rendered(link);
+            }
+            if link.mode == SyncMode::Copy && link.source.is_dir() == link.original.is_dir() {
+                // Copying overwrites files in place and keeps extra local ones
+                return copy_recursively(&link.source, &link.original);
+            }
+            let metadata = fs::symlink_metadata(&link.original)
+                .with_context(|| format!("Failed to read metadata: {:?}", link.original))?;
+            if metadata.is_dir() {
+                fs::remove_dir_all(&link.original)
+                    .with_context(|| format!("Failed to remove directory: {:?}", link.original))?;
+            } else {
+                fs::remove_file(&link.original)
+                    .with_context(|| format!("Failed to remove file: {:?}", link.original))?;
+            }
+        }
+    }
+    if link.encrypted {
+        return crypt::decrypt_file(&link.source, &link.original);
+    }
+    if link.mode == SyncMode::Template {
+        return write_rendered(link);
+    }
+    if link.mode == SyncMode::Copy {
+        return copy_recursively(&link.source, &link.original);
+    }
+    create_link(&link.source, &link.original)
+}
+
+fn write_rendered(link: &PlannedLink) -> Result<()> {
+    let rendered = template::render_file(&link.source)?;
+    if let Some(parent) = link.original.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::write(&link.original, rendered)
+        .with_context(|| format!("Failed to write file: {:?}", link.original))
+}
@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use git2::{Delta, ObjectType, Oid, Repository, Tree};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::sync::glob_set;
+
+/// Files starting with this are age ciphertext and never hold plaintext secrets.
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+
+/// Shortest run of token characters considered for the entropy check.
+const MIN_TOKEN_LENGTH: usize = 32;
+
+/// Bits per character above which a token looks randomly generated. Hex strings
+/// (commit ids, checksums) stay below it.
+const ENTROPY_THRESHOLD: f64 = 4.5;
+
+/// Something in a staged file that looks like a credential.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    /// Path inside the repository
+    pub path: String,
+    pub line: usize,
+    pub kind: &'static str,
+    /// The match with most of it masked
+    pub excerpt: String,
+    /// Stable id of the matched text, usable in the allowlist
+    pub fingerprint: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}  {}  {}  ({})", self.path, self.line, self.kind, self.excerpt, self.fingerprint)
+    }
+}
+
+fn rules() -> &'static [(&'static str, Regex)] {
+    static RULES: OnceLock<Vec<(&'static str, Regex)>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        [
+            ("private key", r"-----BEGIN (?:[A-Z0-9]+ )*PRIVATE KEY(?: BLOCK)?-----"),
+            ("AWS access key id", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+            ("AWS secret access key", r#"(?i)aws_?secret_?access_?key\s*[:=]\s*["']?[A-Za-z0-9/+=]{40}\b"#),
+            ("GitHub token", r"\b(?:gh[pousr]_[A-Za-z0-9]{36,255}|github_pat_[A-Za-z0-9_]{22,255})\b"),
+            ("Slack token", r"\bxox[baprs]-[A-Za-z0-9-]{10,}\b"),
+        ]
+        .into_iter()
+        .map(|(kind, pattern)| (kind, Regex::new(pattern).expect("valid secret pattern")))
+        .collect()
+    })
+}
+
+fn token_pattern() -> &'static Regex {
+    static TOKEN: OnceLock<Regex> = OnceLock::new();
+    TOKEN.get_or_init(|| Regex::new(r"[A-Za-z0-9+/=_-]{32,}").expect("valid token pattern"))
+}
+
+/// Scans every file added or changed between `old` (nothing when `None`) and `new`.
+/// Findings whose fingerprint or path matches `allowed` are left out.
+pub fn scan_changes(git_repo: &Repository, old: Option<&Tree>, new: &Tree, allowed: &[String]) -> Result<Vec<Finding>> {
+    let patterns: Vec<String> = allowed.iter()
+        .filter(|entry| !entry.starts_with("secret:"))
+        .cloned()
+        .collect();
+    let allowed_paths = glob_set(&patterns)?;
+
+    let diff = git_repo.diff_tree_to_tree(old, Some(new), None)
+        .context("Failed to compare staged changes")?;
+    let mut findings = Vec::new();
+    for delta in diff.deltas() {
+        if !matches!(delta.status(), Delta::Added | Delta::Modified | Delta::Renamed | Delta::Copied) {
+            continue;
+        }
+        let file = delta.new_file();
+        let Some(path) = file.path() else {
+            continue;
+        };
+        if path == Path::new(".gitbox") || allowed_paths.is_match(path) {
+            continue;
+        }
+        let Ok(blob) = git_repo.find_blob(file.id()) else {
+            continue;
+        };
+        if blob.is_binary() || blob.content().starts_with(AGE_HEADER) {
+            continue;
+        }
+        findings.extend(scan_content(&path.to_string_lossy(), blob.content()));
+    }
+
+    findings.retain(|finding| !allowed.contains(&finding.fingerprint));
+    Ok(findings)
+}
+
+/// Looks for credentials in one file's content.
+fn scan_content(path: &str, content: &[u8]) -> Vec<Finding> {
+    let text = String::from_utf8_lossy(content);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut findings = Vec::new();
+    // The body of a reported private key is not reported line by line
+    let mut skip_until = 0;
+    for (index, line) in lines.iter().enumerate() {
+        if index < skip_until {
+            continue;
+        }
+        let mut found = |kind: &'static str, matched: &str, identity: &str| findings.push(Finding {
+            path: path.to_string(),
+            line: index + 1,
+            kind,
+            excerpt: redact(matched),
+            fingerprint: fingerprint(identity),
+        });
+
+        let mut matched_rule = false;
+        for (kind, rule) in rules() {
+            for matched in rule.find_iter(line) {
+                matched_rule = true;
+                if *kind != "private key" {
+                    found(kind, matched.as_str(), matched.as_str());
+                    continue;
+                }
+                // Key headers are all alike, so the whole block identifies the key
+                let end = lines[index..].iter()
+                    .position(|line| line.contains("-----END"))
+                    .map_or(lines.len(), |offset| index + offset + 1);
+                found(kind, matched.as_str(), &lines[index..end].join("\n"));
+                skip_until = end;
+            }
+        }
+        // Tokens already reported by name are not reported again as random-looking
+        if matched_rule {
+            continue;
+        }
+        for token in token_pattern().find_iter(line) {
+            if looks_random(token.as_str()) {
+                found("high-entropy string", token.as_str(), token.as_str());
+            }
+        }
+    }
+    findings
+}
+
+fn looks_random(token: &str) -> bool {
+    token.len() >= MIN_TOKEN_LENGTH
+        && token.chars().any(|c| c.is_ascii_digit())
+        && token.chars().any(|c| c.is_ascii_alphabetic())
+        && shannon_entropy(token) > ENTROPY_THRESHOLD
+}
+
+fn shannon_entropy(text: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in text.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let length = text.chars().count() as f64;
+    counts.values()
+        .map(|&count| {
+            let p = count as f64 / length;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Keeps the first few characters, enough to recognise the secret but not to use it.
+fn redact(secret: &str) -> String {
+    let shown: String = secret.chars().take(6).collect();
+    format!("{}... ({} chars)", shown, secret.chars().count())
+}
+
+fn fingerprint(secret: &str) -> String {
+    let id = Oid::hash_object(ObjectType::Blob, secret.as_bytes())
+        .map(|oid| oid.to_string())
+        .unwrap_or_default();
+    format!("secret:{}", &id[..id.len().min(12)])
+}
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use git2::{Oid, Repository, Status, StatusOptions, Tree};
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::crypt;
+use crate::ignore;
+use crate::merge::PendingMerge;
+use crate::profile::Selection;
+use crate::repair::copies_differ;
+use crate::sync::{FileInfo, GitboxMetadata, SyncMode};
+use crate::template;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileState {
+    /// Linked correctly, committed, and identical to the remote
+    Clean,
+    /// Has changes that are not committed yet
+    Modified,
+    /// The original and the repository copy are no longer the same file
+    LinkBroken,
+    /// The original or the repository copy does not exist
+    Missing,
+    /// Has committed changes that are not pushed yet
+    Ahead,
+    /// The remote has changes that are not pulled yet
+    Behind,
+    /// Both local and remote changed since they last agreed
+    Diverged,
+    /// A pull stopped on conflicting changes; waiting for `gitbox resolve`
+    Conflicted,
+}
+
+impl fmt::Display for FileState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            FileState::Clean => "clean",
+            FileState::Modified => "modified",
+            FileState::LinkBroken => "link-broken",
+            FileState::Missing => "missing",
+            FileState::Ahead => "ahead",
+            FileState::Behind => "behind",
+            FileState::Diverged => "diverged",
+            FileState::Conflicted => "conflicted",
+        };
+        f.pad(label)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileStatus {
+    pub original_path: PathBuf,
+    pub repo_path: String,
+    pub is_directory: bool,
+    pub state: FileState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepoStatus {
+    pub name: String,
+    pub branch: String,
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    pub files: Vec<FileStatus>,
+}
+
+/// Trees to compare a synced path against: the last common commit with the remote,
+/// the local HEAD and the remote-tracking branch.
+struct Trees<'repo> {
+    base: Option<Tree<'repo>>,
+    head: Option<Tree<'repo>>,
+    upstream: Option<Tree<'repo>>,
+}
+
+impl Trees<'_> {
+    fn entry_ids(&self, path: &Path) -> (Option<Oid>, Option<Oid>, Option<Oid>) {
+        let id = |tree: &Option<Tree>| tree.as_ref()
+            .and_then(|tree| tree.get_path(path).ok())
+            .map(|entry| entry.id());
+        (id(&self.base), id(&self.head), id(&self.upstream))
+    }
+}
+
+/// Computes the state of every synced item recorded in a repository's `.gitbox` that
+/// applies to this machine.
+pub fn repo_status(name: &str, repo_path: &Path, branch: &str) -> Result<RepoStatus> {
+    let git_repo = Repository::open(repo_path)
+        .with_context(|| format!("Failed to open git repository: {:?}", repo_path))?;
+    // Status only reads, so the ignore rules are not written to .git/info/exclude
+    ignore::apply(&git_repo)?;
+    let metadata = GitboxMetadata::load_from_dir(repo_path)?;
+
+    let head = git_repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let upstream_ref = format!("refs/remotes/origin/{}", branch);
+    let upstream = git_repo.find_reference(&upstream_ref).ok()
+        .and_then(|reference| reference.peel_to_commit().ok());
+
+    let (mut ahead, mut behind) = (0, 0);
+    let mut base = None;
+    if let (Some(head), Some(upstream)) = (&head, &upstream) {
+        (ahead, behind) = git_repo.graph_ahead_behind(head.id(), upstream.id())
+            .context("Failed to compare with remote branch")?;
+        base = git_repo.merge_base(head.id(), upstream.id()).ok()
+            .and_then(|id| git_repo.find_commit(id).ok());
+    }
+
+    let pending = PendingMerge::load(&git_repo)?;
+    let conflicts: Vec<&str> = pending.as_ref().map(|pending| pending.unresolved()).unwrap_or_default();
+
+    let trees = Trees {
+        base: base.and_then(|commit| commit.tree().ok()),
+        head: head.as_ref().and_then(|commit| commit.tree().ok()),
+        upstream: upstream.as_ref().and_then(|commit| commit.tree().ok()),
+    };
+
+    let selection = Selection::new(&metadata, repo_path)?;
+    let mut files: Vec<FileStatus> = metadata.files.values()
+        .filter(|info| selection.includes(info))
+        .map(|info| file_status(&git_repo, repo_path, &trees, &conflicts, info))
+        .collect::<Result<_>>()?;
+    files.sort_by(|a, b| a.original_path.cmp(&b.original_path));
+
+    Ok(RepoStatus {
+        name: name.to_string(),
+        branch: branch.to_string(),
+        upstream: upstream.map(|_| format!("origin/{}", branch)),
+        ahead,
+        behind,
+        files,
+    })
+}
+
+fn file_status(git_repo: &Repository, repo_path: &Path, trees: &Trees, conflicts: &[&str], info: &FileInfo) -> Result<FileStatus> {
+    let relative = info.synced_path.strip_prefix(repo_path)
+        .unwrap_or(&info.synced_path)
+        .to_path_buf();
+
+    let (state, detail) = if conflicts.iter().any(|path| Path::new(path) == relative) {
+        (FileState::Conflicted, Some("run 'gitbox resolve' to choose a version".to_string()))
+    } else if fs::symlink_metadata(&info.original_path).is_err() {
+        (FileState::Missing, Some("original location does not exist".to_string()))
+    } else if fs::symlink_metadata(&info.synced_path).is_err() {
+        (FileState::Missing, Some("repository copy does not exist".to_string()))
+    } else if info.encrypted {
+        let original = fs::read(&info.original_path)
+            .with_context(|| format!("Failed to read file: {:?}", info.original_path))?;
+        if original != crypt::read_decrypted(&info.synced_path)? {
+            (FileState::Modified, Some("not encrypted into the repository yet".to_string()))
+        } else {
+            remote_state(git_repo, trees, &relative)?
+        }
+    } else if info.mode == SyncMode::Template {
+        let original = fs::read(&info.original_path)
+            .with_context(|| format!("Failed to read file: {:?}", info.original_path))?;
+        if original != template::render_file(&info.synced_path)? {
+            (FileState::Modified, Some("differs from the rendered template".to_string()))
+        } else {
+            remote_state(git_repo, trees, &relative)?
+        }
+    } else if info.mode == SyncMode::Copy {
+        if copies_differ(git_repo, repo_path, info)? {
+            (FileState::Modified, Some("not copied into the repository yet".to_string()))
+        } else {
+            remote_state(git_repo, trees, &relative)?
+        }
+    } else if !same_file(&info.original_path, &info.synced_path) {
+        (FileState::LinkBroken, Some("original and repository copy are different files".to_string()))
+    } else if git_repo.is_path_ignored(&relative).unwrap_or(false) {
+        (FileState::Clean, Some("ignored, kept out of the repository".to_string()))
+    } else {
+        remote_state(git_repo, trees, &relative)?
+    };
+
+    Ok(FileStatus {
+        original_path: info.original_path.clone(),
+        repo_path: relative.to_string_lossy().to_string(),
+        is_directory: info.is_directory,
+        state,
+        detail,
+    })
+}
+
+/// State of an item whose original and repository copy agree: uncommitted, or how
+/// its committed version compares with the remote.
+fn remote_state(git_repo: &Repository, trees: &Trees, relative: &Path) -> Result<(FileState, Option<String>)> {
+    if has_uncommitted_changes(git_repo, Some(relative))? {
+        return Ok((FileState::Modified, None));
+    }
+    let (base, head, upstream) = trees.entry_ids(relative);
+    let local_changed = base != head;
+    let remote_changed = base != upstream;
+    let state = if head == upstream || trees.upstream.is_none() {
+        FileState::Clean
+    } else if local_changed && remote_changed {
+        FileState::Diverged
+    } else if remote_changed {
+        FileState::Behind
+    } else {
+        FileState::Ahead
+    };
+    Ok((state, None))
+}
+
+/// Whether both paths resolve to the same inode, i.e. the hard link or symlink is intact.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Whether the working tree (optionally limited to `pathspec`) differs from HEAD.
+pub fn has_uncommitted_changes(git_repo: &Repository, pathspec: Option<&Path>) -> Result<bool> {
+    Ok(!uncommitted_paths(git_repo, pathspec)?.is_empty())
+}
+
+/// Paths in the working tree (optionally limited to `pathspec`) that differ from HEAD.
+/// Files are ignored as `git_repo` knows them: after `ignore::refresh`, or on a
+/// handle given the rules by `ignore::apply`.
+pub fn uncommitted_paths(git_repo: &Repository, pathspec: Option<&Path>) -> Result<Vec<String>> {
+    let mut options = StatusOptions::new();
+    if let Some(pathspec) = pathspec {
+        options.pathspec(pathspec);
+    }
+    options.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+
+    let statuses = git_repo.statuses(Some(&mut options))
+        .context("Failed to read git status")?;
+    let paths = statuses.iter()
+        .filter(|entry| entry.status() != Status::CURRENT)
+        .filter_map(|entry| entry.path().map(|path| path.to_string()))
+        .collect();
+    Ok(paths)
+}
@@ -1,29 +1,91 @@
 use anyhow::{Context, Result};
+use clap::ValueEnum;
+use git2::{ObjectType, Oid};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use uuid::Uuid;
 
+use crate::backup::save_before_replacing;
+use crate::output::{errln, outln};
+use crate::paths::PathMapper;
+use crate::profile::Profile;
+
+/// Current `.gitbox` format. Version 2 stores paths relative to named roots
+/// (`$HOME/...`); older files contain absolute paths.
+pub const METADATA_VERSION: u32 = 2;
+
+/// Metadata for synced files. In memory every path is absolute for this machine;
+/// on disk paths are stored in their portable `$ROOT/...` form.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GitboxMetadata {
-    pub files: HashMap<String, FileInfo>,
+    #[serde(default)]
+    pub version: u32,
+    pub files: BTreeMap<String, FileInfo>,
     pub repo_name: Option<String>,
+    /// Secret fingerprints (`secret:...`) or repository path patterns allowed to be committed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_secrets: Vec<String>,
+    /// Named selections of entries for particular machines (`gitbox profile set`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FileInfo {
     pub id: String,
     pub original_path: PathBuf,
     pub synced_path: PathBuf,
     pub is_directory: bool,
+    /// The repository holds an age-encrypted copy instead of a link to the original
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    /// Ignore patterns for a synced directory, relative to it (`sync --exclude`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(default, skip_serializing_if = "SyncMode::is_link")]
+    pub mode: SyncMode,
+}
+
+/// How the original and its repository copy are kept in step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// The repository copy is a hard link (a symlink for directories) to the original
+    #[default]
+    Link,
+    /// Independent copies; changes are copied in whichever direction they were made
+    Copy,
+    /// The repository holds a template (and per-machine overlays) rendered into the original
+    Template,
+}
+
+impl SyncMode {
+    fn is_link(&self) -> bool {
+        *self == SyncMode::Link
+    }
+}
+
+impl std::fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SyncMode::Link => "link",
+            SyncMode::Copy => "copy",
+            SyncMode::Template => "template",
+        })
+    }
 }
 
 impl GitboxMetadata {
     pub fn new() -> Self {
         Self {
-            files: HashMap::new(),
+            version: METADATA_VERSION,
+            files: BTreeMap::new(),
             repo_name: None,
+            allowed_secrets: Vec::new(),
+            profiles: BTreeMap::new(),
         }
     }
 
@@ -32,15 +94,13 @@ impl GitboxMetadata {
         if gitbox_file.exists() {
             let content = fs::read_to_string(&gitbox_file)
                 .with_context(|| format!("Failed to read .gitbox file: {:?}", gitbox_file))?;
-            
-            // Try JSON first, then fall back to TOML for backward compatibility
-            let metadata: GitboxMetadata = match serde_json::from_str(&content) {
-                Ok(metadata) => metadata,
-                Err(_) => {
-                    // Try parsing as TOML (legacy format)
-                    toml::from_str(&content)
-                        .with_context(|| "Failed to parse .gitbox file as JSON or TOML")?
-                }
+            let metadata = Self::parse(&content)?;
+            let mapper = PathMapper::current();
+            let metadata = if metadata.needs_migration() {
+                // Legacy metadata holds absolute paths, possibly from another machine's home
+                metadata.map_paths(|path| mapper.expand(&mapper.contract_foreign(path)))
+            } else {
+                metadata.map_paths(|path| mapper.expand(path))
             };
             Ok(metadata)
         } else {
@@ -48,27 +108,87 @@ impl GitboxMetadata {
         }
     }
 
+    /// Parses `.gitbox` content as stored on disk, without expanding paths.
+    fn parse(content: &str) -> Result<Self> {
+        // Try JSON first, then fall back to TOML for backward compatibility
+        match serde_json::from_str(content) {
+            Ok(metadata) => Ok(metadata),
+            Err(_) => {
+                // Try parsing as TOML (legacy format)
+                toml::from_str(content)
+                    .with_context(|| "Failed to parse .gitbox file as JSON or TOML")
+            }
+        }
+    }
+
     pub fn save_to_dir(&self, dir: &Path) -> Result<()> {
         let gitbox_file = dir.join(".gitbox");
-        let content = serde_json::to_string_pretty(self)
+        let portable = self.to_portable(|path| PathMapper::current().contract(path));
+        let content = serde_json::to_string_pretty(&portable)
             .with_context(|| "Failed to serialize metadata")?;
         fs::write(&gitbox_file, content)
             .with_context(|| format!("Failed to write .gitbox file: {:?}", gitbox_file))?;
         Ok(())
     }
 
-    pub fn add_file(&mut self, original_path: &Path, synced_path: &Path, is_directory: bool) -> String {
-        let id = Uuid::new_v4().to_string();
+    pub fn add_file(&mut self, original_path: &Path, synced_path: &Path, is_directory: bool, encrypted: bool) -> &mut FileInfo {
         let file_info = FileInfo {
-            id: id.clone(),
+            id: Uuid::new_v4().to_string(),
             original_path: original_path.to_path_buf(),
             synced_path: synced_path.to_path_buf(),
             is_directory,
+            encrypted,
+            exclude: Vec::new(),
+            mode: SyncMode::Link,
         };
-        
+
         let key = original_path.to_string_lossy().to_string();
-        self.files.insert(key, file_info);
-        id
+        self.files.insert(key.clone(), file_info);
+        self.files.get_mut(&key).expect("entry was just inserted")
+    }
+
+    /// Returns true if this metadata was written before paths became portable.
+    pub fn needs_migration(&self) -> bool {
+        self.version < METADATA_VERSION
+    }
+
+    /// Builds the on-disk form of this metadata with every path passed through `contract`.
+    pub fn to_portable(&self, contract: impl Fn(&Path) -> PathBuf) -> Self {
+        let files = self.files.values()
+            .map(|info| {
+                let mut info = info.clone();
+                info.original_path = contract(&info.original_path);
+                info.synced_path = contract(&info.synced_path);
+                (info.original_path.to_string_lossy().to_string(), info)
+            })
+            .collect();
+
+        Self {
+            version: METADATA_VERSION,
+            files,
+            repo_name: self.repo_name.clone(),
+            allowed_secrets: self.allowed_secrets.clone(),
+            profiles: self.profiles.clone(),
+        }
+    }
+
+    /// Rewrites every path with `map` and re-keys the entries by their new original path.
+    pub fn map_paths(self, map: impl Fn(&Path) -> PathBuf) -> Self {
+        let files = self.files.into_values()
+            .map(|mut info| {
+                info.original_path = map(&info.original_path);
+                info.synced_path = map(&info.synced_path);
+                (info.original_path.to_string_lossy().to_string(), info)
+            })
+            .collect();
+
+        Self {
+            version: self.version,
+            files,
+            repo_name: self.repo_name,
+            allowed_secrets: self.allowed_secrets,
+            profiles: self.profiles,
+        }
     }
 
     pub fn remove_file(&mut self, original_path: &Path) -> Option<FileInfo> {
@@ -80,10 +200,245 @@ impl GitboxMetadata {
         let key = original_path.to_string_lossy().to_string();
         self.files.get(&key)
     }
+
+    /// Finds an entry tracked for a different original path whose location in the
+    /// repository is the same as, inside of, or a parent of `synced_path`.
+    pub fn find_conflicting_entry(&self, original_path: &Path, synced_path: &Path) -> Option<&FileInfo> {
+        self.files.values().find(|info| {
+            info.original_path != original_path
+                && (info.synced_path.starts_with(synced_path) || synced_path.starts_with(&info.synced_path))
+        })
+    }
+}
+
+/// Three-way merge of `.gitbox` contents as stored on disk. Each entry takes whichever
+/// side changed it since `base`; if both did, `ours` wins.
+pub fn merge_metadata(base: Option<&[u8]>, ours: &[u8], theirs: &[u8]) -> Result<Vec<u8>> {
+    let parse = |content: &[u8]| GitboxMetadata::parse(&String::from_utf8_lossy(content));
+    let base = base.map(parse).transpose()?.unwrap_or_else(GitboxMetadata::new);
+    let ours = parse(ours)?;
+    let theirs = parse(theirs)?;
+
+    let files = merge_entries(&base.files, &ours.files, &theirs.files);
+    let profiles = merge_entries(&base.profiles, &ours.profiles, &theirs.profiles);
+
+    // An allowance made on either machine stays
+    let mut allowed_secrets = ours.allowed_secrets;
+    for entry in theirs.allowed_secrets {
+        if !allowed_secrets.contains(&entry) {
+            allowed_secrets.push(entry);
+        }
+    }
+
+    let merged = GitboxMetadata {
+        version: ours.version.max(theirs.version),
+        files,
+        repo_name: ours.repo_name.or(theirs.repo_name),
+        allowed_secrets,
+        profiles,
+    };
+    let content = serde_json::to_string_pretty(&merged)
+        .with_context(|| "Failed to serialize metadata")?;
+    Ok(content.into_bytes())
+}
+
+/// Takes each entry from whichever side changed it since `base`; `ours` if both did.
+fn merge_entries<T: Clone + PartialEq>(
+    base: &BTreeMap<String, T>,
+    ours: &BTreeMap<String, T>,
+    theirs: &BTreeMap<String, T>,
+) -> BTreeMap<String, T> {
+    let keys: BTreeSet<&String> = base.keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (base_entry, our_entry) = (base.get(key), ours.get(key));
+            let chosen = if our_entry == base_entry { theirs.get(key) } else { our_entry };
+            chosen.map(|entry| (key.clone(), entry.clone()))
+        })
+        .collect()
+}
+
+/// Builds a matcher for gitignore-like patterns: `*` stays within one path component
+/// and `**` spans any number of them.
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Invalid pattern: {}", pattern))?;
+        builder.add(glob);
+    }
+    builder.build().context("Failed to build pattern matcher")
+}
+
+/// Prefix under `files/` for items that live outside the chosen root.
+const OUTSIDE_ROOT_DIR: &str = "_absolute";
+
+/// Computes where `original` is stored under a repository's `files/` directory.
+///
+/// The layout mirrors the path of `original` relative to `root`, so `~/.bashrc` and
+/// `~/work/.bashrc` end up at `.bashrc` and `work/.bashrc`. Items outside `root` keep
+/// their full path below `_absolute/`.
+pub fn repo_relative_path(original: &Path, root: &Path) -> Result<PathBuf> {
+    let relative = match original.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative.to_path_buf(),
+        _ => {
+            let stripped: PathBuf = original.components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect();
+            if stripped.as_os_str().is_empty() {
+                return Err(anyhow::anyhow!("Cannot sync {:?}: path has no file name", original));
+            }
+            Path::new(OUTSIDE_ROOT_DIR).join(stripped)
+        }
+    };
+    Ok(relative)
+}
+
+/// Validates a user supplied path relative to `files/`, rejecting anything that
+/// could escape the repository.
+pub fn validate_repo_relative(relative: &Path) -> Result<()> {
+    let is_plain = relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if relative.as_os_str().is_empty() || !is_plain {
+        return Err(anyhow::anyhow!("Invalid repository path: {:?}", relative));
+    }
+    Ok(())
+}
+
+/// Lists every synced item below `files_dir` as a path relative to it.
+///
+/// Regular directories (including mirrored synced directories) are descended into;
+/// files and symlinks (directories linked by older versions) are reported as single entries.
+pub fn list_synced_entries(files_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    if files_dir.exists() {
+        collect_entries(files_dir, Path::new(""), &mut entries)?;
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn collect_entries(dir: &Path, prefix: &Path, entries: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {:?}", dir))? {
+        let entry = entry.context("Failed to read directory entry")?;
+        let relative = prefix.join(entry.file_name());
+        let file_type = entry.file_type().context("Failed to read file type")?;
+        if file_type.is_dir() {
+            collect_entries(&entry.path(), &relative, entries)?;
+        } else {
+            entries.push(relative);
+        }
+    }
+    Ok(())
+}
+
+/// Copies a file or directory tree, following symlinks so the result is made of
+/// independent regular files. Missing parent directories are created.
+pub fn copy_recursively(source: &Path, destination: &Path) -> Result<()> {
+    if source.is_dir() {
+        fs::create_dir_all(destination)
+            .with_context(|| format!("Failed to create directory: {:?}", destination))?;
+        for entry in fs::read_dir(source)
+            .with_context(|| format!("Failed to read directory: {:?}", source))? {
+            let entry = entry.context("Failed to read directory entry")?;
+            copy_recursively(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+    } else {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+        fs::copy(source, destination)
+            .with_context(|| format!("Failed to copy {:?} to {:?}", source, destination))?;
+    }
+    Ok(())
+}
+
+/// Regular files below `dir` (following symlinks), relative to it. Empty if `dir` does not exist.
+pub fn list_files(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    if dir.is_dir() {
+        collect_files(dir, Path::new(""), &mut files)?;
+    }
+    Ok(files)
+}
+
+fn collect_files(dir: &Path, prefix: &Path, files: &mut BTreeSet<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {:?}", dir))? {
+        let entry = entry.context("Failed to read directory entry")?;
+        let relative = prefix.join(entry.file_name());
+        // Sockets, pipes and dangling links have no content to copy
+        match fs::metadata(entry.path()) {
+            Ok(metadata) if metadata.is_dir() => collect_files(&entry.path(), &relative, files)?,
+            Ok(metadata) if metadata.is_file() => {
+                files.insert(relative);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Git's content hash of a file, `None` if it is not a readable file.
+pub fn content_hash(path: &Path) -> Option<Oid> {
+    if !path.is_file() {
+        return None;
+    }
+    Oid::hash_file(ObjectType::Blob, path).ok()
+}
+
+/// Replaces a hard link or symlink at `path` with an independent copy of its content.
+pub fn detach_link(path: &Path) -> Result<()> {
+    let link_metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to read metadata: {:?}", path))?;
+
+    let file_name = path.file_name().context("Failed to get file name")?;
+    let temp_path = path.with_file_name(format!(".{}.gitbox-detach", file_name.to_string_lossy()));
+    copy_recursively(path, &temp_path)?;
+
+    if link_metadata.is_dir() {
+        fs::remove_dir_all(path)
+            .with_context(|| format!("Failed to remove directory: {:?}", path))?;
+    } else {
+        fs::remove_file(path)
+            .with_context(|| format!("Failed to remove link: {:?}", path))?;
+    }
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to move {:?} into place", temp_path))?;
+    Ok(())
+}
+
+/// Removes a synced item from a repository's `files/` directory along with any
+/// parent directories that become empty.
+pub fn remove_synced_item(synced_path: &Path, files_dir: &Path) -> Result<()> {
+    match fs::symlink_metadata(synced_path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(synced_path)
+            .with_context(|| format!("Failed to remove directory: {:?}", synced_path))?,
+        Ok(_) => fs::remove_file(synced_path)
+            .with_context(|| format!("Failed to remove file: {:?}", synced_path))?,
+        Err(_) => {}
+    }
+
+    let mut parent = synced_path.parent();
+    while let Some(dir) = parent {
+        if dir == files_dir || !dir.starts_with(files_dir) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(())
 }
 
+/// Links `link` to `original`, backing up whatever `link` held before if it differs.
 pub fn create_link(original: &Path, link: &Path) -> Result<()> {
     if link.exists() {
+        save_before_replacing(link, original, &format!("replaced by a link to {:?}", original))?;
         fs::remove_file(link)
             .with_context(|| format!("Failed to remove existing link: {:?}", link))?;
     }
@@ -103,7 +458,7 @@ pub fn create_link(original: &Path, link: &Path) -> Result<()> {
     // For files, try hard link first, fall back to symlink if it fails
     match fs::hard_link(original, link) {
         Ok(()) => {
-            println!("Created hard link: {} -> {}", 
+            outln!("Created hard link: {} -> {}", 
                 original.display(), 
                 link.display()
             );
@@ -111,7 +466,7 @@ pub fn create_link(original: &Path, link: &Path) -> Result<()> {
         }
         Err(e) => {
             // Hard link failed (likely different filesystems), fall back to symlink
-            eprintln!("Hard link failed ({}), falling back to symlink", e);
+            errln!("Hard link failed ({}), falling back to symlink", e);
             create_symlink(original, link)
         }
     }
@@ -133,7 +488,7 @@ fn create_symlink(original: &Path, link: &Path) -> Result<()> {
         }
     }
 
-    println!("Created symlink: {} -> {}", 
+    outln!("Created symlink: {} -> {}", 
         original.display(), 
         link.display()
     );
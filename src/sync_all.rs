@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinSet};
+
+use crate::git;
+use crate::output::{self, outln};
+use crate::repo::{RepoManager, SyncReport};
+
+/// Repositories synced at once when neither `--jobs` nor `sync_jobs` is set
+pub const DEFAULT_JOBS: usize = 4;
+
+/// How syncing one repository ended.
+pub struct RepoSync {
+    pub repo: String,
+    pub result: Result<SyncReport>,
+    /// Files left with unresolved conflicts by the pull
+    pub conflicts: Vec<String>,
+}
+
+impl RepoSync {
+    pub fn failed(&self) -> bool {
+        self.result.is_err()
+    }
+}
+
+/// Syncs every repository in `repos`, at most `jobs` at a time, printing a line as
+/// each one starts and finishes. Results keep the order of `repos`.
+pub async fn run(repo_manager: Arc<RepoManager>, repos: Vec<String>, jobs: usize) -> Result<Vec<RepoSync>> {
+    if jobs > 1 {
+        // Progress lines and questions each need the terminal to themselves
+        git::hide_progress();
+        output::disable_prompts();
+    }
+    let permits = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut tasks = JoinSet::new();
+    for (index, repo) in repos.into_iter().enumerate() {
+        let repo_manager = Arc::clone(&repo_manager);
+        let permits = Arc::clone(&permits);
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await.context("Sync was cancelled")?;
+            // Syncing blocks on git, so it runs off the async workers
+            let name = repo.clone();
+            let sync = task::spawn_blocking(move || sync_one(&repo_manager, name))
+                .await
+                .unwrap_or_else(|e| RepoSync {
+                    result: Err(anyhow::anyhow!("Sync stopped unexpectedly: {}", e)),
+                    repo,
+                    conflicts: Vec::new(),
+                });
+            anyhow::Ok((index, sync))
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        results.push(joined.context("Sync task failed")??);
+    }
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, sync)| sync).collect())
+}
+
+fn sync_one(repo_manager: &RepoManager, repo: String) -> RepoSync {
+    // Every line printed while syncing names the repository it is about
+    output::with_prefix(&repo, || {
+        outln!("Syncing...");
+        let result = repo_manager.sync_repo(&repo);
+        let conflicts = match &result {
+            Ok(_) => Vec::new(),
+            Err(_) => repo_manager.unresolved_conflicts(&repo).unwrap_or_default(),
+        };
+        match &result {
+            Ok(report) => outln!("✓ Synced (pulled {}, pushed {})", report.pulled, report.pushed),
+            Err(_) if !conflicts.is_empty() => {
+                outln!("✗ Conflicts in {}; run 'gitbox resolve <file>' for each", conflicts.join(", "));
+            }
+            Err(e) => outln!("✗ Failed: {:#}", e),
+        }
+        RepoSync { repo: repo.clone(), result, conflicts }
+    })
+}
+
+/// A table with one row per repository: commits pulled and pushed, conflicted files
+/// and the first line of any error.
+pub fn summary(syncs: &[RepoSync]) -> String {
+    let width = syncs.iter().map(|sync| sync.repo.len()).chain(["REPOSITORY".len()]).max().unwrap_or_default();
+    let mut table = format!("{:<width$}  {:>6}  {:>6}  {:>9}  RESULT\n", "REPOSITORY", "PULLED", "PUSHED", "CONFLICTS");
+    for sync in syncs {
+        let (pulled, pushed, outcome) = match &sync.result {
+            Ok(report) => (report.pulled.to_string(), report.pushed.to_string(), "ok".to_string()),
+            Err(_) if !sync.conflicts.is_empty() => ("-".to_string(), "-".to_string(), "conflicts".to_string()),
+            Err(e) => {
+                let message = e.to_string();
+                ("-".to_string(), "-".to_string(), format!("error: {}", message.lines().next().unwrap_or_default()))
+            }
+        };
+        table.push_str(&format!(
+            "{:<width$}  {:>6}  {:>6}  {:>9}  {}\n",
+            sync.repo, pulled, pushed, sync.conflicts.len(), outcome
+        ));
+    }
+    table
+}
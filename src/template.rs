@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::MachineConfig;
+
+static SETTINGS: OnceLock<MachineConfig> = OnceLock::new();
+static MACHINE: OnceLock<Machine> = OnceLock::new();
+
+/// Separates a template's name from the condition of an overlay, as in `.gitconfig##host.laptop`.
+pub const OVERLAY_SEPARATOR: &str = "##";
+
+/// What templates know about this machine.
+#[derive(Debug)]
+pub struct Machine {
+    pub host: String,
+    pub os: String,
+    pub user: String,
+    pub tags: Vec<String>,
+    /// `host`, `os` and `user` plus the `[machine.vars]` from config.toml
+    pub vars: BTreeMap<String, String>,
+}
+
+/// Makes the `[machine]` settings available to templates.
+pub fn install(settings: &MachineConfig) {
+    let _ = SETTINGS.set(settings.clone());
+}
+
+pub fn machine() -> &'static Machine {
+    MACHINE.get_or_init(|| {
+        let settings = SETTINGS.get_or_init(MachineConfig::default);
+        let host = settings.hostname.clone().unwrap_or_else(system_hostname);
+        let os = std::env::consts::OS.to_string();
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_default();
+
+        let mut vars = settings.vars.clone();
+        vars.insert("host".to_string(), host.clone());
+        vars.insert("os".to_string(), os.clone());
+        vars.insert("user".to_string(), user.clone());
+        Machine { host, os, user, tags: settings.tags.clone(), vars }
+    })
+}
+
+fn system_hostname() -> String {
+    let from_file = ["/proc/sys/kernel/hostname", "/etc/hostname"].iter()
+        .find_map(|path| fs::read_to_string(path).ok());
+    let from_command = || std::process::Command::new("hostname").output().ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+    from_file.or_else(from_command)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// The variants of `template` this machine would use, most specific first: overlays
+/// for its hostname, user, tags and OS, then the template itself.
+pub fn variants(template: &Path) -> Vec<PathBuf> {
+    let machine = machine();
+    let mut conditions = vec![format!("host.{}", machine.host), format!("user.{}", machine.user)];
+    conditions.extend(machine.tags.iter().map(|tag| format!("tag.{}", tag)));
+    conditions.push(format!("os.{}", machine.os));
+
+    let name = template.file_name().unwrap_or_default().to_string_lossy();
+    let mut variants: Vec<PathBuf> = conditions.iter()
+        .map(|condition| template.with_file_name(format!("{}{}{}", name, OVERLAY_SEPARATOR, condition)))
+        .collect();
+    variants.push(template.to_path_buf());
+    variants
+}
+
+/// Renders the first variant of `template` that `read` finds. `None` if there is none.
+pub fn render_with(template: &Path, read: impl Fn(&Path) -> Result<Option<Vec<u8>>>) -> Result<Option<Vec<u8>>> {
+    for variant in variants(template) {
+        if let Some(content) = read(&variant)? {
+            let text = String::from_utf8(content)
+                .map_err(|_| anyhow::anyhow!("Template {:?} is not UTF-8 text", variant))?;
+            let rendered = render(&text).with_context(|| format!("Failed to render template {:?}", variant))?;
+            return Ok(Some(rendered.into_bytes()));
+        }
+    }
+    Ok(None)
+}
+
+/// Renders `template` (or the overlay that applies to this machine) from disk.
+pub fn render_file(template: &Path) -> Result<Vec<u8>> {
+    let rendered = render_with(template, |path| {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        fs::read(path).map(Some).with_context(|| format!("Failed to read template: {:?}", path))
+    })?;
+    rendered.with_context(|| format!("Template not found: {:?}", template))
+}
+
+fn tag_pattern() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| Regex::new(r"\{\{\s*(.*?)\s*\}\}").expect("valid template tag pattern"))
+}
+
+fn condition_pattern() -> &'static Regex {
+    static CONDITION: OnceLock<Regex> = OnceLock::new();
+    CONDITION.get_or_init(|| {
+        Regex::new(r#"^([A-Za-z_][A-Za-z0-9_]*)\s*(==|!=)\s*"([^"]*)"$"#).expect("valid template condition pattern")
+    })
+}
+
+/// One open `{{#if}}` block.
+struct Block {
+    /// Whether the enclosing blocks are being output
+    outer: bool,
+    condition: bool,
+    in_else: bool,
+}
+
+impl Block {
+    fn active(&self) -> bool {
+        self.outer && (self.condition != self.in_else)
+    }
+}
+
+/// Renders `{{ name }}` variables and `{{#if name == "value"}} ... {{#else}} ... {{/if}}`
+/// blocks (`!=` works too; `tag == "x"` tests the machine's tags). A block tag alone on
+/// its line takes the whole line with it.
+pub fn render(template: &str) -> Result<String> {
+    let machine = machine();
+    let mut output = String::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut position = 0;
+    let active = |blocks: &[Block]| blocks.last().is_none_or(Block::active);
+
+    for tag in tag_pattern().captures_iter(template) {
+        let whole = tag.get(0).expect("match");
+        let expression = tag.get(1).expect("group").as_str();
+        let is_block = expression.starts_with('#') || expression.starts_with('/') || expression == "else";
+
+        let (start, end) = if is_block { line_span(template, whole.start(), whole.end()) } else { (whole.start(), whole.end()) };
+        if active(&blocks) {
+            output.push_str(&template[position..start]);
+        }
+        position = end;
+
+        if let Some(condition) = expression.strip_prefix("#if ") {
+            let outer = active(&blocks);
+            blocks.push(Block { outer, condition: evaluate(condition.trim(), machine)?, in_else: false });
+        } else if expression == "#else" || expression == "else" {
+            let block = blocks.last_mut().context("{{#else}} without {{#if}}")?;
+            if block.in_else {
+                return Err(anyhow::anyhow!("Two {{{{#else}}}} in one {{{{#if}}}}"));
+            }
+            block.in_else = true;
+        } else if expression == "/if" {
+            blocks.pop().context("{{/if}} without {{#if}}")?;
+        } else if is_block {
+            return Err(anyhow::anyhow!("Unknown template tag: {{{{{}}}}}", expression));
+        } else if active(&blocks) {
+            let value = machine.vars.get(expression)
+                .with_context(|| format!("Unknown template variable '{}'; define it under [machine.vars] in config.toml", expression))?;
+            output.push_str(value);
+        }
+    }
+    if !blocks.is_empty() {
+        return Err(anyhow::anyhow!("{{{{#if}}}} without {{{{/if}}}}"));
+    }
+    output.push_str(&template[position..]);
+    Ok(output)
+}
+
+/// Widens a tag at `start..end` to its whole line, newline included, if nothing else is on it.
+fn line_span(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[end..].find('\n').map_or(text.len(), |i| end + i + 1);
+    let alone = text[line_start..start].trim().is_empty() && text[end..line_end].trim().is_empty();
+    if alone { (line_start, line_end) } else { (start, end) }
+}
+
+fn evaluate(condition: &str, machine: &Machine) -> Result<bool> {
+    let captures = condition_pattern().captures(condition)
+        .with_context(|| format!("Invalid template condition '{}'; expected e.g. host == \"laptop\"", condition))?;
+    let (name, operator, value) = (&captures[1], &captures[2], &captures[3]);
+    let matches = if name == "tag" {
+        machine.tags.iter().any(|tag| tag == value)
+    } else {
+        let actual = machine.vars.get(name)
+            .with_context(|| format!("Unknown template variable '{}'", name))?;
+        actual == value
+    };
+    Ok(if operator == "==" { matches } else { !matches })
+}
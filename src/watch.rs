@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time::{self, Instant};
+
+use crate::output::{self, errln, outln};
+use crate::repo::RepoManager;
+use crate::sync::FileInfo;
+
+pub struct WatchOptions {
+    /// Quiet period after the last change before committing
+    pub debounce: Duration,
+    /// How often committed changes are pushed
+    pub push_interval: Duration,
+    /// How often remote changes are pulled
+    pub pull_interval: Duration,
+}
+
+/// Watches every tracked original path, commits changes once they settle, pushes on
+/// an interval and periodically pulls remote changes. Runs until SIGTERM or Ctrl-C,
+/// committing and pushing anything pending before returning.
+pub async fn run(repo_manager: &RepoManager, options: WatchOptions) -> Result<()> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<Event>| {
+        if let Ok(event) = result {
+            let _ = sender.send(event);
+        }
+    }).context("Failed to create file watcher")?;
+
+    let mut items = repo_manager.tracked_items()?;
+    let mut watched = watch_items(&mut watcher, &items, &HashSet::new())?;
+    outln!("Watching {} synced item(s) in {} location(s). Press Ctrl-C to stop.", items.len(), watched.len());
+
+    let mut sigterm = signal(SignalKind::terminate()).context("Failed to listen for SIGTERM")?;
+    let mut push_timer = time::interval_at(Instant::now() + options.push_interval, options.push_interval);
+    let mut pull_timer = time::interval_at(Instant::now() + options.pull_interval, options.pull_interval);
+
+    // Repositories with changed originals, waiting for the debounce period to pass
+    let mut pending: BTreeSet<String> = BTreeSet::new();
+    let mut settle_at = Instant::now();
+    // Repositories with commits that have not been pushed yet
+    let mut unpushed: BTreeSet<String> = BTreeSet::new();
+
+    loop {
+        tokio::select! {
+            Some(event) = receiver.recv() => {
+                if record_event(&event, &items, &mut pending) {
+                    settle_at = Instant::now() + options.debounce;
+                }
+            }
+            _ = time::sleep_until(settle_at), if !pending.is_empty() => {
+                commit_pending(repo_manager, &mut pending, &mut unpushed);
+            }
+            _ = push_timer.tick() => {
+                push_unpushed(repo_manager, &mut unpushed);
+            }
+            _ = pull_timer.tick() => {
+                let written = pull_all(repo_manager, &items);
+                // Pulled metadata may track new items
+                match repo_manager.tracked_items() {
+                    Ok(updated) => items = updated,
+                    Err(e) => errln!("Warning: failed to reload tracked items: {}", e),
+                }
+                watched = watch_items(&mut watcher, &items, &watched)?;
+                if record_waiting(&mut receiver, &items, &written, &mut pending) {
+                    settle_at = Instant::now() + options.debounce;
+                }
+            }
+            _ = sigterm.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    outln!("Shutting down...");
+    commit_pending(repo_manager, &mut pending, &mut unpushed);
+    push_unpushed(repo_manager, &mut unpushed);
+    Ok(())
+}
+
+/// Adds watches for every tracked item not covered by `already_watched`. Files are
+/// watched through their parent directory so atomic saves (rename over the file)
+/// are still seen; directories are watched recursively.
+fn watch_items(watcher: &mut RecommendedWatcher, items: &[(String, FileInfo)], already_watched: &HashSet<PathBuf>) -> Result<HashSet<PathBuf>> {
+    let mut watched = already_watched.clone();
+    for (_, info) in items {
+        let (target, mode) = if info.is_directory {
+            (info.original_path.clone(), RecursiveMode::Recursive)
+        } else {
+            match info.original_path.parent() {
+                Some(parent) => (parent.to_path_buf(), RecursiveMode::NonRecursive),
+                None => continue,
+            }
+        };
+
+        if watched.contains(&target) {
+            continue;
+        }
+        match watcher.watch(&target, mode) {
+            Ok(()) => {
+                watched.insert(target);
+            }
+            Err(e) => errln!("Warning: cannot watch {:?}: {}", target, e),
+        }
+    }
+    Ok(watched)
+}
+
+/// Records which tracked items an event touches. Returns true if any did.
+fn record_event(event: &Event, items: &[(String, FileInfo)], pending: &mut BTreeSet<String>) -> bool {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+
+    let mut recorded = false;
+    for path in &event.paths {
+        for (repo_name, info) in items {
+            if tracked_by(path, info) {
+                pending.insert(repo_name.clone());
+                recorded = true;
+            }
+        }
+    }
+    recorded
+}
+
+fn tracked_by(path: &Path, info: &FileInfo) -> bool {
+    if info.is_directory {
+        path.starts_with(&info.original_path)
+    } else {
+        path == info.original_path
+    }
+}
+
+fn commit_pending(repo_manager: &RepoManager, pending: &mut BTreeSet<String>, unpushed: &mut BTreeSet<String>) {
+    for repo_name in std::mem::take(pending) {
+        // What a repository prints, including the commit subject, carries its name
+        output::with_prefix(&repo_name, || match repo_manager.commit_pending(&repo_name) {
+            Ok(Some(subject)) => {
+                outln!("{}", subject);
+                unpushed.insert(repo_name.clone());
+            }
+            Ok(None) => {}
+            Err(e) => errln!("Warning: failed to commit changes: {}", e),
+        });
+    }
+}
+
+fn push_unpushed(repo_manager: &RepoManager, unpushed: &mut BTreeSet<String>) {
+    for repo_name in std::mem::take(unpushed) {
+        output::with_prefix(&repo_name, || {
+            if let Err(e) = repo_manager.push_repo(&repo_name) {
+                errln!("Warning: failed to push: {}", e);
+                // Try again on the next interval
+                unpushed.insert(repo_name.clone());
+            }
+        });
+    }
+}
+
+/// Syncs every repository with tracked items. Returns the originals the pulls wrote.
+fn pull_all(repo_manager: &RepoManager, items: &[(String, FileInfo)]) -> HashSet<PathBuf> {
+    let repo_names: BTreeSet<&String> = items.iter().map(|(repo_name, _)| repo_name).collect();
+    let mut written = HashSet::new();
+    for repo_name in repo_names {
+        output::with_prefix(repo_name, || match repo_manager.sync_repo(repo_name) {
+            Ok(report) => written.extend(report.written),
+            Err(e) => errln!("Warning: failed to sync: {}", e),
+        });
+    }
+    written
+}
+
+/// Records the events that arrived during a pull, leaving out the files the pull
+/// wrote itself so they are not committed back. Returns true if any were recorded.
+fn record_waiting(receiver: &mut UnboundedReceiver<Event>, items: &[(String, FileInfo)], written: &HashSet<PathBuf>, pending: &mut BTreeSet<String>) -> bool {
+    let mut recorded = false;
+    while let Ok(mut event) = receiver.try_recv() {
+        event.paths.retain(|path| !written.contains(path));
+        recorded |= record_event(&event, items, pending);
+    }
+    recorded
+}
@@ -0,0 +1,992 @@
+//! End-to-end tests that run the gitbox binary against bare repositories in a
+//! scratch directory. Each simulated machine gets its own temporary `HOME`.
+
+use git2::{Repository, RepositoryInitOptions};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+/// A temporary directory holding the shared bare remotes and every machine's home.
+struct World {
+    dir: TempDir,
+}
+
+impl World {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        fs::create_dir(dir.path().join("remotes")).unwrap();
+        Self { dir }
+    }
+
+    fn remotes(&self) -> PathBuf {
+        self.dir.path().join("remotes")
+    }
+
+    fn remote(&self, repo: &str) -> Repository {
+        Repository::open_bare(self.remotes().join(format!("{}.git", repo))).expect("open bare remote")
+    }
+
+    /// A machine whose config points its remotes at the shared directory.
+    fn machine(&self, name: &str) -> Machine {
+        self.machine_with(name, &format!("local_remote = \"file://{}\"\n", self.remotes().display()))
+    }
+
+    /// A machine with `remote_config` added to the top level of its config.
+    fn machine_with(&self, name: &str, remote_config: &str) -> Machine {
+        let home = self.dir.path().join(name);
+        let gitbox_dir = home.join(".gitbox");
+        fs::create_dir_all(&gitbox_dir).unwrap();
+        let config = format!(
+            "default_branch = \"main\"\nrepos_dir = {:?}\n{}",
+            gitbox_dir.join("repos"),
+            remote_config
+        );
+        fs::write(gitbox_dir.join("config.toml"), config).unwrap();
+        Machine { home }
+    }
+}
+
+struct Machine {
+    home: PathBuf,
+}
+
+impl Machine {
+    /// Runs gitbox from `dir` (relative to this machine's home).
+    fn run_in(&self, dir: &str, args: &[&str]) -> Output {
+        let cwd = self.home.join(dir);
+        fs::create_dir_all(&cwd).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_gitbox"))
+            .args(args)
+            .current_dir(cwd)
+            .env("HOME", &self.home)
+            .env_remove("XDG_CONFIG_HOME")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("SSH_AUTH_SOCK", "")
+            .env_remove("GH_TOKEN")
+            .env_remove("GITHUB_TOKEN")
+            .output()
+            .expect("run gitbox")
+    }
+
+    /// Runs gitbox and fails the test if it does not succeed.
+    fn ok(&self, dir: &str, args: &[&str]) -> String {
+        let output = self.run_in(dir, args);
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        assert!(
+            output.status.success(),
+            "gitbox {:?} failed\nstdout:\n{}\nstderr:\n{}",
+            args,
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        stdout
+    }
+
+    fn append_config(&self, toml: &str) {
+        let path = self.path(".gitbox/config.toml");
+        let config = fs::read_to_string(&path).unwrap();
+        fs::write(path, config + toml).unwrap();
+    }
+
+    fn path(&self, relative: &str) -> PathBuf {
+        self.home.join(relative)
+    }
+
+    fn write(&self, relative: &str, content: &str) {
+        let path = self.path(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn read(&self, relative: &str) -> String {
+        fs::read_to_string(self.path(relative)).unwrap()
+    }
+
+    fn repo_path(&self, repo: &str) -> PathBuf {
+        self.home.join(".gitbox/repos").join(repo)
+    }
+}
+
+/// Which REST API a [`MockForge`] speaks.
+#[derive(Clone, Copy)]
+enum Forge {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+/// Just enough of the GitHub, GitLab or Gitea REST API for gitbox, on localhost.
+/// Every bare repository in `remotes` belongs to the user `octo`, repositories it
+/// creates go there too, and their SSH URL is that path.
+struct MockForge {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockForge {
+    const TOKEN: &'static str = "test-token";
+
+    fn start(forge: Forge, remotes: PathBuf) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                Self::handle(forge, stream, &remotes, &log);
+            }
+        });
+        Self { url, requests }
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn handle(forge: Forge, mut stream: TcpStream, remotes: &Path, log: &Mutex<Vec<String>>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let (mut authorized, mut length) = (false, 0);
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(": ").unwrap();
+            let name = name.to_ascii_lowercase();
+            if name == "content-length" {
+                length = value.parse().unwrap();
+            }
+            authorized |= match forge {
+                Forge::GitHub => name == "authorization" && value == format!("Bearer {}", Self::TOKEN),
+                Forge::GitLab => name == "private-token" && value == Self::TOKEN,
+                Forge::Gitea => name == "authorization" && value == format!("token {}", Self::TOKEN),
+            };
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        let request = request_line.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+        log.lock().unwrap().push(request.clone());
+        let (status, reply) = if authorized {
+            Self::reply(forge, &request, &body, remotes)
+        } else {
+            ("401 Unauthorized", r#"{"message": "Bad credentials"}"#.to_string())
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reply.len(),
+            reply
+        )
+        .unwrap();
+    }
+
+    fn reply(forge: Forge, request: &str, body: &[u8], remotes: &Path) -> (&'static str, String) {
+        let repo_path = |name: &str| remotes.join(format!("{}.git", name));
+        let ssh_url = |name: &str| {
+            let path = repo_path(name);
+            path.exists().then(|| path.to_str().unwrap().to_string())
+        };
+        let has_gitbox = |name: &str| {
+            Repository::open_bare(repo_path(name)).is_ok_and(|repo| remote_file(&repo, ".gitbox").is_some())
+        };
+        let mut names: Vec<String> = fs::read_dir(remotes).unwrap().flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".git").map(str::to_string))
+            .collect();
+        names.sort();
+        let create = |name: &str| {
+            let mut options = RepositoryInitOptions::new();
+            options.bare(true).initial_head("main");
+            Repository::init_opts(repo_path(name), &options).unwrap();
+        };
+        let found = |json: Option<String>| match json {
+            Some(json) => ("200 OK", json),
+            None => ("404 Not Found", r#"{"message": "Not Found"}"#.to_string()),
+        };
+
+        let (method, target) = request.split_once(' ').unwrap();
+        let prefix = match forge {
+            Forge::GitHub => "",
+            Forge::GitLab => "/api/v4",
+            Forge::Gitea => "/api/v1",
+        };
+        let Some(target) = target.strip_prefix(prefix) else {
+            return found(None);
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        // Everything fits on the first page
+        let first_page = query.split('&').any(|param| param == "page=1");
+        let body = || serde_json::from_slice::<serde_json::Value>(body).unwrap();
+
+        match (forge, method, path) {
+            (Forge::GitLab, "GET", "/user") => ("200 OK", r#"{"username": "octo"}"#.to_string()),
+            (_, "GET", "/user") => ("200 OK", r#"{"login": "octo"}"#.to_string()),
+            (Forge::GitHub | Forge::Gitea, "POST", "/user/repos") => {
+                create(body()["name"].as_str().unwrap());
+                ("201 Created", "{}".to_string())
+            }
+            (Forge::GitLab, "POST", "/projects") => {
+                create(body()["path"].as_str().unwrap());
+                ("201 Created", "{}".to_string())
+            }
+            (Forge::GitHub, "GET", "/user/repos") => {
+                let repos: Vec<_> = names.iter().filter(|_| first_page).map(|name| serde_json::json!({ "name": name })).collect();
+                ("200 OK", serde_json::to_string(&repos).unwrap())
+            }
+            (Forge::Gitea, "GET", "/user/repos") => {
+                let mut repos: Vec<_> = names.iter()
+                    .map(|name| serde_json::json!({ "name": name, "owner": { "login": "octo" } }))
+                    .collect();
+                // A repository of someone else's that octo collaborates on
+                repos.push(serde_json::json!({ "name": "notes", "owner": { "login": "someone-else" } }));
+                repos.retain(|_| first_page);
+                ("200 OK", serde_json::to_string(&repos).unwrap())
+            }
+            (Forge::GitLab, "GET", "/projects") => {
+                let projects: Vec<_> = names.iter().enumerate().filter(|_| first_page)
+                    .map(|(index, name)| {
+                        let branch = Repository::open_bare(repo_path(name)).unwrap().find_reference("refs/heads/main").is_ok();
+                        serde_json::json!({ "id": index + 1, "path": name, "default_branch": branch.then_some("main") })
+                    })
+                    .collect();
+                ("200 OK", serde_json::to_string(&projects).unwrap())
+            }
+            (Forge::GitLab, "GET", path) => {
+                let Some(rest) = path.strip_prefix("/projects/") else {
+                    return found(None);
+                };
+                if let Some(name) = rest.strip_prefix("octo%2F") {
+                    return found(ssh_url(name).map(|url| serde_json::json!({ "ssh_url_to_repo": url }).to_string()));
+                }
+                let name = rest.strip_suffix("/repository/files/.gitbox")
+                    .and_then(|id| id.parse::<usize>().ok())
+                    .and_then(|id| names.get(id - 1));
+                found(name.filter(|name| has_gitbox(name)).map(|_| "{}".to_string()))
+            }
+            (_, "GET", path) => {
+                if path.starts_with("/repos/someone-else/") {
+                    return found(Some("{}".to_string()));
+                }
+                let Some(rest) = path.strip_prefix("/repos/octo/") else {
+                    return found(None);
+                };
+                match rest.strip_suffix("/contents/.gitbox") {
+                    Some(name) => found(has_gitbox(name).then(|| "{}".to_string())),
+                    None => found(ssh_url(rest).map(|url| serde_json::json!({ "ssh_url": url }).to_string())),
+                }
+            }
+            _ => found(None),
+        }
+    }
+}
+
+/// Reads `path` from the tip of the remote's main branch.
+fn remote_file(remote: &Repository, path: &str) -> Option<String> {
+    let tree = remote.find_reference("refs/heads/main").ok()?.peel_to_tree().ok()?;
+    let entry = tree.get_path(Path::new(path)).ok()?;
+    let blob = remote.find_blob(entry.id()).ok()?;
+    Some(String::from_utf8_lossy(blob.content()).into_owned())
+}
+
+fn same_inode(a: &Path, b: &Path) -> bool {
+    fs::metadata(a).unwrap().ino() == fs::metadata(b).unwrap().ino()
+}
+
+#[test]
+fn add_repo_creates_and_pushes_to_bare_remote() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+
+    laptop.ok("", &["add-repo", "notes"]);
+
+    let remote = world.remote("notes");
+    assert!(remote_file(&remote, ".gitbox").is_some());
+    assert!(laptop.repo_path("notes").join(".git").exists());
+    assert!(laptop.ok("", &["list-repos"]).contains("notes"));
+}
+
+#[test]
+fn add_repo_uses_the_github_api_with_the_configured_token() {
+    let world = World::new();
+    let github = MockForge::start(Forge::GitHub, world.remotes());
+    let config = format!("github_token = \"{}\"\ngithub_api_url = \"{}\"\n", MockForge::TOKEN, github.url);
+    let laptop = world.machine_with("laptop", &config);
+    let desktop = world.machine_with("desktop", &config);
+
+    laptop.ok("", &["add-repo", "notes"]);
+    assert!(remote_file(&world.remote("notes"), ".gitbox").is_some());
+
+    // The second machine finds the existing repository instead of creating it
+    desktop.ok("", &["add-repo", "notes"]);
+    let creations = github.requests().iter().filter(|request| *request == "POST /user/repos").count();
+    assert_eq!(creations, 1);
+
+    let intruder = world.machine_with("intruder", &format!("github_token = \"wrong\"\ngithub_api_url = \"{}\"\n", github.url));
+    let output = intruder.run_in("", &["add-repo", "notes"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("401"));
+}
+
+/// Creates a repository through `forge`'s API from one machine, finds it from a second
+/// and restores everything the API lists on a third. Returns the restore's output.
+fn round_trip_through(forge: Forge, kind: &str) -> String {
+    let world = World::new();
+    let server = MockForge::start(forge, world.remotes());
+    let remote = format!(
+        "\n[remotes.forge]\nkind = \"{}\"\nurl = \"{}\"\ntoken = \"{}\"\n",
+        kind,
+        server.url,
+        MockForge::TOKEN
+    );
+    let [laptop, desktop, tablet] = ["laptop", "desktop", "tablet"].map(|name| {
+        let machine = world.machine(name);
+        machine.append_config(&remote);
+        machine
+    });
+
+    laptop.ok("", &["add-repo", "notes", "--remote", "forge"]);
+    laptop.write("docs/todo.txt", "buy milk\n");
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+    assert_eq!(remote_file(&world.remote("notes"), "files/docs/todo.txt").as_deref(), Some("buy milk\n"));
+
+    // The second machine finds the existing repository instead of creating it
+    desktop.ok("", &["add-repo", "notes", "--remote", "forge"]);
+    let creations = server.requests().iter().filter(|request| request.starts_with("POST ")).count();
+    assert_eq!(creations, 1);
+
+    // Only repositories with a .gitbox are restored
+    let mut options = RepositoryInitOptions::new();
+    options.bare(true).initial_head("main");
+    Repository::init_opts(world.remotes().join("photos.git"), &options).unwrap();
+    let output = tablet.ok("", &["restore", "--all", "--remote", "forge"]);
+    assert_eq!(tablet.read("docs/todo.txt"), "buy milk\n");
+    assert!(!tablet.repo_path("photos").exists());
+    output
+}
+
+#[test]
+fn gitlab_remote_creates_finds_and_lists_repositories() {
+    round_trip_through(Forge::GitLab, "gitlab");
+}
+
+#[test]
+fn gitea_remote_creates_finds_and_lists_only_owned_repositories() {
+    // The listing also has someone else's 'notes', which must not be restored a second time
+    let output = round_trip_through(Forge::Gitea, "gitea");
+    assert!(!output.contains("Using existing clone"), "{}", output);
+}
+
+#[test]
+fn add_repo_uses_the_named_remote_and_remembers_it() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let nas = world.dir.path().join("nas");
+    laptop.append_config(&format!("\n[remotes.nas]\nkind = \"git\"\nurl = \"{}\"\n", nas.display()));
+
+    laptop.ok("", &["add-repo", "notes", "--remote", "nas"]);
+
+    assert!(Repository::open_bare(nas.join("notes.git")).is_ok());
+    assert!(!world.remotes().join("notes.git").exists());
+    let config = fs::read_to_string(laptop.path(".gitbox/config.toml")).unwrap();
+    assert!(config.contains("notes = \"nas\""));
+}
+
+#[test]
+fn sync_links_file_and_pushes_it() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    laptop.write("docs/todo.txt", "buy milk\n");
+
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+
+    let synced = laptop.repo_path("notes").join("files/docs/todo.txt");
+    assert!(same_inode(&laptop.path("docs/todo.txt"), &synced));
+    assert_eq!(remote_file(&world.remote("notes"), "files/docs/todo.txt").as_deref(), Some("buy milk\n"));
+}
+
+#[test]
+fn second_machine_pulls_and_pushes_changes() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    laptop.write("docs/todo.txt", "buy milk\n");
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+
+    // The desktop joins the existing remote and links the file into place
+    desktop.ok("", &["add-repo", "notes"]);
+    desktop.ok("docs", &["sync-pull", "todo.txt", "--repo", "notes"]);
+    assert_eq!(desktop.read("docs/todo.txt"), "buy milk\n");
+
+    // Edits on the desktop reach the remote and then the laptop's original file
+    desktop.write("docs/todo.txt", "buy milk\nwalk dog\n");
+    desktop.ok("", &["sync-push", "--repo", "notes"]);
+    assert_eq!(
+        remote_file(&world.remote("notes"), "files/docs/todo.txt").as_deref(),
+        Some("buy milk\nwalk dog\n")
+    );
+
+    laptop.ok("", &["repo", "--get", "notes", "sync"]);
+    assert_eq!(laptop.read("docs/todo.txt"), "buy milk\nwalk dog\n");
+}
+
+#[test]
+fn conflicting_edits_stop_the_sync_until_resolved() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    laptop.write("docs/todo.txt", "buy milk\n");
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+    desktop.ok("", &["add-repo", "notes"]);
+    desktop.ok("docs", &["sync-pull", "todo.txt", "--repo", "notes"]);
+
+    laptop.write("docs/todo.txt", "buy oat milk\n");
+    laptop.ok("", &["sync-push", "--repo", "notes"]);
+    desktop.write("docs/todo.txt", "buy soy milk\n");
+
+    let output = desktop.run_in("", &["sync-push", "--repo", "notes"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("conflict"));
+    // Nothing local is touched while the conflict is pending
+    assert_eq!(desktop.read("docs/todo.txt"), "buy soy milk\n");
+    assert!(desktop.ok("", &["resolve"]).contains("files/docs/todo.txt"));
+
+    desktop.ok("", &["resolve", "docs/todo.txt", "--use", "theirs"]);
+    assert_eq!(desktop.read("docs/todo.txt"), "buy oat milk\n");
+    assert!(desktop.ok("", &["resolve"]).contains("No conflicts to resolve"));
+}
+
+#[test]
+fn restore_clones_every_repo_and_links_files_into_place() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    laptop.write("docs/todo.txt", "buy milk\n");
+    laptop.write(".config/app/settings.toml", "theme = \"dark\"\n");
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+    laptop.ok(".config/app", &["sync", "settings.toml", "--repo", "config"]);
+    desktop.write("docs/todo.txt", "old list\n");
+
+    let preview = desktop.ok("", &["restore", "--all", "--dry-run"]);
+    assert!(preview.contains("Would clone 'config'"));
+    assert!(preview.contains("Would clone 'notes'"));
+    assert!(preview.contains("Would replace"));
+    assert!(!desktop.repo_path("notes").exists());
+    assert_eq!(desktop.read("docs/todo.txt"), "old list\n");
+
+    desktop.ok("", &["restore", "--all"]);
+    let synced = desktop.repo_path("notes").join("files/docs/todo.txt");
+    assert!(same_inode(&desktop.path("docs/todo.txt"), &synced));
+    assert_eq!(desktop.read("docs/todo.txt"), "buy milk\n");
+    assert_eq!(desktop.read(".config/app/settings.toml"), "theme = \"dark\"\n");
+
+    // The replaced file is kept under its absolute path in a timestamped backup
+    let backups: Vec<_> = fs::read_dir(desktop.path(".gitbox/backups")).unwrap().flatten()
+        .filter(|entry| entry.path().is_dir())
+        .collect();
+    assert_eq!(backups.len(), 1);
+    let relative: PathBuf = desktop.path("docs/todo.txt").components().skip(1).collect();
+    assert_eq!(fs::read_to_string(backups[0].path().join(relative)).unwrap(), "old list\n");
+
+    // The clone is a normal gitbox repository from here on
+    desktop.write("docs/todo.txt", "buy milk\nwalk dog\n");
+    desktop.ok("", &["sync-push", "--repo", "notes"]);
+    assert_eq!(
+        remote_file(&world.remote("notes"), "files/docs/todo.txt").as_deref(),
+        Some("buy milk\nwalk dog\n")
+    );
+}
+
+#[test]
+fn delete_local_repo_keeps_the_remote() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    laptop.ok("", &["add-repo", "notes"]);
+
+    laptop.ok("", &["delete-local-repo", "--get", "notes", "--force"]);
+
+    assert!(!laptop.repo_path("notes").exists());
+    assert!(!laptop.ok("", &["list-repos"]).contains("notes"));
+    assert!(remote_file(&world.remote("notes"), ".gitbox").is_some());
+}
+
+#[test]
+fn dry_run_prints_the_plan_without_changing_anything() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    laptop.write("docs/todo.txt", "buy milk\n");
+
+    let plan = laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes", "--dry-run"]);
+    assert!(plan.contains("Would create remote repository 'notes'"));
+    assert!(plan.contains("Would commit 'Add todo.txt on "));
+    assert!(!laptop.repo_path("notes").exists());
+    assert!(!world.remotes().join("notes.git").exists());
+    assert!(!laptop.path("docs/.gitbox").exists());
+
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+    laptop.write("docs/todo.txt", "buy milk\nwalk dog\n");
+    let plan = laptop.ok("", &["--dry-run", "sync-all-repos"]);
+    assert!(plan.contains("Would commit changes to: files/docs/todo.txt"));
+    assert!(plan.contains("Would merge remote changes and push"));
+    assert_eq!(remote_file(&world.remote("notes"), "files/docs/todo.txt").as_deref(), Some("buy milk\n"));
+
+    let plan = laptop.ok("", &["delete-local-repo", "--get", "notes", "--dry-run"]);
+    assert!(plan.contains("Would delete repository 'notes'"));
+    assert!(laptop.repo_path("notes").exists());
+
+    for refused in ["repair", "list-remote-files"] {
+        let output = laptop.run_in("", &[refused, "--dry-run"]);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--dry-run is not supported"), "{} accepted --dry-run", refused);
+    }
+}
+
+#[test]
+fn overwritten_local_edits_are_backed_up_and_can_be_restored() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    laptop.write("docs/todo.txt", "buy milk\n");
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+    desktop.ok("", &["add-repo", "notes"]);
+    desktop.ok("docs", &["sync-pull", "todo.txt", "--repo", "notes"]);
+
+    laptop.write("docs/todo.txt", "buy oat milk\n");
+    laptop.ok("", &["sync-push", "--repo", "notes"]);
+    desktop.write("docs/todo.txt", "buy soy milk\n");
+    assert!(!desktop.run_in("", &["sync-push", "--repo", "notes"]).status.success());
+    desktop.ok("", &["resolve", "docs/todo.txt", "--use", "theirs"]);
+    assert_eq!(desktop.read("docs/todo.txt"), "buy oat milk\n");
+
+    let listing = desktop.ok("", &["backups", "list"]);
+    assert!(listing.contains("todo.txt"), "{}", listing);
+    assert!(listing.contains("overwritten by a merge"));
+
+    desktop.ok("", &["backups", "restore", "1"]);
+    assert_eq!(desktop.read("docs/todo.txt"), "buy soy milk\n");
+    // Restoring writes through the link, and keeps what it replaced
+    let synced = desktop.repo_path("notes").join("files/docs/todo.txt");
+    assert!(same_inode(&desktop.path("docs/todo.txt"), &synced));
+    assert!(desktop.ok("", &["backups", "list"]).contains("restoring backup 1"));
+
+    assert!(desktop.ok("", &["backups", "prune", "--older-than", "0"]).contains("Deleted 2 backup(s)"));
+    assert!(desktop.ok("", &["backups", "list"]).contains("No backups"));
+
+    // Discarding local edits with --force still keeps a copy of them, whether the edit
+    // went through the link or an editor replaced the file
+    for (edit, reason) in [("buy rice milk\n", "discarded by sync-pull --force"), ("buy goat milk\n", "replaced by the repository copy")] {
+        if reason.starts_with("replaced") {
+            fs::remove_file(desktop.path("docs/todo.txt")).unwrap();
+        }
+        desktop.write("docs/todo.txt", edit);
+        desktop.ok("docs", &["sync-pull", "todo.txt", "--repo", "notes", "--force"]);
+        assert_eq!(desktop.read("docs/todo.txt"), "buy oat milk\n");
+
+        let listing = desktop.ok("", &["backups", "list"]);
+        let line = listing.lines().find(|line| line.contains(reason));
+        let id = line.and_then(|line| line.split_whitespace().next()).unwrap_or_else(|| panic!("{}", listing));
+        desktop.ok("", &["backups", "restore", id]);
+        assert_eq!(desktop.read("docs/todo.txt"), edit);
+        desktop.ok("docs", &["sync-pull", "todo.txt", "--repo", "notes", "--force"]);
+    }
+}
+
+#[test]
+fn encrypted_files_never_reach_the_remote_in_plaintext() {
+    const IDENTITY: &str = "AGE-SECRET-KEY-1RCF9WP72GFMG7D4PJW4YFSE6LTZ3YKF59093MRE3V90XWX94RM4Q6X6C2E\n";
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    for machine in [&laptop, &desktop] {
+        machine.write(".gitbox/age-key.txt", IDENTITY);
+        machine.append_config(&format!(
+            "\n[encryption]\nidentity = {:?}\npatterns = [\".aws/credentials\"]\n",
+            machine.path(".gitbox/age-key.txt")
+        ));
+    }
+    laptop.write(".aws/credentials", "aws_secret_access_key = hunter2\n");
+
+    laptop.ok(".aws", &["sync", "credentials", "--repo", "secrets"]);
+
+    let stored = remote_file(&world.remote("secrets"), "files/.aws/credentials").unwrap();
+    assert!(stored.starts_with("age-encryption.org/v1"));
+    assert!(!stored.contains("hunter2"));
+    let synced = laptop.repo_path("secrets").join("files/.aws/credentials");
+    assert!(!same_inode(&laptop.path(".aws/credentials"), &synced));
+
+    desktop.ok("", &["add-repo", "secrets"]);
+    desktop.ok(".aws", &["sync-pull", "credentials", "--repo", "secrets"]);
+    assert_eq!(desktop.read(".aws/credentials"), "aws_secret_access_key = hunter2\n");
+
+    // Edits are encrypted on the way out and decrypted on the way in
+    desktop.write(".aws/credentials", "aws_secret_access_key = correct-horse\n");
+    desktop.ok("", &["sync-push", "--repo", "secrets"]);
+    let stored = remote_file(&world.remote("secrets"), "files/.aws/credentials").unwrap();
+    assert!(!stored.contains("correct-horse"));
+
+    laptop.ok("", &["repo", "--get", "secrets", "sync"]);
+    assert_eq!(laptop.read(".aws/credentials"), "aws_secret_access_key = correct-horse\n");
+    assert!(laptop.ok("", &["status"]).contains("clean"));
+}
+
+#[test]
+fn secrets_are_not_committed_until_allowed() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    // Built at runtime so this file does not look like it leaks a token
+    let token = format!("ghp_{}", "a1B2c3D4".repeat(5));
+    laptop.write(".config/hub/hosts", &format!("github.com:\n  oauth_token: {}\n", token));
+
+    let output = laptop.run_in(".config/hub", &["sync", "hosts", "--repo", "tools"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Refusing to commit"), "{}", stderr);
+    assert!(stderr.contains("files/.config/hub/hosts:2  GitHub token"), "{}", stderr);
+    assert!(!stderr.contains(&token));
+    assert!(remote_file(&world.remote("tools"), "files/.config/hub/hosts").is_none());
+
+    let fingerprint = stderr.split_whitespace()
+        .find_map(|word| word.strip_prefix("(secret:"))
+        .map(|rest| format!("secret:{}", rest.trim_end_matches(')')))
+        .unwrap();
+    laptop.ok("", &["allow-secret", &fingerprint, "--repo", "tools"]);
+    laptop.ok("", &["sync-push", "--repo", "tools"]);
+    assert!(remote_file(&world.remote("tools"), "files/.config/hub/hosts").unwrap().contains(&token));
+    assert!(remote_file(&world.remote("tools"), ".gitbox").unwrap().contains(&fingerprint));
+}
+
+#[test]
+fn ignored_files_are_never_committed() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    laptop.ok("", &["add-repo", "apps"]);
+    fs::write(laptop.repo_path("apps").join(".gitboxignore"), "# editor leftovers\n*.lock\n").unwrap();
+    // Rules of the user's own in .git/info/exclude are kept next to gitbox's
+    let exclude = laptop.repo_path("apps").join(".git/info/exclude");
+    fs::write(&exclude, "# mine\n*.bak\n").unwrap();
+    laptop.write(".config/app/settings.json", "{}\n");
+    laptop.write(".config/app/session.lock", "1234\n");
+
+    laptop.ok(".config/app", &["sync", "settings.json", "--repo", "apps"]);
+    laptop.ok(".config/app", &["sync", "session.lock", "--repo", "apps"]);
+
+    let remote = world.remote("apps");
+    assert!(remote_file(&remote, "files/.config/app/settings.json").is_some());
+    assert!(remote_file(&remote, ".gitboxignore").is_some());
+    assert!(remote_file(&remote, "files/.config/app/session.lock").is_none());
+    let rules = fs::read_to_string(&exclude).unwrap();
+    assert!(rules.starts_with("# mine\n*.bak\n"), "{}", rules);
+    assert_eq!(rules.matches("*.lock").count(), 1, "{}", rules);
+    let status = laptop.ok("", &["status", "--repo", "apps"]);
+    assert!(status.contains("ignored, kept out of the repository"), "{}", status);
+    // Status reads the current patterns but leaves .git/info/exclude alone
+    fs::write(laptop.repo_path("apps").join(".gitboxignore"), "*.lock\n*.json\n").unwrap();
+    let status = laptop.ok("", &["status", "--repo", "apps"]);
+    assert_eq!(status.matches("ignored, kept out of the repository").count(), 2, "{}", status);
+    assert_eq!(fs::read_to_string(&exclude).unwrap(), rules);
+    fs::write(laptop.repo_path("apps").join(".gitboxignore"), "*.lock\n").unwrap();
+
+    // --exclude only applies to directories
+    let output = laptop.run_in(".config/app", &["sync", "settings.json", "--repo", "apps", "--exclude", "*.tmp"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn copy_mode_keeps_independent_copies_in_step() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    laptop.write(".profile", "export EDITOR=vi\n");
+    laptop.write(".config/fish/config.fish", "set -x PAGER less\n");
+    laptop.write(".config/fish/functions/ll.fish", "function ll; ls -l; end\n");
+
+    laptop.ok(".config", &["sync", "../.profile", "--repo", "shell", "--mode", "copy"]);
+    laptop.ok(".config", &["sync", "fish", "--repo", "shell", "--mode", "copy"]);
+
+    let synced = laptop.repo_path("shell").join("files/.profile");
+    assert!(!same_inode(&laptop.path(".profile"), &synced));
+    assert!(!fs::symlink_metadata(laptop.repo_path("shell").join("files/.config/fish")).unwrap().file_type().is_symlink());
+    let remote = world.remote("shell");
+    assert_eq!(remote_file(&remote, "files/.config/fish/functions/ll.fish").unwrap(), "function ll; ls -l; end\n");
+
+    // Local edits are copied into the repository when pushing
+    laptop.write(".profile", "export EDITOR=nvim\n");
+    assert!(laptop.ok("", &["status"]).contains("not copied into the repository yet"));
+    laptop.ok("", &["sync-push", "--repo", "shell"]);
+    assert_eq!(remote_file(&world.remote("shell"), "files/.profile").unwrap(), "export EDITOR=nvim\n");
+
+    desktop.ok("", &["restore", "shell"]);
+    assert_eq!(desktop.read(".profile"), "export EDITOR=nvim\n");
+    assert_eq!(desktop.read(".config/fish/config.fish"), "set -x PAGER less\n");
+
+    // Edits and deletions inside a directory travel too, in either direction
+    desktop.write(".config/fish/config.fish", "set -x PAGER bat\n");
+    fs::remove_file(desktop.path(".config/fish/functions/ll.fish")).unwrap();
+    desktop.ok("", &["repo", "--get", "shell", "sync"]);
+    laptop.ok("", &["repo", "--get", "shell", "sync"]);
+    assert_eq!(laptop.read(".config/fish/config.fish"), "set -x PAGER bat\n");
+    assert!(!laptop.path(".config/fish/functions/ll.fish").exists());
+    assert!(laptop.ok("", &["status"]).contains("clean"));
+}
+
+#[test]
+fn synced_directories_are_committed_with_their_contents() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    laptop.write(".config/nvim/init.lua", "vim.o.number = true\n");
+    laptop.write(".config/nvim/lua/plugins.lua", "return {}\n");
+    laptop.write(".config/nvim/.gitboxignore", "*.log\n");
+    laptop.write(".config/nvim/nvim.log", "started\n");
+    laptop.write(".config/nvim/cache/index", "stale\n");
+
+    laptop.ok(".config", &["sync", "nvim", "--repo", "editor", "--exclude", "cache/"]);
+
+    let remote = world.remote("editor");
+    assert_eq!(remote_file(&remote, "files/.config/nvim/lua/plugins.lua").unwrap(), "return {}\n");
+    assert!(remote_file(&remote, "files/.config/nvim/nvim.log").is_none());
+    assert!(remote_file(&remote, "files/.config/nvim/cache/index").is_none());
+    let output = laptop.run_in(".config", &["sync", "nvim", "--repo", "editor", "--mode", "link"]);
+    assert!(!output.status.success());
+
+    desktop.ok("", &["add-repo", "editor"]);
+    desktop.ok(".config", &["sync-pull", "nvim", "--repo", "editor"]);
+    assert_eq!(desktop.read(".config/nvim/lua/plugins.lua"), "return {}\n");
+    assert!(!fs::symlink_metadata(desktop.path(".config/nvim")).unwrap().file_type().is_symlink());
+
+    // New files in the directory are picked up on the next sync
+    desktop.write(".config/nvim/lua/keys.lua", "return {}\n");
+    desktop.ok("", &["repo", "--get", "editor", "sync"]);
+    laptop.ok("", &["repo", "--get", "editor", "sync"]);
+    assert_eq!(laptop.read(".config/nvim/lua/keys.lua"), "return {}\n");
+    assert_eq!(laptop.read(".config/nvim/cache/index"), "stale\n");
+}
+
+#[test]
+fn templates_render_differently_per_machine() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    laptop.append_config("\n[machine]\nhostname = \"laptop\"\ntags = [\"personal\"]\n\n[machine.vars]\nemail = \"me@home.example\"\n");
+    desktop.append_config("\n[machine]\nhostname = \"office\"\ntags = [\"work\"]\n\n[machine.vars]\nemail = \"me@work.example\"\n");
+    laptop.write(".config/git/config", "[user]\n    name = Me\n");
+    laptop.write(".config/git/ignore", "*.swp\n");
+
+    laptop.ok(".config/git", &["sync", "config", "--repo", "dots", "--mode", "template"]);
+    laptop.ok(".config/git", &["sync", "ignore", "--repo", "dots", "--mode", "template"]);
+    let files = laptop.repo_path("dots").join("files/.config/git");
+    fs::write(
+        files.join("config"),
+        "[user]\n    name = Me\n    email = {{ email }}\n{{#if tag == \"work\"}}\n[http]\n    proxy = http://proxy:3128\n{{/if}}\n",
+    ).unwrap();
+    fs::write(files.join("ignore##host.office"), "*.swp\n.idea/\n").unwrap();
+    laptop.ok("", &["repo", "--get", "dots", "sync"]);
+    assert_eq!(laptop.read(".config/git/config"), "[user]\n    name = Me\n    email = me@home.example\n");
+    assert_eq!(laptop.read(".config/git/ignore"), "*.swp\n");
+
+    desktop.ok("", &["restore", "dots"]);
+    assert_eq!(
+        desktop.read(".config/git/config"),
+        "[user]\n    name = Me\n    email = me@work.example\n[http]\n    proxy = http://proxy:3128\n"
+    );
+    assert_eq!(desktop.read(".config/git/ignore"), "*.swp\n.idea/\n");
+
+    // The rendered file is output; an edit to it is kept and reported, not overwritten
+    laptop.write(".config/git/config", "[user]\n    name = Someone else\n");
+    let output = laptop.run_in("", &["repo", "--get", "dots", "sync"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("edited locally"));
+    assert_eq!(laptop.read(".config/git/config"), "[user]\n    name = Someone else\n");
+    assert!(laptop.ok("", &["status"]).contains("differs from the rendered template"));
+}
+
+#[test]
+fn profiles_keep_entries_off_machines_they_do_not_apply_to() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let office = world.machine("office");
+    let home = world.machine("home");
+    laptop.append_config("\n[machine]\nhostname = \"laptop\"\ntags = [\"work\"]\n");
+    office.append_config("\n[machine]\nhostname = \"office\"\n");
+    home.append_config("\n[machine]\nhostname = \"home\"\n");
+    laptop.write(".config/vpn/client.conf", "remote vpn.work.example\n");
+    laptop.write("shell/aliases", "alias ll='ls -l'\n");
+
+    laptop.ok("shell", &["sync", "aliases", "--repo", "dots"]);
+    laptop.ok(".config", &["sync", "vpn", "--repo", "dots"]);
+    laptop.ok("", &["profile", "set", "work", "--repo", "dots", "--tag", "work", "--host", "office", "--files", ".config/vpn"]);
+    laptop.ok("", &["repo", "--get", "dots", "sync"]);
+
+    office.ok("", &["restore", "dots"]);
+    assert_eq!(office.read(".config/vpn/client.conf"), "remote vpn.work.example\n");
+
+    let output = home.ok("", &["restore", "dots"]);
+    assert!(output.contains("only for machines using profile work"), "{}", output);
+    assert_eq!(home.read("shell/aliases"), "alias ll='ls -l'\n");
+    assert!(!home.path(".config/vpn").exists());
+    assert!(!home.ok("", &["status"]).contains("vpn"));
+    assert!(home.ok("", &["profile", "show"]).contains("work (inactive): hosts office; tags work; files .config/vpn"));
+
+    let output = home.run_in(".config", &["sync-pull", "vpn/client.conf", "--repo", "dots"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only for machines using profile work"));
+    assert!(!home.path(".config/vpn").exists());
+}
+
+#[test]
+fn earlier_versions_can_be_listed_compared_and_restored() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    laptop.write("docs/todo.txt", "buy milk\n");
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+    laptop.write("docs/todo.txt", "buy milk\nwalk dog\n");
+    laptop.ok("", &["sync-push", "--repo", "notes"]);
+
+    let log = laptop.ok("docs", &["log", "todo.txt"]);
+    assert_eq!(log.lines().count(), 2, "{}", log);
+    let first = log.lines().last().unwrap().split_whitespace().next().unwrap().to_string();
+
+    laptop.write("docs/todo.txt", "buy milk\nwalk dog\ncall mum\n");
+    let diff = laptop.ok("docs", &["diff", "todo.txt"]);
+    assert!(diff.contains("+call mum") && !diff.contains("+walk dog"), "{}", diff);
+    let diff = laptop.ok("", &["diff", "docs/todo.txt", &first, "--repo", "notes"]);
+    assert!(diff.contains("+walk dog") && diff.contains("+call mum"), "{}", diff);
+
+    // The old content goes through the hard link, and the rollback is pushed
+    laptop.ok("docs", &["restore-version", "todo.txt", &first]);
+    let synced = laptop.repo_path("notes").join("files/docs/todo.txt");
+    assert_eq!(laptop.read("docs/todo.txt"), "buy milk\n");
+    assert!(same_inode(&laptop.path("docs/todo.txt"), &synced));
+    assert_eq!(remote_file(&world.remote("notes"), "files/docs/todo.txt").as_deref(), Some("buy milk\n"));
+    assert!(laptop.ok("", &["backups", "list"]).contains("replaced by restore-version"));
+    assert_eq!(laptop.ok("docs", &["log", "todo.txt"]).lines().count(), 3);
+
+    // A path that climbs out of a synced directory does not name anything in it
+    laptop.write("docs/music/song.txt", "la la\n");
+    laptop.ok("docs", &["sync", "music", "--repo", "notes"]);
+    let output = laptop.run_in("docs", &["restore-version", "music/../../escape.txt", &first]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not synced"));
+    assert!(!laptop.path("escape.txt").exists());
+
+    // Restoring a directory removes the files it gained since
+    let log = laptop.ok("docs", &["log", "music"]);
+    let before = log.lines().next().unwrap().split_whitespace().next().unwrap().to_string();
+    laptop.write("docs/music/extra.txt", "new\n");
+    laptop.ok("", &["repo", "--get", "notes", "sync"]);
+    assert!(remote_file(&world.remote("notes"), "files/docs/music/extra.txt").is_some());
+    laptop.ok("docs", &["restore-version", "music", &before]);
+    assert!(!laptop.path("docs/music/extra.txt").exists());
+    assert_eq!(laptop.read("docs/music/song.txt"), "la la\n");
+    assert!(remote_file(&world.remote("notes"), "files/docs/music/extra.txt").is_none());
+    assert!(laptop.ok("", &["backups", "list"]).contains("removed by restore-version"));
+}
+
+#[test]
+fn commits_name_their_author_files_and_machine() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    laptop.append_config("\n[machine]\nhostname = \"laptop\"\n");
+    laptop.write(".gitconfig", "[user]\n    name = Jane Doe\n    email = jane@example.com\n");
+    laptop.write("docs/todo.txt", "buy milk\n");
+    let tip = || world.remote("notes").find_reference("refs/heads/main").unwrap().peel_to_commit().unwrap().id();
+
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+    let remote = world.remote("notes");
+    let commit = remote.find_commit(tip()).unwrap();
+    assert_eq!(commit.author().name(), Some("Jane Doe"));
+    assert_eq!(commit.author().email(), Some("jane@example.com"));
+    assert_eq!(commit.message(), Some("Add todo.txt on laptop\n\nAdded docs/todo.txt\nModified .gitbox\n\nHost: laptop\n"));
+
+    laptop.write("docs/todo.txt", "buy milk\nwalk dog\n");
+    laptop.ok("", &["sync-push", "--repo", "notes", "-m", "Plan the week"]);
+    let commit = remote.find_commit(tip()).unwrap();
+    assert_eq!(commit.summary(), Some("Plan the week"));
+    assert!(commit.message().unwrap().contains("Modified docs/todo.txt"));
+
+    // [commit] in config.toml overrides git config and can sign with an ssh key
+    let key = laptop.path("signing_key");
+    let keygen = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&key)
+        .status();
+    if !keygen.is_ok_and(|status| status.success()) {
+        eprintln!("ssh-keygen is not available; skipping commit signing");
+        return;
+    }
+    laptop.append_config(&format!(
+        "\n[commit]\nname = \"Jane at Work\"\nsign = \"ssh\"\nsigning_key = {:?}\n",
+        key.display().to_string()
+    ));
+    laptop.write("docs/todo.txt", "buy milk\n");
+    laptop.ok("", &["sync-push", "--repo", "notes"]);
+    let commit = remote.find_commit(tip()).unwrap();
+    assert_eq!(commit.author().name(), Some("Jane at Work"));
+    assert_eq!(commit.summary(), Some("Update todo.txt on laptop"));
+    let signature = commit.header_field_bytes("gpgsig").unwrap();
+    assert!(String::from_utf8_lossy(&signature).contains("BEGIN SSH SIGNATURE"));
+}
+
+#[test]
+fn sync_all_repos_runs_in_parallel_and_reports_every_repo() {
+    let world = World::new();
+    let laptop = world.machine("laptop");
+    let desktop = world.machine("desktop");
+    laptop.write("docs/todo.txt", "buy milk\n");
+    laptop.ok("docs", &["sync", "todo.txt", "--repo", "notes"]);
+    laptop.write("dots/.bashrc", "alias ll='ls -l'\n");
+    laptop.ok("dots", &["sync", ".bashrc", "--repo", "dots"]);
+    for (dir, file, repo) in [("docs", "todo.txt", "notes"), ("dots", ".bashrc", "dots")] {
+        desktop.ok("", &["add-repo", repo]);
+        desktop.ok(dir, &["sync-pull", file, "--repo", repo]);
+    }
+    // Both machines share one history before the edits, so every later pull fast-forwards
+    desktop.ok("", &["sync-all-repos"]);
+    laptop.ok("", &["sync-all-repos"]);
+
+    // One repository has a remote change to pull, the other a conflict
+    laptop.write("dots/.bashrc", "alias ll='ls -la'\n");
+    laptop.write("docs/todo.txt", "buy oat milk\n");
+    laptop.ok("", &["sync-all-repos"]);
+    desktop.write("docs/todo.txt", "buy soy milk\n");
+    let missing = {
+        let local = Repository::open(desktop.repo_path("dots")).unwrap();
+        let remote = world.remote("dots");
+        let mut walk = remote.revwalk().unwrap();
+        walk.push_ref("refs/heads/main").unwrap();
+        walk.hide(local.head().unwrap().target().unwrap()).unwrap();
+        walk.count()
+    };
+    assert!(missing > 0);
+
+    let output = desktop.run_in("", &["sync-all-repos", "--jobs", "2"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "stdout:\n{}", stdout);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 of 2 repositories failed to sync"));
+    assert!(stdout.contains(&format!("[dots] ✓ Synced (pulled {}, pushed 0)", missing)), "stdout:\n{}", stdout);
+    // Repositories sync side by side, so every line they print names its repository
+    let progress: Vec<&str> = stdout.lines().skip(1).take_while(|line| !line.is_empty()).collect();
+    assert!(progress.iter().all(|line| line.starts_with("[dots] ") || line.starts_with("[notes] ")), "stdout:\n{}", stdout);
+    assert!(progress.iter().any(|line| line.starts_with("[dots] Pulled latest changes")));
+    let row = |repo: &str| {
+        stdout.lines()
+            .find(|line| line.starts_with(&format!("{} ", repo)))
+            .map(|line| line.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+    };
+    let missing = missing.to_string();
+    assert_eq!(row("dots"), Some(["dots", &missing, "0", "0", "ok"].map(String::from).to_vec()));
+    assert_eq!(row("notes"), Some(["notes", "-", "-", "1", "conflicts"].map(String::from).to_vec()));
+    assert_eq!(desktop.read("dots/.bashrc"), "alias ll='ls -la'\n");
+
+    desktop.ok("", &["resolve", "docs/todo.txt", "--use", "ours"]);
+    let stdout = desktop.ok("", &["sync-all-repos"]);
+    assert!(stdout.contains("Sync completed"));
+    assert_eq!(remote_file(&world.remote("notes"), "files/docs/todo.txt").as_deref(), Some("buy soy milk\n"));
+}